@@ -6,18 +6,36 @@ pub use self::qp_staking::{
     QpStakingRef,
 };
 
-/// EVM ID (from astar runtime)
+/// EVM ID (from astar runtime), used until the owner sets another with `set_xvm_target`
 const EVM_ID: u8 = 0x0F;
 
 /// The EVM ERC20 delegation contract.
 #[ink::contract(env = xvm_environment::XvmDefaultEnvironment)]
 mod qp_staking {
+    // Quantum portal calls are encoded with `qp_caller::encoding`
+    const QP_SELECTOR: [u8; 4] = encoding::RUN_WITH_VALUE_SELECTOR;
     // ======= IERC20.sol:IERC20 =======
-    // Quantum portal Function signatures:
-    // function runWithValue(uint256 fee, uint64 remoteChain, address remoteContract, address beneficiary, address token, bytes memory method) external;
-    // c154c628: runWithValue(uint256,uint64,address,address,address,bytes)
-    const QP_SELECTOR: [u8; 4] = hex!["c154c628"];
     const TRANSFER_SELECTOR: [u8; 4] = hex!["a9059cbb"];
+    const TRANSFER_FROM_SELECTOR: [u8; 4] = hex!["23b872dd"];
+    const APPROVE_SELECTOR: [u8; 4] = hex!["095ea7b3"];
+    // d505accf : permit(address,address,uint256,uint256,uint8,bytes32,bytes32)
+    const PERMIT_SELECTOR: [u8; 4] = hex!["d505accf"];
+    // Remote methods dispatched on the master chain contract:
+    // 3183e730 : stakeRemote()
+    // 1f7bfaf7 : stakeRemoteLocked(uint64)
+    // 1325af1d : unstakeRemote(uint256,bool)
+    // 4703e91a : claimRemote()
+    const STAKE_REMOTE_METHOD: [u8; 4] = hex!["3183e730"];
+    const STAKE_REMOTE_LOCKED_METHOD: [u8; 4] = hex!["1f7bfaf7"];
+    const UNSTAKE_REMOTE_METHOD: [u8; 4] = hex!["1325af1d"];
+    const CLAIM_REMOTE_METHOD: [u8; 4] = hex!["4703e91a"];
+    /// Fee token of stakes paying the QP fee in native value
+    const NATIVE_FEE_TOKEN: [u8; 20] = [0; 20];
+    /// Protocol fee denominator, fees are expressed in basis points
+    const FEE_DENOMINATOR: u128 = 10_000;
+    /// Version of the storage layout, bumped along with a step in `migrate_from` whenever
+    /// an upgrade changes the layout
+    const STORAGE_VERSION: u16 = 1;
 
     use ethabi::{
         ethereum_types::{
@@ -27,7 +45,22 @@ mod qp_staking {
         Token,
     };
     use hex_literal::hex;
-    use ink::prelude::vec::Vec;
+    use ink::{
+        env::call::{
+            build_call,
+            ExecutionInput,
+            Selector,
+        },
+        prelude::{
+            string::String,
+            vec::Vec,
+        },
+        storage::{
+            Lazy,
+            Mapping,
+        },
+    };
+    use qp_caller::encoding;
 
     #[ink(storage)]
     pub struct QpStaking {
@@ -35,6 +68,321 @@ mod qp_staking {
         master_chain_id: u128,
         master_contract_address: [u8; 20],
         base_token: [u8; 20],
+        refunds: Mapping<AccountId, Balance>,
+        owner: AccountId,
+        /// Minimum fee forwarded to QP, by remote chain
+        min_fees: Mapping<u128, Balance>,
+        /// Share of native stake fees retained by the contract, in basis points
+        protocol_fee_bps: u16,
+        protocol_fees: Balance,
+        /// Remote chain and master chain staking contract, by staking token
+        routes: Mapping<[u8; 20], (u128, [u8; 20])>,
+        min_stake: Balance,
+        max_stake: Option<Balance>,
+        /// Maximum total staked by a beneficiary, unlimited when not set
+        account_cap: Option<Balance>,
+        /// Total staked through this contract, by beneficiary
+        staked: Mapping<[u8; 20], Balance>,
+        /// Master contract by remote chain, for stakes sent to a chosen chain
+        targets: Mapping<u128, [u8; 20]>,
+        /// Open stakes, by beneficiary and position id
+        positions: Mapping<([u8; 20], u32), Position>,
+        /// Number of positions opened, by beneficiary
+        position_count: Mapping<[u8; 20], u32>,
+        /// Layout version of the stored data, behind `STORAGE_VERSION` until `migrate` runs
+        storage_version: u16,
+        /// Accounts allowed to report the outcome of stakes on the master chain
+        relayers: Mapping<AccountId, ()>,
+        /// Position of each stake request, by request id
+        stake_requests: Mapping<u64, ([u8; 20], u32)>,
+        /// Outcome reported for a position, pending while not set
+        position_status: Mapping<([u8; 20], u32), PositionStatus>,
+        /// Id of the next stake request, lazy so the encoding of the root storage is the
+        /// same as in storage version 1
+        next_request_id: Lazy<u64>,
+        /// QP fee store the fees paid in tokens are approved to
+        fee_store: Lazy<[u8; 20]>,
+        /// Idempotency keys of the stakes made, by caller
+        stake_keys: Mapping<(AccountId, [u8; 32]), ()>,
+        /// Share of the retained protocol fee credited to the referrer of a stake, in basis
+        /// points
+        referral_share_bps: Lazy<u16>,
+        /// Referral credit not paid out yet, by referrer
+        referral_earnings: Mapping<AccountId, Balance>,
+        /// Contract allowed to import its positions into this one with `import_positions`
+        migration_source: Lazy<AccountId>,
+        /// Payer and fee of each pending stake request, credited back if the master chain
+        /// rejects the stake
+        stake_receipts: Mapping<u64, StakeReceipt>,
+        /// Tokens credited back to a staker, by account
+        token_refunds: Mapping<AccountId, Vec<([u8; 20], Balance)>>,
+        /// Stake request of each position, by beneficiary and position id
+        position_requests: Mapping<([u8; 20], u32), u64>,
+        /// XVM id of the EVM and selector of the QP function taking the arguments of
+        /// `runWithValue`, `EVM_ID` and `QP_SELECTOR` while not set
+        xvm_target: Lazy<(u8, [u8; 4])>,
+    }
+
+    /// Outcome of a stake on the master chain, as reported by a relayer.
+    #[derive(
+        Debug, Clone, Copy, PartialEq, Eq, Default, scale::Encode, scale::Decode,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub enum PositionStatus {
+        #[default]
+        Pending,
+        Confirmed,
+        /// The master chain did not take the stake, the position cannot be unstaked
+        Rejected,
+    }
+
+    /// A stake made through this contract, withdrawn with `unstake`.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct Position {
+        pub token: [u8; 20],
+        pub remote_chain: u128,
+        pub remote_contract: [u8; 20],
+        pub amount: Balance,
+        /// Timestamp before which the stake can only be withdrawn with an early exit
+        pub unlock_at: Timestamp,
+    }
+
+    /// Options of a `stake`, the default pays a native fee and sets no other option.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct StakeParams {
+        /// Token the fee is paid in, `NATIVE_FEE_TOKEN` for a fee attached as native value
+        pub fee_token: [u8; 20],
+        /// Key of the stake, a later stake of the caller with the same key is rejected
+        pub idempotency_key: Option<[u8; 32]>,
+        /// Account credited with the referral share of the retained protocol fee
+        pub referrer: Option<AccountId>,
+        /// Timestamp after which the stake is not dispatched
+        pub execute_by: Option<Timestamp>,
+    }
+
+    /// What the payer of a stake paid, the part the contract still holds is credited back
+    /// to them if the master chain rejects it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct StakeReceipt {
+        pub payer: AccountId,
+        /// Token the fee was paid in, `NATIVE_FEE_TOKEN` for native value
+        pub fee_token: [u8; 20],
+        /// Fee paid by the payer
+        pub fee: Balance,
+        /// Part of the fee added to the protocol fees
+        pub retained: Balance,
+        /// Account credited with `referral`
+        pub referrer: Option<AccountId>,
+        /// Part of the fee credited to the referrer
+        pub referral: Balance,
+    }
+
+    /// Stake accounting of a beneficiary, moved to a new contract instance by
+    /// `migrate_positions`. Positions and stake requests keep their ids.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct PositionLedger {
+        pub beneficiary: [u8; 20],
+        pub staked: Balance,
+        pub position_count: u32,
+        pub positions: Vec<(u32, Position, PositionStatus)>,
+        /// Stake requests of the pending positions and of the rejected ones whose amount is
+        /// not returned yet, by request id with the position id and the receipt of the stake
+        pub requests: Vec<(u64, u32, Option<StakeReceipt>)>,
+    }
+
+    /// Unclaimed refunds of an account, moved to a new contract instance by
+    /// `migrate_positions`.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct RefundLedger {
+        pub account: AccountId,
+        /// Value claimed with `claim_refund`
+        pub refund: Balance,
+        /// Tokens claimed with `claim_token_refund`
+        pub token_refunds: Vec<([u8; 20], Balance)>,
+    }
+
+    /// Breakdown of the cost of a `stake`, returned by `estimate_total_cost`.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct CostEstimate {
+        /// Amount pulled in the staked token
+        pub amount: Balance,
+        /// Part of the fee forwarded to QP
+        pub qp_fee: Balance,
+        /// Part of the fee retained by the contract
+        pub protocol_fee: Balance,
+        /// Value to attach, both fees
+        pub total: Balance,
+    }
+
+    /// EIP-2612 permit signed by the caller, approving this contract to pull the staked
+    /// amount of a token.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct Permit {
+        /// Unix time in seconds after which the token rejects the permit
+        pub deadline: u64,
+        pub v: u8,
+        pub r: [u8; 32],
+        pub s: [u8; 32],
+    }
+
+    /// Emitted when value is credited back to a staker, who claims it with `claim_refund`.
+    #[ink(event)]
+    pub struct RefundRecorded {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a stake is not dispatched because its `execute_by` deadline passed, the
+    /// attached value is credited back to the staker.
+    #[ink(event)]
+    pub struct StakeExpired {
+        #[ink(topic)]
+        account: AccountId,
+        token: [u8; 20],
+        amount: Balance,
+        execute_by: Timestamp,
+    }
+
+    /// Emitted when tokens are credited back to a staker, who claims them with
+    /// `claim_token_refund`.
+    #[ink(event)]
+    pub struct TokenRefundRecorded {
+        #[ink(topic)]
+        account: AccountId,
+        token: [u8; 20],
+        amount: Balance,
+    }
+
+    /// Emitted when a staker claims a token refund.
+    #[ink(event)]
+    pub struct TokenRefundClaimed {
+        #[ink(topic)]
+        account: AccountId,
+        token: [u8; 20],
+        amount: Balance,
+    }
+
+    /// Emitted when a staker claims a refund.
+    #[ink(event)]
+    pub struct RefundClaimed {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a stake is sent to the master chain, `request_id` identifies it in
+    /// `finalize_stake`.
+    #[ink(event)]
+    pub struct StakeRequested {
+        #[ink(topic)]
+        request_id: u64,
+        beneficiary: [u8; 20],
+        position: u32,
+    }
+
+    /// Emitted when a stake is sent to the master chain, with the referrer credited for it
+    /// if any.
+    #[ink(event)]
+    pub struct Staked {
+        #[ink(topic)]
+        account: AccountId,
+        beneficiary: [u8; 20],
+        token: [u8; 20],
+        amount: Balance,
+        #[ink(topic)]
+        referrer: Option<AccountId>,
+    }
+
+    /// Emitted when the owner pays out the referral credit of a referrer.
+    #[ink(event)]
+    pub struct ReferralPaid {
+        #[ink(topic)]
+        referrer: AccountId,
+        amount: Balance,
+    }
+
+    /// Emitted when a relayer reports a stake as taken by the master chain.
+    #[ink(event)]
+    pub struct StakeConfirmed {
+        #[ink(topic)]
+        request_id: u64,
+        beneficiary: [u8; 20],
+        position: u32,
+    }
+
+    /// Emitted when a relayer reports a stake as rejected by the master chain.
+    #[ink(event)]
+    pub struct StakeRejected {
+        #[ink(topic)]
+        request_id: u64,
+        beneficiary: [u8; 20],
+        position: u32,
+    }
+
+    /// Emitted when a position is withdrawn, `early_exit` is set when it was still locked
+    /// and the master chain applies the early exit penalty.
+    #[ink(event)]
+    pub struct Unstaked {
+        #[ink(topic)]
+        account: AccountId,
+        position: u32,
+        amount: Balance,
+        early_exit: bool,
+    }
+
+    /// Emitted when the owner replaces the code of the contract.
+    #[ink(event)]
+    pub struct Upgraded {
+        code_hash: [u8; 32],
+    }
+
+    /// Emitted when the storage is migrated to a newer layout.
+    #[ink(event)]
+    pub struct Migrated {
+        from: u16,
+        to: u16,
+    }
+
+    /// Emitted when the positions of `count` beneficiaries are moved to the contract `to`.
+    #[ink(event)]
+    pub struct PositionsMigrated {
+        #[ink(topic)]
+        to: AccountId,
+        count: u32,
+    }
+
+    /// Emitted when the positions of `count` beneficiaries are imported from the contract
+    /// `from`.
+    #[ink(event)]
+    pub struct PositionsImported {
+        #[ink(topic)]
+        from: AccountId,
+        count: u32,
+    }
+
+    /// Emitted when the owner withdraws the retained protocol fees.
+    #[ink(event)]
+    pub struct ProtocolFeesWithdrawn {
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
     }
 
     /// The error types.
@@ -45,6 +393,80 @@ mod qp_staking {
         InsufficientBalance,
         /// Remote execution failed
         RemoteExecutionFailed,
+        /// The value attached to the call does not match the native fee
+        InvalidTransferredValue,
+        /// Pulling the tokens from the caller with `transferFrom` failed
+        TransferFromFailed,
+        /// Arithmetic overflow
+        Overflow,
+        /// There is no refund to claim
+        NoRefund,
+        /// Transferring the refund to the caller failed
+        RefundTransferFailed,
+        /// The caller is not the contract owner
+        NotOwner,
+        /// The fee is below the amount returned by `quote_fee`
+        FeeTooLow,
+        /// The protocol fee must be below 100%
+        InvalidProtocolFee,
+        /// There are no protocol fees to withdraw
+        NoProtocolFees,
+        /// Transferring the protocol fees failed
+        ProtocolFeeTransferFailed,
+        /// No remote contract is configured for the token
+        NoRoute,
+        /// The amount is below the minimum stake
+        BelowMinimum,
+        /// The amount is above the maximum stake
+        AboveMaximum,
+        /// The stake would take the beneficiary above the per-account cap
+        CapExceeded,
+        /// No master contract is allowed on the remote chain
+        UnknownTarget,
+        /// The caller has no position with the given id
+        NoPosition,
+        /// The position is locked and no early exit was requested
+        StillLocked,
+        /// Replacing the code of the contract failed
+        UpgradeFailed,
+        /// The storage is already at the current layout version
+        AlreadyMigrated,
+        /// The token rejected the permit
+        PermitFailed,
+        /// The caller is not an allowed relayer
+        NotRelayer,
+        /// There is no stake request with the given id
+        UnknownRequest,
+        /// The outcome of the stake request was already reported
+        AlreadyFinalized,
+        /// The master chain rejected the stake of the position
+        StakeRejected,
+        /// The master chain did not confirm the stake of the position yet
+        StakePending,
+        /// The stake of the request was not rejected
+        NotRejected,
+        /// The amount of the rejected stake was already credited back
+        AlreadyReturned,
+        /// No fee store is set to approve the fee token to
+        NoFeeStore,
+        /// Approving the fee token to the fee store failed
+        FeeApprovalFailed,
+        /// The caller already made a stake with the idempotency key
+        DuplicateRequest,
+        /// The caller cannot refer their own stake
+        SelfReferral,
+        /// The referral share must be at most 100%
+        InvalidReferralShare,
+        /// The referrer has no referral credit to pay out
+        NoReferralEarnings,
+        /// Transferring the referral credit failed
+        ReferralTransferFailed,
+        /// The new contract did not import the positions
+        MigrationFailed,
+        /// The caller is not the contract positions are imported from
+        NotMigrationSource,
+        /// The beneficiary already has positions in this contract
+        PositionsExist,
     }
 
     impl QpStaking {
@@ -61,6 +483,33 @@ mod qp_staking {
                 master_chain_id: master_chain_id.into(),
                 master_contract_address,
                 base_token,
+                refunds: Mapping::default(),
+                owner: Self::env().caller(),
+                min_fees: Mapping::default(),
+                protocol_fee_bps: 0,
+                protocol_fees: 0,
+                routes: Mapping::default(),
+                min_stake: 0,
+                max_stake: None,
+                account_cap: None,
+                staked: Mapping::default(),
+                targets: Mapping::default(),
+                positions: Mapping::default(),
+                position_count: Mapping::default(),
+                storage_version: STORAGE_VERSION,
+                relayers: Mapping::default(),
+                stake_requests: Mapping::default(),
+                position_status: Mapping::default(),
+                next_request_id: Lazy::default(),
+                fee_store: Lazy::default(),
+                stake_keys: Mapping::default(),
+                referral_share_bps: Lazy::default(),
+                referral_earnings: Mapping::default(),
+                migration_source: Lazy::default(),
+                xvm_target: Lazy::default(),
+                stake_receipts: Mapping::default(),
+                token_refunds: Mapping::default(),
+                position_requests: Mapping::default(),
             }
         }
 
@@ -70,67 +519,2712 @@ mod qp_staking {
             self.base_token
         }
 
-        /// Send `transfer_from` call to ERC20 contract.
+        /// Stake `amount` of `token`, pulled from the caller with `transferFrom` like
+        /// `stake_erc20`. A native `fee` is attached to the call, nothing is attached when
+        /// `fee` is paid in the `fee_token` of `params`, which also holds the options below.
+        ///
+        /// The stake is routed to the remote contract configured for `token`. The protocol
+        /// share of a native `fee` is retained by the contract and the rest covers the fee
+        /// passed to QP, which the contract pays on the EVM side. A fee paid in `fee_token`
+        /// is pulled from the caller with `transferFrom` and approved to the QP fee store in
+        /// full. If the QP call fails the message returns `RemoteExecutionFailed` and
+        /// reverts, which undoes the token transfers and returns the attached value.
+        ///
+        /// A stake with an `idempotency_key` the caller already used is rejected, so a retry
+        /// of a stake that went through does not stake twice.
+        ///
+        /// The `referrer` is credited with the referral share of the protocol fee retained
+        /// on a native `fee`, paid out by the owner with `pay_referral_earnings`.
+        ///
+        /// A stake executed after its `execute_by` timestamp, in milliseconds like the block
+        /// timestamp, is not dispatched. It is recorded as expired and the attached value is
+        /// credited to the caller, so a stale stake does not go through at a later time.
+        #[ink(message, payable)]
+        pub fn stake(
+            &mut self,
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+            params: StakeParams,
+        ) -> Result<(), Error> {
+            let StakeParams {
+                fee_token,
+                idempotency_key,
+                referrer,
+                execute_by,
+            } = params;
+            let caller = self.env().caller();
+            if referrer == Some(caller) {
+                return Err(Error::SelfReferral)
+            }
+            if let Some(execute_by) = execute_by {
+                if self.env().block_timestamp() > execute_by {
+                    return self.expire_stake(caller, token, amount, execute_by)
+                }
+            }
+            if let Some(key) = idempotency_key {
+                if self.stake_keys.contains((caller, key)) {
+                    return Err(Error::DuplicateRequest)
+                }
+                self.stake_keys.insert((caller, key), &());
+            }
+            let beneficiary = Self::h160(&caller);
+            let route = self.route(token)?;
+            if fee_token == NATIVE_FEE_TOKEN {
+                self.stake_native(beneficiary, token, route, amount, fee, 0, referrer)
+            } else {
+                self.stake_with_fee_token(
+                    beneficiary,
+                    token,
+                    route,
+                    amount,
+                    fee,
+                    fee_token,
+                    referrer,
+                )
+            }
+        }
+
+        /// Stake `amount` of `token`, locked for `lock_period` seconds. Works like
+        /// `stake`, the lock period is passed to the master chain contract and the position
+        /// can only be withdrawn early with an early exit.
+        #[ink(message, payable)]
+        pub fn stake_locked(
+            &mut self,
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+            lock_period: u64,
+        ) -> Result<(), Error> {
+            let beneficiary = Self::h160(&self.env().caller());
+            let route = self.route(token)?;
+            self.stake_native(beneficiary, token, route, amount, fee, lock_period, None)
+        }
+
+        /// Stake `amount` of `token` to the master contract allowed on
+        /// `remote_chain`, instead of the route of `token`. Works like `stake`.
+        #[ink(message, payable)]
+        pub fn stake_to(
+            &mut self,
+            remote_chain: u128,
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+        ) -> Result<(), Error> {
+            let beneficiary = Self::h160(&self.env().caller());
+            let route = self.target(remote_chain)?;
+            self.stake_native(beneficiary, token, route, amount, fee, 0, None)
+        }
+
+        /// Stake `amount` of `token` on behalf of `beneficiary`, who is credited on the
+        /// master chain instead of the caller. Works like `stake`, the tokens are pulled
+        /// from the caller and a refund goes to the caller.
+        #[ink(message, payable)]
+        pub fn stake_for(
+            &mut self,
+            beneficiary: [u8; 20],
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+        ) -> Result<(), Error> {
+            let route = self.route(token)?;
+            self.stake_native(beneficiary.into(), token, route, amount, fee, 0, None)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn stake_native(
+            &mut self,
+            beneficiary: H160,
+            token: [u8; 20],
+            route: (u128, [u8; 20]),
+            amount: u128,
+            fee: u128,
+            lock_period: u64,
+            referrer: Option<AccountId>,
+        ) -> Result<(), Error> {
+            if self.env().transferred_value() != fee {
+                return Err(Error::InvalidTransferredValue)
+            }
+
+            let staked = self.check_stake_limits(beneficiary, amount)?;
+            self.pull_from_caller(token, amount)?;
+
+            let protocol_fee = self.protocol_fee(fee);
+            self.dispatch_stake(
+                beneficiary,
+                token,
+                route,
+                amount,
+                fee - protocol_fee,
+                lock_period,
+            )?;
+            let referral = self.credit_referral(referrer, protocol_fee)?;
+            let retained = protocol_fee - referral;
+            self.protocol_fees = self
+                .protocol_fees
+                .checked_add(retained)
+                .ok_or(Error::Overflow)?;
+            self.staked.insert(beneficiary.0, &staked);
+            let request_id = self.open_position(
+                beneficiary,
+                token,
+                route,
+                amount,
+                lock_period,
+                referrer,
+            )?;
+            let receipt = StakeReceipt {
+                payer: self.env().caller(),
+                fee_token: NATIVE_FEE_TOKEN,
+                fee,
+                retained,
+                referrer: referrer.filter(|_| referral > 0),
+                referral,
+            };
+            self.stake_receipts.insert(request_id, &receipt);
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn stake_with_fee_token(
+            &mut self,
+            beneficiary: H160,
+            token: [u8; 20],
+            route: (u128, [u8; 20]),
+            amount: u128,
+            fee: u128,
+            fee_token: [u8; 20],
+            referrer: Option<AccountId>,
+        ) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if self.env().transferred_value() != 0 {
+                return Err(Error::InvalidTransferredValue)
+            }
+            let fee_store = self.fee_store.get().ok_or(Error::NoFeeStore)?;
+            let staked = self.check_stake_limits(beneficiary, amount)?;
+            let contract = self.env().account_id();
+
+            let encoded_input = Self::transfer_from_encode(
+                Self::h160(&caller),
+                Self::h160(&contract),
+                fee.into(),
+            );
+            self.env()
+                .extension()
+                .xvm_call(self.evm_id(), Vec::from(fee_token.as_ref()), encoded_input)
+                .map_err(|_| Error::TransferFromFailed)?;
+
+            let encoded_input = Self::approve_encode(fee_store.into(), fee.into());
+            self.env()
+                .extension()
+                .xvm_call(self.evm_id(), Vec::from(fee_token.as_ref()), encoded_input)
+                .map_err(|_| Error::FeeApprovalFailed)?;
+            self.pull_from_caller(token, amount)?;
+
+            self.dispatch_stake(beneficiary, token, route, amount, fee, 0)?;
+            self.staked.insert(beneficiary.0, &staked);
+            let request_id =
+                self.open_position(beneficiary, token, route, amount, 0, referrer)?;
+            let receipt = StakeReceipt {
+                payer: caller,
+                fee_token,
+                fee,
+                retained: 0,
+                referrer: None,
+                referral: 0,
+            };
+            self.stake_receipts.insert(request_id, &receipt);
+            Ok(())
+        }
+
+        /// Returns the fee to pass to `stake` for a stake towards `remote_chain`, the minimum
+        /// fee grossed up by the protocol fee.
+        #[ink(message)]
+        pub fn quote_fee(&self, remote_chain: u128) -> Balance {
+            let min_fee = self.min_fees.get(remote_chain).unwrap_or_default();
+            let forwarded = FEE_DENOMINATOR - u128::from(self.protocol_fee_bps);
+            min_fee
+                .saturating_mul(FEE_DENOMINATOR)
+                .saturating_add(forwarded - 1)
+                / forwarded
+        }
+
+        /// Returns the cost of staking `amount` of `token` with `stake`, with the fee from
+        /// `quote_fee` for the route of `token` split into its QP and protocol parts.
+        #[ink(message)]
+        pub fn estimate_total_cost(
+            &self,
+            token: [u8; 20],
+            amount: Balance,
+        ) -> Result<CostEstimate, Error> {
+            let (remote_chain, _) = self.route(token)?;
+            let fee = self.quote_fee(remote_chain);
+            let protocol_fee = self.protocol_fee(fee);
+            Ok(CostEstimate {
+                amount,
+                qp_fee: fee - protocol_fee,
+                protocol_fee,
+                total: fee,
+            })
+        }
+
+        /// Set the minimum fee forwarded to QP for `remote_chain`.
+        #[ink(message)]
+        pub fn set_min_fee(
+            &mut self,
+            remote_chain: u128,
+            fee: Balance,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.min_fees.insert(remote_chain, &fee);
+            Ok(())
+        }
+
+        /// Set the QP fee store the fees paid in tokens are approved to.
+        #[ink(message)]
+        pub fn set_fee_store(&mut self, fee_store: [u8; 20]) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.fee_store.set(&fee_store);
+            Ok(())
+        }
+
+        /// Set the XVM id of the EVM and the name of the QP function taking the arguments of
+        /// `runWithValue` that calls are dispatched to, for runtimes assigning the EVM
+        /// another id or QP contracts exposing it under another name.
+        #[ink(message)]
+        pub fn set_xvm_target(
+            &mut self,
+            vm_id: u8,
+            qp_function: String,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let qp_selector = encoding::run_with_value_selector(&qp_function);
+            self.xvm_target.set(&(vm_id, qp_selector));
+            Ok(())
+        }
+
+        /// Returns the XVM id of the EVM and the selector of the QP function calls are
+        /// dispatched to.
+        #[ink(message)]
+        pub fn xvm_target(&self) -> (u8, [u8; 4]) {
+            self.xvm_target
+                .get()
+                .unwrap_or((super::EVM_ID, QP_SELECTOR))
+        }
+
+        /// Route stakes of `token` to `remote_contract` on `remote_chain`.
+        #[ink(message)]
+        pub fn set_route(
+            &mut self,
+            token: [u8; 20],
+            remote_chain: u128,
+            remote_contract: [u8; 20],
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.routes.insert(token, &(remote_chain, remote_contract));
+            Ok(())
+        }
+
+        /// Returns the remote chain and contract stakes of `token` are routed to.
+        #[ink(message)]
+        pub fn route_of(&self, token: [u8; 20]) -> Option<(u128, [u8; 20])> {
+            self.route(token).ok()
+        }
+
+        /// Allow stakes to be sent to `master_contract` on `remote_chain`.
+        #[ink(message)]
+        pub fn set_target(
+            &mut self,
+            remote_chain: u128,
+            master_contract: [u8; 20],
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.targets.insert(remote_chain, &master_contract);
+            Ok(())
+        }
+
+        /// Stop allowing stakes to be sent to `remote_chain`.
+        #[ink(message)]
+        pub fn remove_target(&mut self, remote_chain: u128) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.targets.remove(remote_chain);
+            Ok(())
+        }
+
+        /// Returns the master contract stakes to `remote_chain` are sent to.
+        #[ink(message)]
+        pub fn target_of(&self, remote_chain: u128) -> Option<[u8; 20]> {
+            self.targets.get(remote_chain)
+        }
+
+        /// Set the minimum and maximum amount of a single stake, `max_stake` is unlimited
+        /// when not set.
+        #[ink(message)]
+        pub fn set_stake_limits(
+            &mut self,
+            min_stake: Balance,
+            max_stake: Option<Balance>,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.min_stake = min_stake;
+            self.max_stake = max_stake;
+            Ok(())
+        }
+
+        /// Set the maximum total staked by a beneficiary, unlimited when not set.
+        #[ink(message)]
+        pub fn set_account_cap(
+            &mut self,
+            account_cap: Option<Balance>,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.account_cap = account_cap;
+            Ok(())
+        }
+
+        /// Returns the total staked through this contract for `beneficiary`.
+        #[ink(message)]
+        pub fn staked_of(&self, beneficiary: [u8; 20]) -> Balance {
+            self.staked.get(beneficiary).unwrap_or_default()
+        }
+
+        /// Returns the open position `id` of `beneficiary`.
+        #[ink(message)]
+        pub fn position_of(&self, beneficiary: [u8; 20], id: u32) -> Option<Position> {
+            self.positions.get((beneficiary, id))
+        }
+
+        /// Returns the number of positions opened by `beneficiary`, their ids start at 0.
         #[ink(message)]
-        pub fn stake(&mut self, amount: u128, fee: u128) -> Result<(), Error> {
+        pub fn position_count_of(&self, beneficiary: [u8; 20]) -> u32 {
+            self.position_count.get(beneficiary).unwrap_or_default()
+        }
+
+        /// Withdraw the caller's position `id` on the master chain contract, through
+        /// `unstakeRemote(amount, earlyExit)`.
+        ///
+        /// A locked position is only withdrawn with `early_exit`, which is passed on so
+        /// the master chain contract applies its early exit penalty. Only `fee` is passed
+        /// to the QP contract, attached to the call as native value.
+        ///
+        /// Only a position whose stake the master chain confirmed can be unstaked.
+        #[ink(message, payable)]
+        pub fn unstake(
+            &mut self,
+            id: u32,
+            early_exit: bool,
+            fee: u128,
+        ) -> Result<(), Error> {
+            if self.env().transferred_value() != fee {
+                return Err(Error::InvalidTransferredValue)
+            }
             let caller = self.env().caller();
+            let beneficiary = Self::h160(&caller);
+            let position = self
+                .positions
+                .get((beneficiary.0, id))
+                .ok_or(Error::NoPosition)?;
+            match self.position_status_of(beneficiary.0, id) {
+                PositionStatus::Confirmed => (),
+                PositionStatus::Pending => return Err(Error::StakePending),
+                PositionStatus::Rejected => return Err(Error::StakeRejected),
+            }
+            let locked = self.env().block_timestamp() < position.unlock_at;
+            if locked && !early_exit {
+                return Err(Error::StillLocked)
+            }
 
-            // transfer the amount to the QP contract
-            let encoded_input =
-                Self::transfer_encode(self.qp_contract_address.into(), amount.into());
+            let mut method = UNSTAKE_REMOTE_METHOD.to_vec();
+            method.extend(ethabi::encode(&[
+                Token::Uint(position.amount.into()),
+                Token::Bool(locked),
+            ]));
+            self.dispatch_run(
+                beneficiary,
+                position.remote_chain,
+                position.remote_contract,
+                position.token,
+                fee,
+                &method,
+            )?;
+
+            self.positions.remove((beneficiary.0, id));
+            self.position_status.remove((beneficiary.0, id));
+            let staked = self
+                .staked_of(beneficiary.0)
+                .saturating_sub(position.amount);
+            self.staked.insert(beneficiary.0, &staked);
+            self.env().emit_event(Unstaked {
+                account: caller,
+                position: id,
+                amount: position.amount,
+                early_exit: locked,
+            });
+            Ok(())
+        }
+
+        /// Allow or disallow `account` to report stake outcomes with `finalize_stake`. The
+        /// account mapped to the QP contract is allowed for callbacks made through XVM.
+        #[ink(message)]
+        pub fn set_relayer(
+            &mut self,
+            account: AccountId,
+            allowed: bool,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if allowed {
+                self.relayers.insert(account, &());
+            } else {
+                self.relayers.remove(account);
+            }
+            Ok(())
+        }
+
+        /// Returns whether `account` may report stake outcomes.
+        #[ink(message)]
+        pub fn is_relayer(&self, account: AccountId) -> bool {
+            self.relayers.contains(account)
+        }
+
+        /// Report the outcome of stake request `request_id` on the master chain. A rejected
+        /// stake no longer counts towards the staked total of the beneficiary and its
+        /// position cannot be unstaked. Its payer is credited back the part of the native fee
+        /// the contract still holds, claimed with `claim_refund`, the part forwarded to QP
+        /// was spent on the remote call. The amount is credited back once a relayer reports
+        /// it returned with `confirm_stake_returned`.
+        #[ink(message)]
+        pub fn finalize_stake(
+            &mut self,
+            request_id: u64,
+            success: bool,
+        ) -> Result<(), Error> {
+            if !self.relayers.contains(self.env().caller()) {
+                return Err(Error::NotRelayer)
+            }
+            let (beneficiary, position) = self
+                .stake_requests
+                .get(request_id)
+                .ok_or(Error::UnknownRequest)?;
+            let staked_position = self
+                .positions
+                .get((beneficiary, position))
+                .ok_or(Error::NoPosition)?;
+            if self.position_status_of(beneficiary, position) != PositionStatus::Pending {
+                return Err(Error::AlreadyFinalized)
+            }
+
+            let receipt = self.stake_receipts.get(request_id);
+            if success {
+                self.stake_receipts.remove(request_id);
+                self.position_status
+                    .insert((beneficiary, position), &PositionStatus::Confirmed);
+                self.env().emit_event(StakeConfirmed {
+                    request_id,
+                    beneficiary,
+                    position,
+                });
+            } else {
+                self.position_status
+                    .insert((beneficiary, position), &PositionStatus::Rejected);
+                let staked = self
+                    .staked_of(beneficiary)
+                    .saturating_sub(staked_position.amount);
+                self.staked.insert(beneficiary, &staked);
+                if let Some(receipt) = receipt {
+                    // kept until the amount is returned
+                    let receipt = self.refund_stake_fee(receipt)?;
+                    self.stake_receipts.insert(request_id, &receipt);
+                }
+                self.env().emit_event(StakeRejected {
+                    request_id,
+                    beneficiary,
+                    position,
+                });
+            }
+            Ok(())
+        }
+
+        /// Credit the payer of rejected stake request `request_id` back with its amount, once
+        /// a relayer observed the master chain return it to this contract. The payer claims
+        /// it with `claim_token_refund`.
+        #[ink(message)]
+        pub fn confirm_stake_returned(&mut self, request_id: u64) -> Result<(), Error> {
+            if !self.relayers.contains(self.env().caller()) {
+                return Err(Error::NotRelayer)
+            }
+            let (beneficiary, position) = self
+                .stake_requests
+                .get(request_id)
+                .ok_or(Error::UnknownRequest)?;
+            if self.position_status_of(beneficiary, position) != PositionStatus::Rejected
+            {
+                return Err(Error::NotRejected)
+            }
+            let staked_position = self
+                .positions
+                .get((beneficiary, position))
+                .ok_or(Error::NoPosition)?;
+            let receipt = self
+                .stake_receipts
+                .take(request_id)
+                .ok_or(Error::AlreadyReturned)?;
+            self.record_token_refund(
+                receipt.payer,
+                staked_position.token,
+                staked_position.amount,
+            )
+        }
+
+        /// Returns the reported outcome of position `id` of `beneficiary`.
+        #[ink(message)]
+        pub fn position_status_of(
+            &self,
+            beneficiary: [u8; 20],
+            id: u32,
+        ) -> PositionStatus {
+            self.position_status
+                .get((beneficiary, id))
+                .unwrap_or_default()
+        }
+
+        /// Set the share of native stake fees retained by the contract, in basis points.
+        #[ink(message)]
+        pub fn set_protocol_fee(&mut self, protocol_fee_bps: u16) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if u128::from(protocol_fee_bps) >= FEE_DENOMINATOR {
+                return Err(Error::InvalidProtocolFee)
+            }
+            self.protocol_fee_bps = protocol_fee_bps;
+            Ok(())
+        }
+
+        /// Returns the protocol fees retained by the contract.
+        #[ink(message)]
+        pub fn protocol_fees(&self) -> Balance {
+            self.protocol_fees
+        }
+
+        /// Transfer the retained protocol fees to `to`.
+        #[ink(message)]
+        pub fn withdraw_protocol_fees(&mut self, to: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let amount = self.protocol_fees;
+            if amount == 0 {
+                return Err(Error::NoProtocolFees)
+            }
+            self.protocol_fees = 0;
+
+            self.env()
+                .transfer(to, amount)
+                .map_err(|_| Error::ProtocolFeeTransferFailed)?;
+
+            self.env().emit_event(ProtocolFeesWithdrawn { to, amount });
+            Ok(())
+        }
+
+        /// Set the share of the retained protocol fee credited to the referrer of a stake, in
+        /// basis points.
+        #[ink(message)]
+        pub fn set_referral_share(&mut self, share_bps: u16) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if u128::from(share_bps) > FEE_DENOMINATOR {
+                return Err(Error::InvalidReferralShare)
+            }
+            self.referral_share_bps.set(&share_bps);
+            Ok(())
+        }
+
+        /// Returns the referral credit of `account` not paid out yet.
+        #[ink(message)]
+        pub fn referral_earnings(&self, account: AccountId) -> Balance {
+            self.referral_earnings.get(account).unwrap_or_default()
+        }
+
+        /// Transfer the referral credit of `referrer` to them.
+        #[ink(message)]
+        pub fn pay_referral_earnings(
+            &mut self,
+            referrer: AccountId,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let amount = self
+                .referral_earnings
+                .take(referrer)
+                .ok_or(Error::NoReferralEarnings)?;
+
+            self.env()
+                .transfer(referrer, amount)
+                .map_err(|_| Error::ReferralTransferFailed)?;
+
+            self.env().emit_event(ReferralPaid { referrer, amount });
+            Ok(())
+        }
+
+        /// Returns the refund claimable by `account`.
+        #[ink(message)]
+        pub fn refund_of(&self, account: AccountId) -> Balance {
+            self.refunds.get(account).unwrap_or_default()
+        }
+
+        /// Transfer the refund credited to the caller back to them.
+        #[ink(message)]
+        pub fn claim_refund(&mut self) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let amount = self.refunds.take(caller).ok_or(Error::NoRefund)?;
+
+            self.env()
+                .transfer(caller, amount)
+                .map_err(|_| Error::RefundTransferFailed)?;
+
+            self.env().emit_event(RefundClaimed {
+                account: caller,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// Returns the refund in `token` claimable by `account`.
+        #[ink(message)]
+        pub fn token_refund_of(&self, account: AccountId, token: [u8; 20]) -> Balance {
+            self.token_refunds
+                .get(account)
+                .unwrap_or_default()
+                .into_iter()
+                .find_map(|(refunded, amount)| (refunded == token).then_some(amount))
+                .unwrap_or_default()
+        }
+
+        /// Transfer the refund in `token` credited to the caller back to them.
+        #[ink(message)]
+        pub fn claim_token_refund(&mut self, token: [u8; 20]) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let mut refunds = self.token_refunds.get(caller).unwrap_or_default();
+            let index = refunds
+                .iter()
+                .position(|(refunded, _)| *refunded == token)
+                .ok_or(Error::NoRefund)?;
+            let (_, amount) = refunds.swap_remove(index);
+            if refunds.is_empty() {
+                self.token_refunds.remove(caller);
+            } else {
+                self.token_refunds.insert(caller, &refunds);
+            }
 
+            let encoded_input = Self::transfer_encode(Self::h160(&caller), amount.into());
             self.env()
                 .extension()
-                .xvm_call(
-                    super::EVM_ID,
-                    Vec::from(self.base_token.as_ref()),
-                    encoded_input,
-                )
-                .map_err(|_| Error::InsufficientBalance)?;
+                .xvm_call(self.evm_id(), Vec::from(token.as_ref()), encoded_input)
+                .map_err(|_| Error::RefundTransferFailed)?;
 
-            let encoded_input = Self::qp_encode(self, fee.into(), Self::h160(&caller));
+            self.env().emit_event(TokenRefundClaimed {
+                account: caller,
+                token,
+                amount,
+            });
+            Ok(())
+        }
 
-            let qp_result = self
-                .env()
+        /// Stake `amount` of `token`, pulled from the caller with `transferFrom`.
+        /// The caller must have approved this contract on the EVM side beforehand. The
+        /// native `fee` is attached to the call like for `stake`.
+        #[ink(message, payable)]
+        pub fn stake_erc20(
+            &mut self,
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+        ) -> Result<(), Error> {
+            // fail before pulling the tokens if there is nowhere to send them
+            let route = self.route(token)?;
+            self.stake_pulled(token, route, amount, fee)
+        }
+
+        /// Stake `amount` of `token` like `stake_erc20`, with the transfer approved by a
+        /// `permit` of the caller for this contract and `amount` instead of an approval
+        /// transaction on the EVM side. The native `fee` is attached to the call.
+        #[ink(message, payable)]
+        pub fn stake_erc20_with_permit(
+            &mut self,
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+            permit: Permit,
+        ) -> Result<(), Error> {
+            let route = self.route(token)?;
+            let caller = self.env().caller();
+            let contract = self.env().account_id();
+
+            let encoded_input = Self::permit_encode(
+                Self::h160(&caller),
+                Self::h160(&contract),
+                amount.into(),
+                &permit,
+            );
+
+            self.env()
                 .extension()
-                .xvm_call(
-                    super::EVM_ID,
-                    Vec::from(self.qp_contract_address.as_ref()),
-                    encoded_input,
-                )
-                .is_ok();
+                .xvm_call(self.evm_id(), Vec::from(token.as_ref()), encoded_input)
+                .map_err(|_| Error::PermitFailed)?;
 
-            qp_result.then_some(()).ok_or(Error::RemoteExecutionFailed)
+            self.stake_pulled(token, route, amount, fee)
         }
 
-        fn qp_encode(&mut self, fee: U256, sender_address: H160) -> Vec<u8> {
-            let mut encoded = QP_SELECTOR.to_vec();
-            // 3183e730 : stakeRemote()
-            let encoded_method: [u8; 4] = hex!["3183e730"];
-            let input = [
-                Token::Uint(fee),
-                Token::Uint(self.master_chain_id.into()),
-                Token::Address(self.master_contract_address.into()),
-                Token::Address(sender_address),
-                Token::Address(self.base_token.into()),
-                Token::Bytes(encoded_method.to_vec()),
-            ];
-            encoded.extend(&ethabi::encode(&input));
-            encoded
+        /// Stake `amount` of `token`, pulled from the caller with `transferFrom`, to the
+        /// master contract allowed on `remote_chain`. Works like `stake_erc20`.
+        #[ink(message, payable)]
+        pub fn stake_erc20_to(
+            &mut self,
+            remote_chain: u128,
+            token: [u8; 20],
+            amount: u128,
+            fee: u128,
+        ) -> Result<(), Error> {
+            let route = self.target(remote_chain)?;
+            self.stake_pulled(token, route, amount, fee)
         }
 
-        fn transfer_encode(to: H160, value: U256) -> Vec<u8> {
-            let mut encoded = TRANSFER_SELECTOR.to_vec();
-            let input = [Token::Address(to), Token::Uint(value)];
-            encoded.extend(&ethabi::encode(&input));
-            encoded
+        /// Stake the tokens pulled from the caller, with the native `fee` attached to the
+        /// call like for `stake`.
+        fn stake_pulled(
+            &mut self,
+            token: [u8; 20],
+            route: (u128, [u8; 20]),
+            amount: u128,
+            fee: u128,
+        ) -> Result<(), Error> {
+            let beneficiary = Self::h160(&self.env().caller());
+            self.stake_native(beneficiary, token, route, amount, fee, 0, None)
         }
 
-        fn h160(from: &AccountId) -> H160 {
-            let mut dest: H160 = [0; 20].into();
-            dest.as_bytes_mut()
-                .copy_from_slice(&<AccountId as AsRef<[u8]>>::as_ref(from)[..20]);
-            dest
+        /// Claim the caller's staking rewards on the master chain contract.
+        ///
+        /// Dispatches `claimRemote()` through `runWithValue` without any principal, only
+        /// `fee` is passed to the QP contract. The native `fee` is attached to the call.
+        #[ink(message, payable)]
+        pub fn claim_rewards(&mut self, fee: u128) -> Result<(), Error> {
+            if self.env().transferred_value() != fee {
+                return Err(Error::InvalidTransferredValue)
+            }
+            let caller = Self::h160(&self.env().caller());
+            self.dispatch_remote(caller, self.base_token, fee, &CLAIM_REMOTE_METHOD)
+        }
+
+        /// Run `method_selector` with the ABI encoded `encoded_args` on `remote_contract`
+        /// through `runWithValue`, for cross-chain interactions without a dedicated message.
+        ///
+        /// The call carries no principal, only `fee` is passed to the QP contract.
+        #[ink(message)]
+        pub fn run_remote(
+            &mut self,
+            remote_chain: u128,
+            remote_contract: [u8; 20],
+            method_selector: [u8; 4],
+            encoded_args: Vec<u8>,
+            fee: u128,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let caller = Self::h160(&self.env().caller());
+            let mut method = method_selector.to_vec();
+            method.extend(encoded_args);
+            self.dispatch_run(
+                caller,
+                remote_chain,
+                remote_contract,
+                self.base_token,
+                fee,
+                &method,
+            )
+        }
+
+        /// Replace the code of the contract with the uploaded code `code_hash`, keeping its
+        /// storage so stakes, positions and refunds carry over. If the new code changes the
+        /// storage layout the owner calls `migrate` next.
+        #[ink(message)]
+        pub fn upgrade(&mut self, code_hash: [u8; 32]) -> Result<(), Error> {
+            self.ensure_owner()?;
+            ink::env::set_code_hash(&code_hash).map_err(|_| Error::UpgradeFailed)?;
+            self.env().emit_event(Upgraded { code_hash });
+            Ok(())
+        }
+
+        /// Bring the stored data up to the layout of the running code, one version at a
+        /// time.
+        #[ink(message)]
+        pub fn migrate(&mut self) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let from = self.storage_version;
+            if from >= STORAGE_VERSION {
+                return Err(Error::AlreadyMigrated)
+            }
+            for version in from..STORAGE_VERSION {
+                self.migrate_from(version)?;
+            }
+            self.storage_version = STORAGE_VERSION;
+            self.env().emit_event(Migrated {
+                from,
+                to: STORAGE_VERSION,
+            });
+            Ok(())
+        }
+
+        /// Move the positions and stake accounting of `beneficiaries` and the unclaimed
+        /// refunds of `accounts` to `new_contract`, a redeployed instance that set this
+        /// contract as its migration source. They are passed in batches small enough for a
+        /// call, and can be listed from the `StakeRequested` and refund events.
+        ///
+        /// Pending stake requests move along with their positions, so their outcome is
+        /// reported to the new contract. The refunded value is attached to the import and
+        /// the refunded tokens are transferred to the new contract. Nothing is removed here
+        /// before the new contract imported it.
+        #[ink(message)]
+        pub fn migrate_positions(
+            &mut self,
+            new_contract: AccountId,
+            beneficiaries: Vec<[u8; 20]>,
+            accounts: Vec<AccountId>,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let ledgers: Vec<PositionLedger> = beneficiaries
+                .into_iter()
+                .map(|beneficiary| self.position_ledger(beneficiary))
+                .filter(|ledger| ledger.position_count > 0)
+                .collect();
+            let refunds: Vec<RefundLedger> = accounts
+                .into_iter()
+                .map(|account| self.refund_ledger(account))
+                .filter(|ledger| ledger.refund > 0 || !ledger.token_refunds.is_empty())
+                .collect();
+
+            let mut value: Balance = 0;
+            let mut tokens = Vec::new();
+            for ledger in refunds.iter() {
+                value = value.checked_add(ledger.refund).ok_or(Error::Overflow)?;
+                for (token, amount) in ledger.token_refunds.iter() {
+                    Self::credit(&mut tokens, *token, *amount)?;
+                }
+            }
+            for (token, amount) in tokens {
+                let encoded_input =
+                    Self::transfer_encode(Self::h160(&new_contract), amount.into());
+                self.env()
+                    .extension()
+                    .xvm_call(self.evm_id(), Vec::from(token.as_ref()), encoded_input)
+                    .map_err(|_| Error::MigrationFailed)?;
+            }
+
+            let imported = build_call::<Environment>()
+                .call(new_contract)
+                .transferred_value(value)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!(
+                        "import_positions"
+                    )))
+                    .push_arg(&ledgers)
+                    .push_arg(&refunds),
+                )
+                .returns::<Result<(), Error>>()
+                .try_invoke();
+            if !matches!(imported, Ok(Ok(Ok(())))) {
+                return Err(Error::MigrationFailed)
+            }
+
+            for ledger in ledgers.iter() {
+                for (id, _, _) in ledger.positions.iter() {
+                    self.positions.remove((ledger.beneficiary, *id));
+                    self.position_status.remove((ledger.beneficiary, *id));
+                    self.position_requests.remove((ledger.beneficiary, *id));
+                }
+                for (request_id, _, _) in ledger.requests.iter() {
+                    self.stake_requests.remove(*request_id);
+                    self.stake_receipts.remove(*request_id);
+                }
+                self.position_count.remove(ledger.beneficiary);
+                self.staked.remove(ledger.beneficiary);
+            }
+            for ledger in refunds.iter() {
+                self.refunds.remove(ledger.account);
+                self.token_refunds.remove(ledger.account);
+            }
+            self.env().emit_event(PositionsMigrated {
+                to: new_contract,
+                count: ledgers.len() as u32,
+            });
+            Ok(())
+        }
+
+        /// Allow `source` to import its positions into this contract.
+        #[ink(message)]
+        pub fn set_migration_source(&mut self, source: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.migration_source.set(&source);
+            Ok(())
+        }
+
+        /// Register the positions and refunds exported by the migration source with
+        /// `migrate_positions`, with the refunded value attached. A beneficiary who already
+        /// has positions here or a stake request id already in use is rejected, so the
+        /// imported ids stay valid.
+        #[ink(message, payable)]
+        pub fn import_positions(
+            &mut self,
+            ledgers: Vec<PositionLedger>,
+            refunds: Vec<RefundLedger>,
+        ) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if self.migration_source.get() != Some(caller) {
+                return Err(Error::NotMigrationSource)
+            }
+            if ledgers
+                .iter()
+                .any(|ledger| self.position_count_of(ledger.beneficiary) > 0)
+            {
+                return Err(Error::PositionsExist)
+            }
+            if ledgers
+                .iter()
+                .flat_map(|ledger| ledger.requests.iter())
+                .any(|(request_id, _, _)| self.stake_requests.contains(request_id))
+            {
+                return Err(Error::DuplicateRequest)
+            }
+            let value = refunds
+                .iter()
+                .try_fold(0, |value: Balance, ledger| value.checked_add(ledger.refund))
+                .ok_or(Error::Overflow)?;
+            if self.env().transferred_value() != value {
+                return Err(Error::InvalidTransferredValue)
+            }
+
+            let mut next_request_id = self.next_request_id.get().unwrap_or_default();
+            for ledger in ledgers.iter() {
+                for (id, position, status) in ledger.positions.iter() {
+                    self.positions.insert((ledger.beneficiary, *id), position);
+                    if *status != PositionStatus::Pending {
+                        self.position_status
+                            .insert((ledger.beneficiary, *id), status);
+                    }
+                }
+                for (request_id, id, receipt) in ledger.requests.iter() {
+                    self.stake_requests
+                        .insert(request_id, &(ledger.beneficiary, *id));
+                    self.position_requests
+                        .insert((ledger.beneficiary, *id), request_id);
+                    if let Some(receipt) = receipt {
+                        self.stake_receipts.insert(request_id, receipt);
+                    }
+                    next_request_id = next_request_id
+                        .max(request_id.checked_add(1).ok_or(Error::Overflow)?);
+                }
+                self.position_count
+                    .insert(ledger.beneficiary, &ledger.position_count);
+                self.staked.insert(ledger.beneficiary, &ledger.staked);
+            }
+            self.next_request_id.set(&next_request_id);
+            for ledger in refunds.iter() {
+                if ledger.refund > 0 {
+                    self.record_refund(ledger.account, ledger.refund)?;
+                }
+                for (token, amount) in ledger.token_refunds.iter() {
+                    self.record_token_refund(ledger.account, *token, *amount)?;
+                }
+            }
+            self.env().emit_event(PositionsImported {
+                from: caller,
+                count: ledgers.len() as u32,
+            });
+            Ok(())
+        }
+
+        /// Returns the layout version of the stored data.
+        #[ink(message)]
+        pub fn storage_version(&self) -> u16 {
+            self.storage_version
+        }
+
+        fn position_ledger(&self, beneficiary: [u8; 20]) -> PositionLedger {
+            let position_count = self.position_count_of(beneficiary);
+            let positions: Vec<_> = (0..position_count)
+                .filter_map(|id| {
+                    let position = self.positions.get((beneficiary, id))?;
+                    Some((id, position, self.position_status_of(beneficiary, id)))
+                })
+                .collect();
+            let requests = positions
+                .iter()
+                .filter(|(_, _, status)| *status != PositionStatus::Confirmed)
+                .filter_map(|(id, _, status)| {
+                    let request_id = self.position_requests.get((beneficiary, *id))?;
+                    let receipt = self.stake_receipts.get(request_id);
+                    // a rejected stake keeps its receipt until its amount is returned
+                    if *status == PositionStatus::Rejected && receipt.is_none() {
+                        return None
+                    }
+                    Some((request_id, *id, receipt))
+                })
+                .collect();
+            PositionLedger {
+                beneficiary,
+                staked: self.staked_of(beneficiary),
+                position_count,
+                positions,
+                requests,
+            }
+        }
+
+        fn refund_ledger(&self, account: AccountId) -> RefundLedger {
+            RefundLedger {
+                account,
+                refund: self.refund_of(account),
+                token_refunds: self.token_refunds.get(account).unwrap_or_default(),
+            }
+        }
+
+        /// Migrate the stored data from `version` to the next one. A layout change adds its
+        /// step here and bumps `STORAGE_VERSION`.
+        ///
+        /// No step is needed so far. The refunds, stake requests, positions, referrals and
+        /// every other field added along with or after the version are `Mapping` or `Lazy`
+        /// fields, stored under their own keys outside the root storage. The root data
+        /// written by earlier code decodes unchanged and the new fields start empty.
+        fn migrate_from(&mut self, version: u16) -> Result<(), Error> {
+            match version {
+                0 => Ok(()),
+                _ => Err(Error::AlreadyMigrated),
+            }
+        }
+
+        /// Pull `amount` of `token` from the caller with `transferFrom`, the caller must have
+        /// approved this contract on the EVM side.
+        fn pull_from_caller(
+            &mut self,
+            token: [u8; 20],
+            amount: Balance,
+        ) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let contract = self.env().account_id();
+            let encoded_input = Self::transfer_from_encode(
+                Self::h160(&caller),
+                Self::h160(&contract),
+                amount.into(),
+            );
+
+            self.env()
+                .extension()
+                .xvm_call(self.evm_id(), Vec::from(token.as_ref()), encoded_input)
+                .map_err(|_| Error::TransferFromFailed)
+        }
+
+        /// Send the staked amount to the QP contract and dispatch `runWithValue` to the
+        /// `(remote_chain, remote_contract)` route, with the lock period if any.
+        fn dispatch_stake(
+            &mut self,
+            beneficiary: H160,
+            token: [u8; 20],
+            route: (u128, [u8; 20]),
+            amount: u128,
+            fee: u128,
+            lock_period: u64,
+        ) -> Result<(), Error> {
+            // transfer the amount to the QP contract
+            let encoded_input =
+                Self::transfer_encode(self.qp_contract_address.into(), amount.into());
+
+            self.env()
+                .extension()
+                .xvm_call(self.evm_id(), Vec::from(token.as_ref()), encoded_input)
+                .map_err(|_| Error::InsufficientBalance)?;
+
+            let method = if lock_period == 0 {
+                STAKE_REMOTE_METHOD.to_vec()
+            } else {
+                let mut method = STAKE_REMOTE_LOCKED_METHOD.to_vec();
+                method.extend(ethabi::encode(&[Token::Uint(lock_period.into())]));
+                method
+            };
+            let (remote_chain, remote_contract) = route;
+            self.dispatch_run(
+                beneficiary,
+                remote_chain,
+                remote_contract,
+                token,
+                fee,
+                &method,
+            )
+        }
+
+        /// Open a position for a stake sent to the master chain, returns the id of its
+        /// stake request.
+        fn open_position(
+            &mut self,
+            beneficiary: H160,
+            token: [u8; 20],
+            route: (u128, [u8; 20]),
+            amount: Balance,
+            lock_period: u64,
+            referrer: Option<AccountId>,
+        ) -> Result<u64, Error> {
+            let unlock_at = self
+                .env()
+                .block_timestamp()
+                .checked_add(lock_period.checked_mul(1000).ok_or(Error::Overflow)?)
+                .ok_or(Error::Overflow)?;
+            let id = self.position_count_of(beneficiary.0);
+            let position = Position {
+                token,
+                remote_chain: route.0,
+                remote_contract: route.1,
+                amount,
+                unlock_at,
+            };
+            self.positions.insert((beneficiary.0, id), &position);
+            self.position_count
+                .insert(beneficiary.0, &id.checked_add(1).ok_or(Error::Overflow)?);
+
+            let request_id = self.next_request_id.get().unwrap_or_default();
+            self.stake_requests.insert(request_id, &(beneficiary.0, id));
+            self.position_requests
+                .insert((beneficiary.0, id), &request_id);
+            self.next_request_id
+                .set(&request_id.checked_add(1).ok_or(Error::Overflow)?);
+            self.env().emit_event(Staked {
+                account: self.env().caller(),
+                beneficiary: beneficiary.0,
+                token,
+                amount,
+                referrer,
+            });
+            self.env().emit_event(StakeRequested {
+                request_id,
+                beneficiary: beneficiary.0,
+                position: id,
+            });
+            Ok(request_id)
+        }
+
+        /// Credit `referrer` with the referral share of `protocol_fee`, returns the amount
+        /// credited.
+        fn credit_referral(
+            &mut self,
+            referrer: Option<AccountId>,
+            protocol_fee: Balance,
+        ) -> Result<Balance, Error> {
+            let Some(referrer) = referrer else {
+                return Ok(0)
+            };
+            let share = self.referral_share_bps.get().unwrap_or_default();
+            let credit = protocol_fee.saturating_mul(share.into()) / FEE_DENOMINATOR;
+            if credit > 0 {
+                let total = self
+                    .referral_earnings(referrer)
+                    .checked_add(credit)
+                    .ok_or(Error::Overflow)?;
+                self.referral_earnings.insert(referrer, &total);
+            }
+            Ok(credit)
+        }
+
+        /// Dispatch `method` on the remote contract of `token` through `runWithValue`, on
+        /// behalf of `beneficiary`.
+        fn dispatch_remote(
+            &mut self,
+            beneficiary: H160,
+            token: [u8; 20],
+            fee: u128,
+            method: &[u8],
+        ) -> Result<(), Error> {
+            let (remote_chain, remote_contract) = self.route(token)?;
+            self.dispatch_run(
+                beneficiary,
+                remote_chain,
+                remote_contract,
+                token,
+                fee,
+                method,
+            )
+        }
+
+        /// Dispatch `method` on `remote_contract` through `runWithValue`, on behalf of
+        /// `beneficiary`.
+        fn dispatch_run(
+            &mut self,
+            beneficiary: H160,
+            remote_chain: u128,
+            remote_contract: [u8; 20],
+            token: [u8; 20],
+            fee: u128,
+            method: &[u8],
+        ) -> Result<(), Error> {
+            let min_fee = self.min_fees.get(remote_chain).unwrap_or_default();
+            if fee < min_fee {
+                return Err(Error::FeeTooLow)
+            }
+
+            let (vm_id, qp_selector) = self.xvm_target();
+            let encoded_input = Self::qp_encode(
+                qp_selector,
+                fee.into(),
+                remote_chain,
+                remote_contract,
+                beneficiary,
+                token,
+                method,
+            );
+
+            let qp_result = self
+                .env()
+                .extension()
+                .xvm_call(
+                    vm_id,
+                    Vec::from(self.qp_contract_address.as_ref()),
+                    encoded_input,
+                )
+                .is_ok();
+
+            qp_result.then_some(()).ok_or(Error::RemoteExecutionFailed)
+        }
+
+        fn evm_id(&self) -> u8 {
+            self.xvm_target().0
+        }
+
+        /// The base token falls back to the master contract when no route is set.
+        fn route(&self, token: [u8; 20]) -> Result<(u128, [u8; 20]), Error> {
+            match self.routes.get(token) {
+                Some(route) => Ok(route),
+                None if token == self.base_token => {
+                    Ok((self.master_chain_id, self.master_contract_address))
+                },
+                None => Err(Error::NoRoute),
+            }
+        }
+
+        fn target(&self, remote_chain: u128) -> Result<(u128, [u8; 20]), Error> {
+            let master_contract =
+                self.targets.get(remote_chain).ok_or(Error::UnknownTarget)?;
+            Ok((remote_chain, master_contract))
+        }
+
+        /// Returns the total staked by `beneficiary` once `amount` is staked.
+        fn check_stake_limits(
+            &self,
+            beneficiary: H160,
+            amount: Balance,
+        ) -> Result<Balance, Error> {
+            if amount < self.min_stake {
+                return Err(Error::BelowMinimum)
+            }
+            if matches!(self.max_stake, Some(max_stake) if amount > max_stake) {
+                return Err(Error::AboveMaximum)
+            }
+            let staked = self
+                .staked_of(beneficiary.0)
+                .checked_add(amount)
+                .ok_or(Error::Overflow)?;
+            if matches!(self.account_cap, Some(cap) if staked > cap) {
+                return Err(Error::CapExceeded)
+            }
+            Ok(staked)
+        }
+
+        fn protocol_fee(&self, fee: Balance) -> Balance {
+            fee.saturating_mul(self.protocol_fee_bps.into()) / FEE_DENOMINATOR
+        }
+
+        fn ensure_owner(&self) -> Result<(), Error> {
+            if self.env().caller() != self.owner {
+                return Err(Error::NotOwner)
+            }
+            Ok(())
+        }
+
+        /// Credit the value attached to an expired stake back to `account`.
+        fn expire_stake(
+            &mut self,
+            account: AccountId,
+            token: [u8; 20],
+            amount: Balance,
+            execute_by: Timestamp,
+        ) -> Result<(), Error> {
+            let value = self.env().transferred_value();
+            if value > 0 {
+                self.record_refund(account, value)?;
+            }
+            self.env().emit_event(StakeExpired {
+                account,
+                token,
+                amount,
+                execute_by,
+            });
+            Ok(())
+        }
+
+        /// Credit the payer of a rejected stake back with the part of its fee the contract
+        /// still holds: the retained protocol fee not withdrawn yet and the referral credit
+        /// not paid out yet, which is taken back from the referrer. Returns the receipt with
+        /// nothing left to refund.
+        fn refund_stake_fee(
+            &mut self,
+            receipt: StakeReceipt,
+        ) -> Result<StakeReceipt, Error> {
+            let reclaimed = receipt.retained.min(self.protocol_fees);
+            self.protocol_fees -= reclaimed;
+            let reversed = match receipt.referrer {
+                Some(referrer) => {
+                    let earnings = self.referral_earnings(referrer);
+                    let reversed = earnings.min(receipt.referral);
+                    if earnings == reversed {
+                        self.referral_earnings.remove(referrer);
+                    } else {
+                        self.referral_earnings
+                            .insert(referrer, &(earnings - reversed));
+                    }
+                    reversed
+                },
+                None => 0,
+            };
+            let refund = reclaimed.checked_add(reversed).ok_or(Error::Overflow)?;
+            if refund > 0 {
+                self.record_refund(receipt.payer, refund)?;
+            }
+            Ok(StakeReceipt {
+                retained: 0,
+                referral: 0,
+                ..receipt
+            })
+        }
+
+        fn record_token_refund(
+            &mut self,
+            account: AccountId,
+            token: [u8; 20],
+            amount: Balance,
+        ) -> Result<(), Error> {
+            let mut refunds = self.token_refunds.get(account).unwrap_or_default();
+            Self::credit(&mut refunds, token, amount)?;
+            self.token_refunds.insert(account, &refunds);
+
+            self.env().emit_event(TokenRefundRecorded {
+                account,
+                token,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// Add `amount` to the entry of `token` in `balances`.
+        fn credit(
+            balances: &mut Vec<([u8; 20], Balance)>,
+            token: [u8; 20],
+            amount: Balance,
+        ) -> Result<(), Error> {
+            match balances.iter_mut().find(|(credited, _)| *credited == token) {
+                Some((_, total)) => {
+                    *total = total.checked_add(amount).ok_or(Error::Overflow)?
+                },
+                None => balances.push((token, amount)),
+            }
+            Ok(())
+        }
+
+        fn record_refund(
+            &mut self,
+            account: AccountId,
+            amount: Balance,
+        ) -> Result<(), Error> {
+            let total = self
+                .refund_of(account)
+                .checked_add(amount)
+                .ok_or(Error::Overflow)?;
+            self.refunds.insert(account, &total);
+
+            self.env().emit_event(RefundRecorded { account, amount });
+            Ok(())
+        }
+
+        fn qp_encode(
+            selector: [u8; 4],
+            fee: U256,
+            remote_chain: u128,
+            remote_contract: [u8; 20],
+            sender_address: H160,
+            token: [u8; 20],
+            method: &[u8],
+        ) -> Vec<u8> {
+            encoding::run_with_value_encode_as(
+                selector,
+                fee,
+                remote_chain.into(),
+                remote_contract.into(),
+                sender_address,
+                token.into(),
+                method,
+            )
+        }
+
+        fn transfer_encode(to: H160, value: U256) -> Vec<u8> {
+            let mut encoded = TRANSFER_SELECTOR.to_vec();
+            let input = [Token::Address(to), Token::Uint(value)];
+            encoded.extend(&ethabi::encode(&input));
+            encoded
+        }
+
+        fn approve_encode(spender: H160, value: U256) -> Vec<u8> {
+            let mut encoded = APPROVE_SELECTOR.to_vec();
+            let input = [Token::Address(spender), Token::Uint(value)];
+            encoded.extend(&ethabi::encode(&input));
+            encoded
+        }
+
+        fn transfer_from_encode(from: H160, to: H160, value: U256) -> Vec<u8> {
+            let mut encoded = TRANSFER_FROM_SELECTOR.to_vec();
+            let input = [Token::Address(from), Token::Address(to), Token::Uint(value)];
+            encoded.extend(&ethabi::encode(&input));
+            encoded
+        }
+
+        fn permit_encode(
+            owner: H160,
+            spender: H160,
+            value: U256,
+            permit: &Permit,
+        ) -> Vec<u8> {
+            let mut encoded = PERMIT_SELECTOR.to_vec();
+            let input = [
+                Token::Address(owner),
+                Token::Address(spender),
+                Token::Uint(value),
+                Token::Uint(permit.deadline.into()),
+                Token::Uint(permit.v.into()),
+                Token::FixedBytes(permit.r.to_vec()),
+                Token::FixedBytes(permit.s.to_vec()),
+            ];
+            encoded.extend(&ethabi::encode(&input));
+            encoded
+        }
+
+        fn h160(from: &AccountId) -> H160 {
+            let mut dest: H160 = [0; 20].into();
+            dest.as_bytes_mut()
+                .copy_from_slice(&<AccountId as AsRef<[u8]>>::as_ref(from)[..20]);
+            dest
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ink::env::test;
+        use scale::{
+            Decode,
+            Encode,
+        };
+        use std::{
+            cell::RefCell,
+            rc::Rc,
+        };
+
+        type Env = xvm_environment::XvmDefaultEnvironment;
+
+        /// Chain extension id of `xvm_call`
+        const XVM_CALL_FUNC_ID: u32 = 0x00010001;
+
+        const QP_CONTRACT: [u8; 20] = [0x44; 20];
+        const MASTER_CHAIN_ID: u128 = 26100;
+        const MASTER_CONTRACT: [u8; 20] = [0x11; 20];
+        const BASE_TOKEN: [u8; 20] = [0x33; 20];
+
+        type XvmCalls = Rc<RefCell<Vec<(Vec<u8>, Vec<u8>)>>>;
+
+        /// Records the `(target, input)` of every `xvm_call` and reverts the calls made to
+        /// `reverting_target`. Every call must be made to the VM `vm_id`.
+        struct MockXvm {
+            calls: XvmCalls,
+            reverting_target: Option<Vec<u8>>,
+            vm_id: u8,
+        }
+
+        impl test::ChainExtension for MockXvm {
+            fn func_id(&self) -> u32 {
+                XVM_CALL_FUNC_ID
+            }
+
+            fn call(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> u32 {
+                let (vm_id, target, input) =
+                    <(u8, Vec<u8>, Vec<u8>)>::decode(&mut input).unwrap();
+                assert_eq!(vm_id, self.vm_id, "xvm_call to another VM");
+                let reverted = self.reverting_target.as_ref() == Some(&target);
+                self.calls.borrow_mut().push((target, input));
+                if reverted {
+                    return 1
+                }
+                Ok::<(), ()>(()).encode_to(output);
+                0
+            }
+        }
+
+        fn setup(reverting_target: Option<[u8; 20]>) -> (QpStaking, XvmCalls) {
+            let calls = XvmCalls::default();
+            test::register_chain_extension(MockXvm {
+                calls: calls.clone(),
+                reverting_target: reverting_target.map(Vec::from),
+                vm_id: crate::EVM_ID,
+            });
+            let accounts = test::default_accounts::<Env>();
+            test::set_caller::<Env>(accounts.alice);
+            let contract =
+                QpStaking::new(QP_CONTRACT, MASTER_CHAIN_ID, MASTER_CONTRACT, BASE_TOKEN);
+            (contract, calls)
+        }
+
+        /// Runs `message` like a call on chain, where a message returning `Err` reverts
+        /// along with the XVM calls it made.
+        fn transact<T>(
+            calls: &XvmCalls,
+            message: impl FnOnce() -> Result<T, Error>,
+        ) -> Result<T, Error> {
+            let made = calls.borrow().len();
+            let result = message();
+            if result.is_err() {
+                calls.borrow_mut().truncate(made);
+            }
+            result
+        }
+
+        /// Returns the change of the `token` balance of `holder` made by the recorded
+        /// `transfer` and `transferFrom` calls, the contract being the sender of transfers.
+        fn token_balance(calls: &XvmCalls, token: [u8; 20], holder: H160) -> i128 {
+            let contract = QpStaking::h160(&test::callee::<Env>());
+            let address = |word: &[u8]| H160::from_slice(&word[12..32]);
+            let value =
+                |word: &[u8]| i128::from_be_bytes(word[16..32].try_into().unwrap());
+            calls
+                .borrow()
+                .iter()
+                .filter(|(target, _)| target[..] == token[..])
+                .map(|(_, input)| {
+                    let selector = &input[..4];
+                    let (from, to, amount) = if selector == TRANSFER_SELECTOR {
+                        (contract, address(&input[4..]), value(&input[36..]))
+                    } else if selector == TRANSFER_FROM_SELECTOR {
+                        (
+                            address(&input[4..]),
+                            address(&input[36..]),
+                            value(&input[68..]),
+                        )
+                    } else {
+                        return 0
+                    };
+                    match holder {
+                        h if h == from && h == to => 0,
+                        h if h == from => -amount,
+                        h if h == to => amount,
+                        _ => 0,
+                    }
+                })
+                .sum()
+        }
+
+        #[ink::test]
+        fn stake_transfers_and_dispatches_stake_remote() {
+            let (mut contract, calls) = setup(None);
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+
+            let caller = QpStaking::h160(&test::default_accounts::<Env>().alice);
+            let contract_address = QpStaking::h160(&test::callee::<Env>());
+            assert_eq!(token_balance(&calls, BASE_TOKEN, caller), -100);
+            assert_eq!(token_balance(&calls, BASE_TOKEN, contract_address), 0);
+            assert_eq!(token_balance(&calls, BASE_TOKEN, QP_CONTRACT.into()), 100);
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 3);
+            assert_eq!(
+                calls[0],
+                (
+                    BASE_TOKEN.to_vec(),
+                    QpStaking::transfer_from_encode(caller, contract_address, 100.into())
+                )
+            );
+            assert_eq!(
+                calls[1],
+                (
+                    BASE_TOKEN.to_vec(),
+                    QpStaking::transfer_encode(QP_CONTRACT.into(), 100.into())
+                )
+            );
+            assert_eq!(calls[2].0, QP_CONTRACT.to_vec());
+            assert_eq!(calls[2].1[..4], QP_SELECTOR);
+            assert_eq!(
+                calls[2].1[calls[2].1.len() - 32..][..4],
+                STAKE_REMOTE_METHOD
+            );
+        }
+
+        #[ink::test]
+        fn stake_dispatches_with_the_configured_selector() {
+            let (mut contract, calls) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            let selector = encoding::run_with_value_selector("runWithValueV2");
+            assert_eq!(contract.xvm_target(), (crate::EVM_ID, QP_SELECTOR));
+            assert_eq!(
+                contract.set_xvm_target(0x1F, String::from("runWithValueV2")),
+                Ok(())
+            );
+            assert_eq!(contract.xvm_target(), (0x1F, selector));
+
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.set_xvm_target(crate::EVM_ID, String::from("runWithValue")),
+                Err(Error::NotOwner)
+            );
+
+            test::register_chain_extension(MockXvm {
+                calls: calls.clone(),
+                reverting_target: None,
+                vm_id: 0x1F,
+            });
+            test::set_caller::<Env>(accounts.alice);
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 3);
+            assert_eq!(calls[2].0, QP_CONTRACT.to_vec());
+            assert_eq!(
+                calls[2].1,
+                QpStaking::qp_encode(
+                    selector,
+                    10.into(),
+                    MASTER_CHAIN_ID,
+                    MASTER_CONTRACT,
+                    QpStaking::h160(&accounts.alice),
+                    BASE_TOKEN,
+                    &STAKE_REMOTE_METHOD,
+                )
+            );
+        }
+
+        #[ink::test]
+        fn stake_pays_the_fee_in_the_fee_token() {
+            let (mut contract, calls) = setup(None);
+            let fee_token = [0x77; 20];
+            let fee_store = [0x88; 20];
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        fee_token,
+                        ..Default::default()
+                    }
+                ),
+                Err(Error::InvalidTransferredValue)
+            );
+            test::set_value_transferred::<Env>(0);
+
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        fee_token,
+                        ..Default::default()
+                    }
+                ),
+                Err(Error::NoFeeStore)
+            );
+            assert_eq!(contract.set_fee_store(fee_store), Ok(()));
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        fee_token,
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+
+            let caller = QpStaking::h160(&test::default_accounts::<Env>().alice);
+            let contract_address = QpStaking::h160(&test::callee::<Env>());
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 5);
+            assert_eq!(
+                calls[0],
+                (
+                    fee_token.to_vec(),
+                    QpStaking::transfer_from_encode(caller, contract_address, 10.into())
+                )
+            );
+            assert_eq!(
+                calls[1],
+                (
+                    fee_token.to_vec(),
+                    QpStaking::approve_encode(fee_store.into(), 10.into())
+                )
+            );
+            assert_eq!(
+                calls[2],
+                (
+                    BASE_TOKEN.to_vec(),
+                    QpStaking::transfer_from_encode(caller, contract_address, 100.into())
+                )
+            );
+            assert_eq!(calls[4].0, QP_CONTRACT.to_vec());
+        }
+
+        #[ink::test]
+        fn stake_rejects_a_reused_idempotency_key() {
+            let (mut contract, calls) = setup(None);
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        idempotency_key: Some([1; 32]),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        idempotency_key: Some([1; 32]),
+                        ..Default::default()
+                    }
+                ),
+                Err(Error::DuplicateRequest)
+            );
+            assert_eq!(calls.borrow().len(), 3);
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        idempotency_key: Some([2; 32]),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+        }
+
+        #[ink::test]
+        fn stake_expires_after_its_deadline() {
+            let (mut contract, calls) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            test::set_block_timestamp::<Env>(2_000);
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        execute_by: Some(1_999),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert!(calls.borrow().is_empty());
+            assert_eq!(contract.refund_of(accounts.alice), 10);
+            assert_eq!(
+                contract.position_count_of(QpStaking::h160(&accounts.alice).0),
+                0
+            );
+
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    10,
+                    StakeParams {
+                        execute_by: Some(2_000),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(calls.borrow().len(), 3);
+            assert_eq!(contract.refund_of(accounts.alice), 10);
+        }
+
+        #[ink::test]
+        fn stake_credits_the_referrer() {
+            let (mut contract, _) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            contract.set_protocol_fee(1000).unwrap();
+            assert_eq!(contract.set_referral_share(5000), Ok(()));
+            test::set_value_transferred::<Env>(100);
+
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    100,
+                    StakeParams {
+                        referrer: Some(accounts.alice),
+                        ..Default::default()
+                    }
+                ),
+                Err(Error::SelfReferral)
+            );
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    100,
+                    100,
+                    StakeParams {
+                        referrer: Some(accounts.bob),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(contract.referral_earnings(accounts.bob), 5);
+            assert_eq!(contract.protocol_fees(), 5);
+
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.pay_referral_earnings(accounts.bob),
+                Err(Error::NotOwner)
+            );
+            test::set_caller::<Env>(accounts.alice);
+            test::set_account_balance::<Env>(test::callee::<Env>(), 100);
+            assert_eq!(contract.pay_referral_earnings(accounts.bob), Ok(()));
+            assert_eq!(contract.referral_earnings(accounts.bob), 0);
+            assert_eq!(
+                contract.pay_referral_earnings(accounts.bob),
+                Err(Error::NoReferralEarnings)
+            );
+        }
+
+        #[ink::test]
+        fn stake_rejects_wrong_transferred_value() {
+            let (mut contract, calls) = setup(None);
+            test::set_value_transferred::<Env>(110);
+
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Err(Error::InvalidTransferredValue)
+            );
+            assert!(calls.borrow().is_empty());
+        }
+
+        #[ink::test]
+        fn stake_reverts_when_qp_reverts() {
+            let (mut contract, calls) = setup(Some(QP_CONTRACT));
+            let caller = test::default_accounts::<Env>().alice;
+            let staker = QpStaking::h160(&caller);
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                transact(&calls, || {
+                    contract.stake(BASE_TOKEN, 100, 10, StakeParams::default())
+                }),
+                Err(Error::RemoteExecutionFailed)
+            );
+            assert_eq!(token_balance(&calls, BASE_TOKEN, staker), 0);
+            assert_eq!(token_balance(&calls, BASE_TOKEN, QP_CONTRACT.into()), 0);
+            assert_eq!(contract.refund_of(caller), 0);
+            assert_eq!(contract.staked_of(staker.0), 0);
+            assert_eq!(contract.position_count_of(staker.0), 0);
+        }
+
+        #[ink::test]
+        fn stake_fails_when_token_transfer_reverts() {
+            let (mut contract, _) = setup(Some(BASE_TOKEN));
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Err(Error::TransferFromFailed)
+            );
+        }
+
+        #[ink::test]
+        fn stake_erc20_requires_the_fee_as_value() {
+            let (mut contract, calls) = setup(None);
+            test::set_value_transferred::<Env>(0);
+
+            assert_eq!(
+                transact(&calls, || contract.stake_erc20(BASE_TOKEN, 100, 10)),
+                Err(Error::InvalidTransferredValue)
+            );
+            assert!(calls.borrow().is_empty());
+
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(contract.stake_erc20(BASE_TOKEN, 100, 10), Ok(()));
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 3);
+            assert_eq!(calls[2].0, QP_CONTRACT.to_vec());
+        }
+
+        #[ink::test]
+        fn stake_erc20_with_permit_permits_then_pulls() {
+            let (mut contract, calls) = setup(None);
+            test::set_value_transferred::<Env>(10);
+            let permit = Permit {
+                deadline: 1_700_000_000,
+                v: 27,
+                r: [0x01; 32],
+                s: [0x02; 32],
+            };
+
+            assert_eq!(
+                contract.stake_erc20_with_permit(BASE_TOKEN, 100, 10, permit.clone()),
+                Ok(())
+            );
+
+            let caller = QpStaking::h160(&test::default_accounts::<Env>().alice);
+            let contract_address = QpStaking::h160(&test::callee::<Env>());
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 4);
+            assert_eq!(
+                calls[0],
+                (
+                    BASE_TOKEN.to_vec(),
+                    QpStaking::permit_encode(
+                        caller,
+                        contract_address,
+                        100.into(),
+                        &permit
+                    )
+                )
+            );
+            assert_eq!(
+                calls[1].1,
+                QpStaking::transfer_from_encode(caller, contract_address, 100.into())
+            );
+            assert_eq!(calls[3].0, QP_CONTRACT.to_vec());
+        }
+
+        #[ink::test]
+        fn stake_erc20_with_permit_requires_the_fee_as_value() {
+            let (mut contract, calls) = setup(None);
+            test::set_value_transferred::<Env>(0);
+            let permit = Permit {
+                deadline: 1_700_000_000,
+                v: 27,
+                r: [0x01; 32],
+                s: [0x02; 32],
+            };
+
+            assert_eq!(
+                transact(&calls, || {
+                    contract.stake_erc20_with_permit(BASE_TOKEN, 100, 10, permit)
+                }),
+                Err(Error::InvalidTransferredValue)
+            );
+            assert!(calls.borrow().is_empty());
+        }
+
+        #[ink::test]
+        fn stake_erc20_with_permit_fails_on_a_rejected_permit() {
+            let (mut contract, calls) = setup(Some(BASE_TOKEN));
+            let permit = Permit {
+                deadline: 0,
+                v: 27,
+                r: [0; 32],
+                s: [0; 32],
+            };
+
+            assert_eq!(
+                contract.stake_erc20_with_permit(BASE_TOKEN, 100, 10, permit),
+                Err(Error::PermitFailed)
+            );
+            assert_eq!(calls.borrow().len(), 1);
+        }
+
+        #[ink::test]
+        fn claim_rewards_dispatches_claim_remote_only() {
+            let (mut contract, calls) = setup(None);
+
+            assert_eq!(
+                contract.claim_rewards(10),
+                Err(Error::InvalidTransferredValue)
+            );
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(contract.claim_rewards(10), Ok(()));
+
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 1);
+            assert_eq!(calls[0].0, QP_CONTRACT.to_vec());
+            assert_eq!(
+                calls[0].1[calls[0].1.len() - 32..][..4],
+                CLAIM_REMOTE_METHOD
+            );
+        }
+
+        #[ink::test]
+        fn stake_uses_the_token_route() {
+            let (mut contract, calls) = setup(None);
+            let token = [0x55; 20];
+            let remote_contract = [0x66; 20];
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake(token, 100, 10, StakeParams::default()),
+                Err(Error::NoRoute)
+            );
+            assert_eq!(contract.set_route(token, 97, remote_contract), Ok(()));
+            assert_eq!(
+                contract.stake(token, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+
+            let calls = calls.borrow();
+            assert_eq!(calls[0].0, token.to_vec());
+            assert_eq!(calls[1].0, token.to_vec());
+            assert_eq!(
+                calls[2].1,
+                QpStaking::qp_encode(
+                    QP_SELECTOR,
+                    10.into(),
+                    97,
+                    remote_contract,
+                    QpStaking::h160(&test::default_accounts::<Env>().alice),
+                    token,
+                    &STAKE_REMOTE_METHOD,
+                )
+            );
+        }
+
+        #[ink::test]
+        fn stake_for_encodes_the_beneficiary() {
+            let (mut contract, calls) = setup(None);
+            let caller = test::default_accounts::<Env>().alice;
+            let beneficiary = [0x77; 20];
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(contract.stake_for(beneficiary, BASE_TOKEN, 100, 10), Ok(()));
+
+            assert_eq!(
+                calls.borrow()[2].1,
+                QpStaking::qp_encode(
+                    QP_SELECTOR,
+                    10.into(),
+                    MASTER_CHAIN_ID,
+                    MASTER_CONTRACT,
+                    beneficiary.into(),
+                    BASE_TOKEN,
+                    &STAKE_REMOTE_METHOD,
+                )
+            );
+            assert_eq!(contract.refund_of(caller), 0);
+        }
+
+        #[ink::test]
+        fn stake_enforces_the_stake_limits() {
+            let (mut contract, calls) = setup(None);
+            assert_eq!(contract.set_stake_limits(50, Some(200)), Ok(()));
+
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 40, 10, StakeParams::default()),
+                Err(Error::BelowMinimum)
+            );
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 300, 10, StakeParams::default()),
+                Err(Error::AboveMaximum)
+            );
+            assert!(calls.borrow().is_empty());
+        }
+
+        #[ink::test]
+        fn stake_enforces_the_account_cap() {
+            let (mut contract, _) = setup(None);
+            let beneficiary = QpStaking::h160(&test::default_accounts::<Env>().alice).0;
+            assert_eq!(contract.set_account_cap(Some(150)), Ok(()));
+
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+            assert_eq!(contract.staked_of(beneficiary), 100);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Err(Error::CapExceeded)
+            );
+            assert_eq!(contract.staked_of(beneficiary), 100);
+        }
+
+        #[ink::test]
+        fn stake_to_uses_the_allowed_target() {
+            let (mut contract, calls) = setup(None);
+            let master_contract = [0x66; 20];
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(
+                contract.stake_to(97, BASE_TOKEN, 100, 10),
+                Err(Error::UnknownTarget)
+            );
+            assert_eq!(contract.set_target(97, master_contract), Ok(()));
+            assert_eq!(contract.stake_to(97, BASE_TOKEN, 100, 10), Ok(()));
+
+            assert_eq!(
+                calls.borrow()[2].1,
+                QpStaking::qp_encode(
+                    QP_SELECTOR,
+                    10.into(),
+                    97,
+                    master_contract,
+                    QpStaking::h160(&test::default_accounts::<Env>().alice),
+                    BASE_TOKEN,
+                    &STAKE_REMOTE_METHOD,
+                )
+            );
+
+            assert_eq!(contract.remove_target(97), Ok(()));
+            assert_eq!(
+                contract.stake_to(97, BASE_TOKEN, 100, 10),
+                Err(Error::UnknownTarget)
+            );
+        }
+
+        #[ink::test]
+        fn stake_erc20_to_requires_the_fee_as_value() {
+            let (mut contract, calls) = setup(None);
+            assert_eq!(contract.set_target(97, [0x66; 20]), Ok(()));
+            test::set_value_transferred::<Env>(0);
+
+            assert_eq!(
+                transact(&calls, || contract.stake_erc20_to(97, BASE_TOKEN, 100, 10)),
+                Err(Error::InvalidTransferredValue)
+            );
+            assert!(calls.borrow().is_empty());
+
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(contract.stake_erc20_to(97, BASE_TOKEN, 100, 10), Ok(()));
+        }
+
+        #[ink::test]
+        fn stake_locked_encodes_the_lock_period() {
+            let (mut contract, calls) = setup(None);
+            let caller = test::default_accounts::<Env>().alice;
+            let beneficiary = QpStaking::h160(&caller);
+            test::set_value_transferred::<Env>(10);
+
+            assert_eq!(contract.stake_locked(BASE_TOKEN, 100, 10, 3600), Ok(()));
+
+            let mut method = STAKE_REMOTE_LOCKED_METHOD.to_vec();
+            method.extend(ethabi::encode(&[Token::Uint(3600.into())]));
+            assert_eq!(
+                calls.borrow()[2].1,
+                QpStaking::qp_encode(
+                    QP_SELECTOR,
+                    10.into(),
+                    MASTER_CHAIN_ID,
+                    MASTER_CONTRACT,
+                    beneficiary,
+                    BASE_TOKEN,
+                    &method,
+                )
+            );
+            let position = contract.position_of(beneficiary.0, 0).unwrap();
+            assert_eq!(position.amount, 100);
+            assert_eq!(
+                position.unlock_at,
+                ink::env::block_timestamp::<Env>() + 3_600_000
+            );
+        }
+
+        #[ink::test]
+        fn unstake_requires_an_early_exit_while_locked() {
+            let (mut contract, calls) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            let beneficiary = QpStaking::h160(&accounts.alice);
+            contract.set_relayer(accounts.bob, true).unwrap();
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(contract.stake_locked(BASE_TOKEN, 100, 10, 3600), Ok(()));
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(0, true), Ok(()));
+            test::set_caller::<Env>(accounts.alice);
+
+            assert_eq!(contract.unstake(0, false, 10), Err(Error::StillLocked));
+            assert_eq!(contract.unstake(0, true, 10), Ok(()));
+
+            let mut method = UNSTAKE_REMOTE_METHOD.to_vec();
+            method.extend(ethabi::encode(&[
+                Token::Uint(100.into()),
+                Token::Bool(true),
+            ]));
+            assert_eq!(
+                calls.borrow()[3].1,
+                QpStaking::qp_encode(
+                    QP_SELECTOR,
+                    10.into(),
+                    MASTER_CHAIN_ID,
+                    MASTER_CONTRACT,
+                    beneficiary,
+                    BASE_TOKEN,
+                    &method,
+                )
+            );
+            assert_eq!(contract.position_of(beneficiary.0, 0), None);
+            assert_eq!(contract.staked_of(beneficiary.0), 0);
+            assert_eq!(contract.unstake(0, true, 10), Err(Error::NoPosition));
+        }
+
+        #[ink::test]
+        fn unstake_rejects_a_pending_stake() {
+            let (mut contract, calls) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            let beneficiary = QpStaking::h160(&accounts.alice).0;
+            contract.set_relayer(accounts.bob, true).unwrap();
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+
+            assert_eq!(
+                transact(&calls, || contract.unstake(0, false, 10)),
+                Err(Error::StakePending)
+            );
+            assert_eq!(calls.borrow().len(), 3);
+            assert!(contract.position_of(beneficiary, 0).is_some());
+            assert_eq!(contract.staked_of(beneficiary), 100);
+
+            // the outcome is still reported once the master chain handles the stake
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(0, false), Ok(()));
+        }
+
+        #[ink::test]
+        fn unstake_requires_the_fee_as_value() {
+            let (mut contract, calls) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            contract.set_relayer(accounts.bob, true).unwrap();
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(0, true), Ok(()));
+
+            test::set_caller::<Env>(accounts.alice);
+            test::set_value_transferred::<Env>(0);
+            assert_eq!(
+                transact(&calls, || contract.unstake(0, false, 10)),
+                Err(Error::InvalidTransferredValue)
+            );
+            assert_eq!(calls.borrow().len(), 3);
+        }
+
+        #[ink::test]
+        fn finalize_stake_records_the_outcome() {
+            let (mut contract, _) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            let beneficiary = QpStaking::h160(&accounts.alice).0;
+            contract.set_relayer(accounts.bob, true).unwrap();
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+
+            assert_eq!(contract.finalize_stake(0, true), Err(Error::NotRelayer));
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(0, true), Ok(()));
+            assert_eq!(
+                contract.finalize_stake(0, false),
+                Err(Error::AlreadyFinalized)
+            );
+            assert_eq!(contract.finalize_stake(1, false), Ok(()));
+            assert_eq!(contract.finalize_stake(2, true), Err(Error::UnknownRequest));
+
+            assert_eq!(
+                contract.position_status_of(beneficiary, 0),
+                PositionStatus::Confirmed
+            );
+            assert_eq!(
+                contract.position_status_of(beneficiary, 1),
+                PositionStatus::Rejected
+            );
+            assert_eq!(contract.staked_of(beneficiary), 100);
+            // the fee was forwarded to QP in full and the amount is not returned yet
+            assert_eq!(contract.token_refund_of(accounts.alice, BASE_TOKEN), 0);
+            assert_eq!(contract.refund_of(accounts.alice), 0);
+
+            assert_eq!(contract.confirm_stake_returned(0), Err(Error::NotRejected));
+            assert_eq!(contract.confirm_stake_returned(1), Ok(()));
+            assert_eq!(contract.token_refund_of(accounts.alice, BASE_TOKEN), 100);
+            assert_eq!(
+                contract.confirm_stake_returned(1),
+                Err(Error::AlreadyReturned)
+            );
+
+            test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.confirm_stake_returned(1), Err(Error::NotRelayer));
+            assert_eq!(contract.unstake(1, false, 10), Err(Error::StakeRejected));
+        }
+
+        #[ink::test]
+        fn finalize_stake_refunds_the_fee_still_held() {
+            let (mut contract, _) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            contract.set_relayer(accounts.django, true).unwrap();
+            contract.set_protocol_fee(1000).unwrap();
+            contract.set_referral_share(5000).unwrap();
+            test::set_value_transferred::<Env>(100);
+            let params = StakeParams {
+                referrer: Some(accounts.bob),
+                ..Default::default()
+            };
+            for _ in 0..2 {
+                assert_eq!(contract.stake(BASE_TOKEN, 100, 100, params.clone()), Ok(()));
+            }
+            assert_eq!(contract.referral_earnings(accounts.bob), 10);
+            assert_eq!(contract.protocol_fees(), 10);
+
+            test::set_caller::<Env>(accounts.django);
+            assert_eq!(contract.finalize_stake(0, false), Ok(()));
+            // the retained fee and the referral credit, not the 90 forwarded to QP
+            assert_eq!(contract.refund_of(accounts.alice), 10);
+            assert_eq!(contract.referral_earnings(accounts.bob), 5);
+            assert_eq!(contract.protocol_fees(), 5);
+
+            test::set_caller::<Env>(accounts.alice);
+            test::set_account_balance::<Env>(test::callee::<Env>(), 100);
+            assert_eq!(contract.pay_referral_earnings(accounts.bob), Ok(()));
+            test::set_caller::<Env>(accounts.django);
+            assert_eq!(contract.finalize_stake(1, false), Ok(()));
+            // the referral credit was paid out already
+            assert_eq!(contract.refund_of(accounts.alice), 15);
+            assert_eq!(contract.protocol_fees(), 0);
+            assert_eq!(contract.token_refund_of(accounts.alice, BASE_TOKEN), 0);
+        }
+
+        #[ink::test]
+        fn claim_token_refund_transfers_the_rejected_amount() {
+            let (mut contract, calls) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            contract.set_relayer(accounts.bob, true).unwrap();
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(0, false), Ok(()));
+            assert_eq!(contract.confirm_stake_returned(0), Ok(()));
+
+            test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.claim_token_refund(BASE_TOKEN), Ok(()));
+            assert_eq!(
+                calls.borrow().last().unwrap(),
+                &(
+                    BASE_TOKEN.to_vec(),
+                    QpStaking::transfer_encode(
+                        QpStaking::h160(&accounts.alice),
+                        100.into()
+                    )
+                )
+            );
+            assert_eq!(contract.token_refund_of(accounts.alice, BASE_TOKEN), 0);
+            assert_eq!(
+                contract.claim_token_refund(BASE_TOKEN),
+                Err(Error::NoRefund)
+            );
+        }
+
+        #[ink::test]
+        fn set_route_requires_owner() {
+            let (mut contract, _) = setup(None);
+            test::set_caller::<Env>(test::default_accounts::<Env>().bob);
+
+            assert_eq!(
+                contract.set_route([0x55; 20], 97, [0x66; 20]),
+                Err(Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn estimate_total_cost_covers_the_stake() {
+            let (mut contract, _) = setup(None);
+            contract.set_min_fee(MASTER_CHAIN_ID, 100).unwrap();
+            contract.set_protocol_fee(1000).unwrap();
+
+            let estimate = contract.estimate_total_cost(BASE_TOKEN, 1000).unwrap();
+            assert_eq!(
+                estimate,
+                CostEstimate {
+                    amount: 1000,
+                    qp_fee: 101,
+                    protocol_fee: 11,
+                    total: 112,
+                }
+            );
+
+            test::set_value_transferred::<Env>(estimate.total);
+            assert_eq!(
+                contract.stake(
+                    BASE_TOKEN,
+                    1000,
+                    estimate.qp_fee + estimate.protocol_fee,
+                    StakeParams::default()
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.estimate_total_cost([0x55; 20], 1000),
+                Err(Error::NoRoute)
+            );
+        }
+
+        #[ink::test]
+        fn upgrade_requires_owner() {
+            let (mut contract, _) = setup(None);
+            test::set_caller::<Env>(test::default_accounts::<Env>().bob);
+
+            assert_eq!(contract.upgrade([0x77; 32]), Err(Error::NotOwner));
+            assert_eq!(contract.migrate(), Err(Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn import_positions_registers_the_exported_ledger() {
+            let (mut contract, _) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            let beneficiary = QpStaking::h160(&accounts.alice).0;
+            contract.set_relayer(accounts.bob, true).unwrap();
+            contract.set_protocol_fee(1000).unwrap();
+            test::set_value_transferred::<Env>(10);
+            for _ in 0..2 {
+                assert_eq!(
+                    contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                    Ok(())
+                );
+            }
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(1, false), Ok(()));
+            assert_eq!(
+                contract.migrate_positions(
+                    accounts.django,
+                    vec![beneficiary],
+                    vec![accounts.alice]
+                ),
+                Err(Error::NotOwner)
+            );
+            let ledger = contract.position_ledger(beneficiary);
+            assert_eq!(ledger.staked, 100);
+            assert_eq!(ledger.positions.len(), 2);
+            let receipt = StakeReceipt {
+                payer: accounts.alice,
+                fee_token: NATIVE_FEE_TOKEN,
+                fee: 10,
+                retained: 1,
+                referrer: None,
+                referral: 0,
+            };
+            // the rejected stake is moved while its amount is not returned
+            assert_eq!(
+                ledger.requests,
+                vec![
+                    (0, 0, Some(receipt.clone())),
+                    (
+                        1,
+                        1,
+                        Some(StakeReceipt {
+                            retained: 0,
+                            ..receipt
+                        })
+                    )
+                ]
+            );
+            let refunds = contract.refund_ledger(accounts.alice);
+            assert_eq!(
+                refunds,
+                RefundLedger {
+                    account: accounts.alice,
+                    refund: 1,
+                    token_refunds: vec![],
+                }
+            );
+
+            test::set_callee::<Env>(accounts.django);
+            test::set_value_transferred::<Env>(0);
+            let mut new_contract =
+                QpStaking::new(QP_CONTRACT, MASTER_CHAIN_ID, MASTER_CONTRACT, BASE_TOKEN);
+            assert_eq!(
+                new_contract
+                    .import_positions(vec![ledger.clone()], vec![refunds.clone()]),
+                Err(Error::NotMigrationSource)
+            );
+            assert_eq!(new_contract.set_migration_source(accounts.bob), Ok(()));
+            assert_eq!(
+                new_contract
+                    .import_positions(vec![ledger.clone()], vec![refunds.clone()]),
+                Err(Error::InvalidTransferredValue)
+            );
+            test::set_value_transferred::<Env>(1);
+            assert_eq!(
+                new_contract
+                    .import_positions(vec![ledger.clone()], vec![refunds.clone()]),
+                Ok(())
+            );
+            assert_eq!(new_contract.position_ledger(beneficiary), ledger);
+            assert_eq!(new_contract.refund_ledger(accounts.alice), refunds);
+            assert_eq!(
+                new_contract.import_positions(vec![ledger], vec![]),
+                Err(Error::PositionsExist)
+            );
+
+            assert_eq!(new_contract.set_relayer(accounts.bob, true), Ok(()));
+            assert_eq!(new_contract.confirm_stake_returned(1), Ok(()));
+            assert_eq!(new_contract.finalize_stake(0, false), Ok(()));
+            assert_eq!(new_contract.confirm_stake_returned(0), Ok(()));
+            assert_eq!(
+                new_contract.token_refund_of(accounts.alice, BASE_TOKEN),
+                200
+            );
+            // the retained fee of request 0 stayed with the protocol fees of the source
+            assert_eq!(new_contract.refund_of(accounts.alice), 1);
+        }
+
+        #[ink::test]
+        fn migrate_from_version_0_keeps_the_stored_data() {
+            let (mut contract, _) = setup(None);
+            let accounts = test::default_accounts::<Env>();
+            let beneficiary = QpStaking::h160(&accounts.alice).0;
+            contract.set_relayer(accounts.bob, true).unwrap();
+            test::set_value_transferred::<Env>(10);
+            assert_eq!(
+                contract.stake(BASE_TOKEN, 100, 10, StakeParams::default()),
+                Ok(())
+            );
+            let ledger = contract.position_ledger(beneficiary);
+            contract.storage_version = 0;
+
+            assert_eq!(contract.migrate(), Ok(()));
+            assert_eq!(contract.storage_version(), STORAGE_VERSION);
+            assert_eq!(contract.position_ledger(beneficiary), ledger);
+            assert!(contract.is_relayer(accounts.bob));
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.finalize_stake(0, true), Ok(()));
+        }
+
+        #[ink::test]
+        fn migrate_is_rejected_at_the_current_version() {
+            let (mut contract, _) = setup(None);
+
+            assert_eq!(contract.storage_version(), STORAGE_VERSION);
+            assert_eq!(contract.migrate(), Err(Error::AlreadyMigrated));
+        }
+
+        #[ink::test]
+        fn run_remote_appends_the_encoded_args() {
+            let (mut contract, calls) = setup(None);
+            let remote_contract = [0x66; 20];
+            let selector = [0xde, 0xad, 0xbe, 0xef];
+            let args = ethabi::encode(&[Token::Uint(42.into())]);
+
+            assert_eq!(
+                contract.run_remote(97, remote_contract, selector, args.clone(), 10),
+                Ok(())
+            );
+
+            let mut method = selector.to_vec();
+            method.extend(args);
+            assert_eq!(
+                calls.borrow()[0].1,
+                QpStaking::qp_encode(
+                    QP_SELECTOR,
+                    10.into(),
+                    97,
+                    remote_contract,
+                    QpStaking::h160(&test::default_accounts::<Env>().alice),
+                    BASE_TOKEN,
+                    &method,
+                )
+            );
+        }
+
+        #[ink::test]
+        fn run_remote_requires_owner() {
+            let (mut contract, calls) = setup(None);
+            test::set_caller::<Env>(test::default_accounts::<Env>().bob);
+
+            assert_eq!(
+                contract.run_remote(
+                    97,
+                    [0x66; 20],
+                    [0xde, 0xad, 0xbe, 0xef],
+                    Vec::new(),
+                    10
+                ),
+                Err(Error::NotOwner)
+            );
+            assert!(calls.borrow().is_empty());
+        }
+
+        #[test]
+        fn run_with_value_calldata_matches_golden_vector() {
+            let encoded = QpStaking::qp_encode(
+                QP_SELECTOR,
+                1.into(),
+                MASTER_CHAIN_ID,
+                MASTER_CONTRACT,
+                [0x22; 20].into(),
+                BASE_TOKEN,
+                &STAKE_REMOTE_METHOD,
+            );
+
+            let expected = hex!(
+                "
+                c154c628
+                0000000000000000000000000000000000000000000000000000000000000001
+                00000000000000000000000000000000000000000000000000000000000065f4
+                0000000000000000000000001111111111111111111111111111111111111111
+                0000000000000000000000002222222222222222222222222222222222222222
+                0000000000000000000000003333333333333333333333333333333333333333
+                00000000000000000000000000000000000000000000000000000000000000c0
+                0000000000000000000000000000000000000000000000000000000000000004
+                3183e73000000000000000000000000000000000000000000000000000000000
+            "
+            );
+            assert_eq!(encoded, expected.to_vec());
+        }
+
+        #[test]
+        fn transfer_calldata_matches_golden_vector() {
+            let encoded = QpStaking::transfer_encode(QP_CONTRACT.into(), 100.into());
+
+            let expected = hex!(
+                "
+                a9059cbb
+                0000000000000000000000004444444444444444444444444444444444444444
+                0000000000000000000000000000000000000000000000000000000000000064
+            "
+            );
+            assert_eq!(encoded, expected.to_vec());
         }
     }
-}
\ No newline at end of file
+}
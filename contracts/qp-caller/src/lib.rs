@@ -0,0 +1,406 @@
+//! Quantum Portal interface for ink! contracts, with an implementation calling the QP EVM
+//! contract through the XVM interface.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use ink::prelude::vec::Vec;
+
+pub use self::qp_caller::{
+    XvmQuantumPortal,
+    XvmQuantumPortalRef,
+};
+
+/// EVM ID (from astar runtime), used until the owner sets another with `set_vm_id`
+const EVM_ID: u8 = 0x0F;
+
+/// Error of a call to Quantum Portal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum QpError {
+    /// The caller is not allowed to run remote calls through the implementation
+    NotOwner,
+    /// The call to the QP contract reverted
+    RemoteExecutionFailed,
+}
+
+/// Runs calls on contracts of remote chains through Quantum Portal.
+#[ink::trait_definition]
+pub trait QuantumPortalCaller {
+    /// Run `method` on `remote_contract` of `remote_chain`, with the `token` transferred to
+    /// the QP contract beforehand credited to `beneficiary` on the remote chain. `fee` is
+    /// passed to the QP contract.
+    #[ink(message)]
+    fn run_with_value(
+        &mut self,
+        fee: u128,
+        remote_chain: u64,
+        remote_contract: [u8; 20],
+        beneficiary: [u8; 20],
+        token: [u8; 20],
+        method: Vec<u8>,
+    ) -> Result<(), QpError>;
+
+    /// Run `method` on `remote_contract` of `remote_chain` on behalf of `beneficiary`, without
+    /// value. `fee` is passed to the QP contract.
+    #[ink(message)]
+    fn run(
+        &mut self,
+        fee: u128,
+        remote_chain: u64,
+        remote_contract: [u8; 20],
+        beneficiary: [u8; 20],
+        method: Vec<u8>,
+    ) -> Result<(), QpError>;
+
+    /// Returns the address the QP fees are paid to.
+    #[ink(message)]
+    fn fee_target(&self) -> [u8; 20];
+}
+
+/// Selectors and ABI encoding of the QP contract methods, for contracts calling it through
+/// XVM themselves.
+pub mod encoding {
+    use ethabi::{
+        ethereum_types::{
+            H160,
+            U256,
+        },
+        Token,
+    };
+    use hex_literal::hex;
+    use ink::prelude::vec::Vec;
+
+    // Quantum portal Function signatures:
+    // function runWithValue(uint256 fee, uint64 remoteChain, address remoteContract, address beneficiary, address token, bytes memory method) external;
+    // function run(uint256 fee, uint64 remoteChain, address remoteContract, address beneficiary, bytes memory method) external;
+    // c154c628: runWithValue(uint256,uint64,address,address,address,bytes)
+    // 38d6ddff: run(uint256,uint64,address,address,bytes)
+    // cd3c695a: feeTarget()
+    pub const RUN_WITH_VALUE_SELECTOR: [u8; 4] = hex!["c154c628"];
+    pub const RUN_SELECTOR: [u8; 4] = hex!["38d6ddff"];
+    pub const FEE_TARGET_SELECTOR: [u8; 4] = hex!["cd3c695a"];
+    /// Argument types of `runWithValue`
+    pub const RUN_WITH_VALUE_ARGS: &str =
+        "(uint256,uint64,address,address,address,bytes)";
+
+    /// Selector of the QP function `name` taking the arguments of `runWithValue`, for QP
+    /// contracts exposing it under another name
+    pub fn run_with_value_selector(name: &str) -> [u8; 4] {
+        let mut signature = name.as_bytes().to_vec();
+        signature.extend_from_slice(RUN_WITH_VALUE_ARGS.as_bytes());
+        let mut hash = [0; 32];
+        ink::env::hash_bytes::<ink::env::hash::Keccak256>(&signature, &mut hash);
+        let mut selector = [0; 4];
+        selector.copy_from_slice(&hash[..4]);
+        selector
+    }
+
+    /// Calldata of `runWithValue`
+    pub fn run_with_value_encode(
+        fee: U256,
+        remote_chain: U256,
+        remote_contract: H160,
+        beneficiary: H160,
+        token: H160,
+        method: &[u8],
+    ) -> Vec<u8> {
+        run_with_value_encode_as(
+            RUN_WITH_VALUE_SELECTOR,
+            fee,
+            remote_chain,
+            remote_contract,
+            beneficiary,
+            token,
+            method,
+        )
+    }
+
+    /// Calldata of the QP function with `selector` taking the arguments of `runWithValue`
+    pub fn run_with_value_encode_as(
+        selector: [u8; 4],
+        fee: U256,
+        remote_chain: U256,
+        remote_contract: H160,
+        beneficiary: H160,
+        token: H160,
+        method: &[u8],
+    ) -> Vec<u8> {
+        let mut encoded = selector.to_vec();
+        let input = [
+            Token::Uint(fee),
+            Token::Uint(remote_chain),
+            Token::Address(remote_contract),
+            Token::Address(beneficiary),
+            Token::Address(token),
+            Token::Bytes(method.to_vec()),
+        ];
+        encoded.extend(&ethabi::encode(&input));
+        encoded
+    }
+
+    /// Calldata of `run`
+    pub fn run_encode(
+        fee: U256,
+        remote_chain: U256,
+        remote_contract: H160,
+        beneficiary: H160,
+        method: &[u8],
+    ) -> Vec<u8> {
+        let mut encoded = RUN_SELECTOR.to_vec();
+        let input = [
+            Token::Uint(fee),
+            Token::Uint(remote_chain),
+            Token::Address(remote_contract),
+            Token::Address(beneficiary),
+            Token::Bytes(method.to_vec()),
+        ];
+        encoded.extend(&ethabi::encode(&input));
+        encoded
+    }
+}
+
+/// `QuantumPortalCaller` calling the QP EVM contract through XVM.
+///
+/// The remote contracts see this contract as the sender of the calls, so an integrating
+/// contract instantiates its own and is its owner, the only account allowed to run calls.
+#[ink::contract(env = xvm_environment::XvmDefaultEnvironment)]
+mod qp_caller {
+    use super::{
+        encoding,
+        QpError,
+        QuantumPortalCaller,
+    };
+    use ink::prelude::vec::Vec;
+
+    #[ink(storage)]
+    pub struct XvmQuantumPortal {
+        qp_contract_address: [u8; 20],
+        /// QP fee store, fetching it from the QP contract is not possible over XVM
+        fee_target: [u8; 20],
+        owner: AccountId,
+        /// XVM id of the VM running the QP contract
+        vm_id: u8,
+    }
+
+    impl XvmQuantumPortal {
+        /// Create a caller of the QP contract at `qp_contract_address`, owned by the
+        /// instantiating account.
+        #[ink(constructor)]
+        pub fn new(qp_contract_address: [u8; 20], fee_target: [u8; 20]) -> Self {
+            Self {
+                qp_contract_address,
+                fee_target,
+                owner: Self::env().caller(),
+                vm_id: super::EVM_ID,
+            }
+        }
+
+        /// Set the XVM id of the VM running the QP contract, for runtimes assigning the EVM
+        /// another id.
+        #[ink(message)]
+        pub fn set_vm_id(&mut self, vm_id: u8) -> Result<(), QpError> {
+            self.ensure_owner()?;
+            self.vm_id = vm_id;
+            Ok(())
+        }
+
+        /// Returns the XVM id of the VM running the QP contract.
+        #[ink(message)]
+        pub fn vm_id(&self) -> u8 {
+            self.vm_id
+        }
+
+        fn ensure_owner(&self) -> Result<(), QpError> {
+            if self.env().caller() != self.owner {
+                return Err(QpError::NotOwner)
+            }
+            Ok(())
+        }
+
+        fn call_qp(&self, encoded_input: Vec<u8>) -> Result<(), QpError> {
+            self.env()
+                .extension()
+                .xvm_call(
+                    self.vm_id,
+                    Vec::from(self.qp_contract_address.as_ref()),
+                    encoded_input,
+                )
+                .map_err(|_| QpError::RemoteExecutionFailed)
+        }
+    }
+
+    impl QuantumPortalCaller for XvmQuantumPortal {
+        #[ink(message)]
+        fn run_with_value(
+            &mut self,
+            fee: u128,
+            remote_chain: u64,
+            remote_contract: [u8; 20],
+            beneficiary: [u8; 20],
+            token: [u8; 20],
+            method: Vec<u8>,
+        ) -> Result<(), QpError> {
+            self.ensure_owner()?;
+            self.call_qp(encoding::run_with_value_encode(
+                fee.into(),
+                remote_chain.into(),
+                remote_contract.into(),
+                beneficiary.into(),
+                token.into(),
+                &method,
+            ))
+        }
+
+        #[ink(message)]
+        fn run(
+            &mut self,
+            fee: u128,
+            remote_chain: u64,
+            remote_contract: [u8; 20],
+            beneficiary: [u8; 20],
+            method: Vec<u8>,
+        ) -> Result<(), QpError> {
+            self.ensure_owner()?;
+            self.call_qp(encoding::run_encode(
+                fee.into(),
+                remote_chain.into(),
+                remote_contract.into(),
+                beneficiary.into(),
+                &method,
+            ))
+        }
+
+        #[ink(message)]
+        fn fee_target(&self) -> [u8; 20] {
+            self.fee_target
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use hex_literal::hex;
+        use ink::env::test;
+        use scale::{
+            Decode,
+            Encode,
+        };
+        use std::{
+            cell::RefCell,
+            rc::Rc,
+        };
+
+        type Env = xvm_environment::XvmDefaultEnvironment;
+
+        /// Chain extension id of `xvm_call`
+        const XVM_CALL_FUNC_ID: u32 = 0x00010001;
+
+        const QP_CONTRACT: [u8; 20] = [0x44; 20];
+        const FEE_TARGET: [u8; 20] = [0x55; 20];
+
+        type XvmCalls = Rc<RefCell<Vec<(u8, Vec<u8>, Vec<u8>)>>>;
+
+        /// Records the `(vm_id, target, input)` of every `xvm_call`.
+        struct MockXvm {
+            calls: XvmCalls,
+        }
+
+        impl test::ChainExtension for MockXvm {
+            fn func_id(&self) -> u32 {
+                XVM_CALL_FUNC_ID
+            }
+
+            fn call(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> u32 {
+                let (vm_id, target, input) =
+                    <(u8, Vec<u8>, Vec<u8>)>::decode(&mut input).unwrap();
+                self.calls.borrow_mut().push((vm_id, target, input));
+                Ok::<(), ()>(()).encode_to(output);
+                0
+            }
+        }
+
+        fn setup() -> (XvmQuantumPortal, XvmCalls) {
+            let calls = XvmCalls::default();
+            test::register_chain_extension(MockXvm {
+                calls: calls.clone(),
+            });
+            let accounts = test::default_accounts::<Env>();
+            test::set_caller::<Env>(accounts.alice);
+            (XvmQuantumPortal::new(QP_CONTRACT, FEE_TARGET), calls)
+        }
+
+        #[ink::test]
+        fn run_calls_the_qp_contract() {
+            let (mut contract, calls) = setup();
+            let method = vec![0xde, 0xad, 0xbe, 0xef];
+
+            assert_eq!(
+                contract.run(1, 26100, [0x11; 20], [0x22; 20], method.clone()),
+                Ok(())
+            );
+
+            let expected = hex!(
+                "
+                38d6ddff
+                0000000000000000000000000000000000000000000000000000000000000001
+                00000000000000000000000000000000000000000000000000000000000065f4
+                0000000000000000000000001111111111111111111111111111111111111111
+                0000000000000000000000002222222222222222222222222222222222222222
+                00000000000000000000000000000000000000000000000000000000000000a0
+                0000000000000000000000000000000000000000000000000000000000000004
+                deadbeef00000000000000000000000000000000000000000000000000000000
+            "
+            );
+            assert_eq!(
+                *calls.borrow(),
+                vec![(crate::EVM_ID, QP_CONTRACT.to_vec(), expected.to_vec())]
+            );
+            assert_eq!(contract.fee_target(), FEE_TARGET);
+        }
+
+        #[ink::test]
+        fn run_with_value_selector_hashes_the_signature() {
+            assert_eq!(
+                encoding::run_with_value_selector("runWithValue"),
+                encoding::RUN_WITH_VALUE_SELECTOR
+            );
+        }
+
+        #[ink::test]
+        fn only_the_owner_runs_calls() {
+            let (mut contract, calls) = setup();
+            test::set_caller::<Env>(test::default_accounts::<Env>().bob);
+
+            assert_eq!(
+                contract.run_with_value(
+                    1,
+                    26100,
+                    [0x11; 20],
+                    [0x22; 20],
+                    [0x33; 20],
+                    Vec::new()
+                ),
+                Err(QpError::NotOwner)
+            );
+            assert!(calls.borrow().is_empty());
+        }
+
+        #[ink::test]
+        fn calls_go_to_the_vm_set_by_the_owner() {
+            let (mut contract, calls) = setup();
+            let accounts = test::default_accounts::<Env>();
+            assert_eq!(contract.vm_id(), crate::EVM_ID);
+
+            test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.set_vm_id(0x1F), Err(QpError::NotOwner));
+            test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.set_vm_id(0x1F), Ok(()));
+            assert_eq!(contract.vm_id(), 0x1F);
+
+            assert_eq!(
+                contract.run(1, 26100, [0x11; 20], [0x22; 20], Vec::new()),
+                Ok(())
+            );
+            assert_eq!(calls.borrow()[0].0, 0x1F);
+        }
+    }
+}
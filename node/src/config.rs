@@ -38,11 +38,18 @@ pub struct NetworkItem {
 pub struct NetworkConfig {
 	// The NetworkItem data structure
 	network_vec: Vec<NetworkItem>,
-	// The pair of ChainIds to mine
+	// The pair of ChainIds to mine, every pair of configured chains when empty
+	#[serde(default)]
 	pair_vec: Vec<(u64, u64)>,
 	// The public key for the signer account
 	#[serde(with = "serde_bytes")]
 	pub signer_public_key: Vec<u8>,
+	/// Additional public keys transactions are submitted from, in turns with the signer key
+	#[serde(default)]
+	pub relayer_public_keys: Vec<String>,
+	/// The pairs are relayed every `run_interval` blocks, every block when left out
+	#[serde(default)]
+	pub run_interval: u32,
 	/// The role of this node
 	#[serde(with = "serde_bytes")]
 	pub role: Vec<u8>,
@@ -62,7 +69,13 @@ pub fn convert(network_config: NetworkConfig) -> QpConfig {
 			.collect(),
 		pair_vec: network_config.pair_vec,
 		signer_public_key: network_config.signer_public_key,
+		relayer_public_keys: network_config
+			.relayer_public_keys
+			.into_iter()
+			.map(String::into_bytes)
+			.collect(),
 		role: role_as_bytes.into(),
+		run_interval: network_config.run_interval,
 	}
 }
 
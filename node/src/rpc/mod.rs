@@ -19,15 +19,16 @@ use sp_api::{CallApiAt, ProvideRuntimeApi};
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
 use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use sp_inherents::CreateInherentDataProviders;
-use sp_runtime::traits::Block as BlockT;
+use sp_runtime::{offchain::OffchainStorage, traits::Block as BlockT};
 // Runtime
 use crate::primitives::{AccountId, Balance, Block, Hash, Nonce};
 
 mod eth;
+mod quantum_portal;
 pub use self::eth::{create_eth, EthDeps};
 
 /// Full client dependencies.
-pub struct FullDeps<C, P, A: ChainApi, CT, CIDP> {
+pub struct FullDeps<C, P, A: ChainApi, CT, CIDP, S> {
 	/// The client instance to use.
 	pub client: Arc<C>,
 	/// Transaction pool instance.
@@ -36,6 +37,8 @@ pub struct FullDeps<C, P, A: ChainApi, CT, CIDP> {
 	pub deny_unsafe: DenyUnsafe,
 	/// Manual seal command sink
 	pub command_sink: Option<mpsc::Sender<EngineCommand<Hash>>>,
+	/// Offchain storage the quantum portal relayer state is read from
+	pub offchain_storage: Option<S>,
 	/// Ethereum-compatibility specific dependencies.
 	pub eth: EthDeps<Block, C, P, A, CT, CIDP>,
 }
@@ -53,8 +56,8 @@ where
 }
 
 /// Instantiate all Full RPC extensions.
-pub fn create_full<C, P, BE, A, CT, CIDP>(
-	deps: FullDeps<C, P, A, CT, CIDP>,
+pub fn create_full<C, P, BE, A, CT, CIDP, S>(
+	deps: FullDeps<C, P, A, CT, CIDP, S>,
 	subscription_task_executor: SubscriptionTaskExecutor,
 	pubsub_notification_sinks: Arc<
 		fc_mapping_sync::EthereumBlockNotificationSinks<
@@ -77,16 +80,21 @@ where
 	A: ChainApi<Block = Block> + 'static,
 	CIDP: CreateInherentDataProviders<Block, ()> + Send + 'static,
 	CT: fp_rpc::ConvertTransaction<<Block as BlockT>::Extrinsic> + Send + Sync + 'static,
+	S: OffchainStorage + 'static,
 {
 	use pallet_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApiServer};
+	use quantum_portal::{QuantumPortal, QuantumPortalApiServer};
 	use sc_consensus_manual_seal::rpc::{ManualSeal, ManualSealApiServer};
 	use substrate_frame_rpc_system::{System, SystemApiServer};
 
 	let mut io = RpcModule::new(());
-	let FullDeps { client, pool, deny_unsafe, command_sink, eth } = deps;
+	let FullDeps { client, pool, deny_unsafe, command_sink, offchain_storage, eth } = deps;
 
 	io.merge(System::new(client.clone(), pool, deny_unsafe).into_rpc())?;
 	io.merge(TransactionPayment::new(client).into_rpc())?;
+	if let Some(offchain_storage) = offchain_storage {
+		io.merge(QuantumPortal::new(offchain_storage, deny_unsafe).into_rpc())?;
+	}
 
 	// Ethereum compatibility RPCs
 	let io = create_eth::<_, _, _, _, _, _, _, DefaultEthConfig<C, BE>>(
@@ -0,0 +1,294 @@
+//! Quantum portal relayer state, read from the offchain storage of the node. Operator commands on
+//! stuck relayer transactions are queued there for the offchain worker.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use codec::{Decode, Encode};
+use ferrum_primitives::OFFCHAIN_SIGNER_CONFIG_KEY;
+use jsonrpsee::{core::RpcResult, proc_macros::rpc, types::ErrorObject};
+use pallet_quantum_portal::{
+	qp_types::{CatchUpProgress, QpConfig, CHAIN_PAIRS_KEY},
+	quantum_portal_service::{
+		ConfirmedTransaction, LowBalance, PairError, PendingTransaction, QuantumPortalService,
+		RelayerActivity, TxCommand, WorkItem, WorkKind, WorkQueue, WorkState,
+	},
+};
+use sc_rpc_api::DenyUnsafe;
+use serde::Serialize;
+use sp_core::{
+	offchain::{OffchainStorage, STORAGE_PREFIX},
+	H160, H256, U256,
+};
+
+use crate::primitives::BlockNumber;
+
+type Service = QuantumPortalService<ferrum_runtime::Runtime>;
+
+/// A relayer transaction waiting for confirmation
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransactionStatus {
+	pub kind: &'static str,
+	pub tx_hash: H256,
+	pub submitted_at: u64,
+	/// Milliseconds since the transaction was submitted
+	pub age: u64,
+}
+
+/// A confirmed relayer transaction and the block it was included in
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmedTransactionStatus {
+	pub tx_hash: H256,
+	pub block_number: u64,
+	pub block_hash: H256,
+}
+
+/// An item of the work queue of a pair, in the order they run
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkItemStatus {
+	pub kind: &'static str,
+	pub state: &'static str,
+	/// Transaction sent for the item while it is submitted
+	pub tx_hash: Option<H256>,
+	pub updated_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairErrorStatus {
+	pub error: String,
+	pub timestamp: u64,
+}
+
+/// Progress of the relayer catching up on a backlog of remote blocks
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatchUpStatus {
+	pub started_at: u64,
+	pub initial_backlog: u64,
+	pub backlog: u64,
+}
+
+/// A relayer key to top up, its balance is under the low balance threshold of the chain
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LowBalanceStatus {
+	pub address: H160,
+	pub balance: U256,
+	/// Time the balance was first seen under the threshold
+	pub since: u64,
+}
+
+/// Deadline of the processing lock of a pair, the lock expires once both are passed
+#[derive(Debug, Serialize, Decode)]
+#[serde(rename_all = "camelCase")]
+pub struct LockDeadline {
+	pub block_number: BlockNumber,
+	pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayerStatus {
+	pub remote_chain: u64,
+	pub local_chain: u64,
+	pub pending_transactions: Vec<PendingTransactionStatus>,
+	pub last_mine: Option<ConfirmedTransactionStatus>,
+	pub last_finalize: Option<ConfirmedTransactionStatus>,
+	pub last_error: Option<PairErrorStatus>,
+	pub work_queue: Vec<WorkItemStatus>,
+	/// Set while the relayer catches up on the blocks that piled up during a downtime
+	pub catch_up: Option<CatchUpStatus>,
+	/// Set while the pair is locked by the offchain worker
+	pub lock: Option<LockDeadline>,
+	/// Relayer keys of the local chain with a balance under the low balance threshold
+	pub low_balances: Vec<LowBalanceStatus>,
+}
+
+#[rpc(server, namespace = "qp")]
+pub trait QuantumPortalApi {
+	/// Relayer state of every chain pair configured on the node
+	#[method(name = "relayerStatus")]
+	fn relayer_status(&self) -> RpcResult<Vec<RelayerStatus>>;
+
+	/// Transactions of the relayer waiting for confirmation on `chain_id`
+	#[method(name = "pendingTransactions")]
+	fn pending_transactions(&self, chain_id: u64) -> RpcResult<Vec<PendingTransactionStatus>>;
+
+	/// Replace a stuck pending transaction with a zero value transfer at the same nonce, on the
+	/// next run of the offchain worker
+	#[method(name = "cancelTransaction")]
+	fn cancel_transaction(&self, chain_id: u64, tx_hash: H256) -> RpcResult<()>;
+
+	/// Stop tracking a pending transaction on the next run of the offchain worker, as if it timed
+	/// out
+	#[method(name = "expireTransaction")]
+	fn expire_transaction(&self, chain_id: u64, tx_hash: H256) -> RpcResult<()>;
+}
+
+/// Serves the relayer state kept by the quantum portal offchain worker.
+pub struct QuantumPortal<S> {
+	storage: S,
+	deny_unsafe: DenyUnsafe,
+}
+
+impl<S: OffchainStorage> QuantumPortal<S> {
+	pub fn new(storage: S, deny_unsafe: DenyUnsafe) -> Self {
+		Self { storage, deny_unsafe }
+	}
+
+	fn get<V: Decode>(&self, key: &[u8]) -> Option<V> {
+		self.storage
+			.get(STORAGE_PREFIX, key)
+			.and_then(|value| V::decode(&mut value.as_slice()).ok())
+	}
+
+	fn pending(&self, chain_id: u64, now: u64) -> Vec<PendingTransactionStatus> {
+		self.get::<PendingTransaction>(&Service::storage_key(chain_id))
+			.and_then(|tx| {
+				let (kind, submitted_at, tx_hash) = match tx {
+					PendingTransaction::MineTransaction(_, _, timestamp, tx_id) =>
+						("mine", timestamp, tx_id),
+					PendingTransaction::FinalizeTransaction(_, timestamp, tx_id) =>
+						("finalize", timestamp, tx_id),
+					PendingTransaction::RecoveredTransaction(_, timestamp, tx_id) =>
+						("recovered", timestamp, tx_id),
+					PendingTransaction::None => return None,
+				};
+				let age = now.saturating_sub(submitted_at);
+				Some(PendingTransactionStatus { kind, tx_hash, submitted_at, age })
+			})
+			.into_iter()
+			.collect()
+	}
+
+	/// Queue `command` for the offchain worker, the transaction has to be pending on `chain_id`
+	fn queue_command(&self, chain_id: u64, tx_hash: H256, command: TxCommand) -> RpcResult<()> {
+		self.deny_unsafe.check_if_safe()?;
+		if !self.pending(chain_id, 0).iter().any(|tx| tx.tx_hash == tx_hash) {
+			return Err(ErrorObject::owned(
+				1,
+				format!("Transaction {:?} is not pending on chain {}", tx_hash, chain_id),
+				None::<()>,
+			));
+		}
+		let key = Service::tx_commands_storage_key(chain_id);
+		let mut commands = self.get::<Vec<TxCommand>>(&key).unwrap_or_default();
+		if !commands.contains(&command) {
+			commands.push(command);
+		}
+		self.storage.clone().set(STORAGE_PREFIX, &key, &commands.encode());
+		Ok(())
+	}
+
+	fn pair_status(&self, remote_chain: u64, local_chain: u64, now: u64) -> RelayerStatus {
+		let pending_transactions = self.pending(local_chain, now);
+
+		let activity = self
+			.get::<RelayerActivity>(&Service::activity_storage_key(local_chain))
+			.unwrap_or_default();
+		let last_error = self
+			.get::<PairError>(&Service::pair_error_storage_key(remote_chain, local_chain))
+			.map(|e| PairErrorStatus { error: format!("{:?}", e.error), timestamp: e.timestamp });
+
+		let work_queue = self
+			.get::<WorkQueue>(&Service::work_queue_storage_key(remote_chain, local_chain))
+			.map(|queue| queue.items.into_iter().map(work_item_status).collect())
+			.unwrap_or_default();
+
+		RelayerStatus {
+			remote_chain,
+			local_chain,
+			pending_transactions,
+			last_mine: activity.last_mine.and_then(confirmed_status),
+			last_finalize: activity.last_finalize.and_then(confirmed_status),
+			last_error,
+			work_queue,
+			catch_up: self
+				.get::<CatchUpProgress>(&CatchUpProgress::storage_key(remote_chain, local_chain))
+				.map(|p| CatchUpStatus {
+					started_at: p.started_at,
+					initial_backlog: p.initial_backlog,
+					backlog: p.backlog,
+				}),
+			lock: self.get(&Service::pair_lock_storage_key(remote_chain, local_chain)),
+			low_balances: self
+				.get::<Vec<LowBalance>>(&Service::low_balances_storage_key(local_chain))
+				.unwrap_or_default()
+				.into_iter()
+				.map(|low| LowBalanceStatus {
+					address: low.address,
+					balance: low.balance,
+					since: low.since,
+				})
+				.collect(),
+		}
+	}
+}
+
+fn confirmed_status(confirmed: ConfirmedTransaction) -> Option<ConfirmedTransactionStatus> {
+	let tx_hash = match confirmed.tx {
+		PendingTransaction::MineTransaction(_, _, _, tx_id) |
+		PendingTransaction::FinalizeTransaction(_, _, tx_id) |
+		PendingTransaction::RecoveredTransaction(_, _, tx_id) => tx_id,
+		PendingTransaction::None => return None,
+	};
+	Some(ConfirmedTransactionStatus {
+		tx_hash,
+		block_number: confirmed.block_number,
+		block_hash: confirmed.block_hash,
+	})
+}
+
+fn work_item_status(item: WorkItem) -> WorkItemStatus {
+	let kind = match item.kind {
+		WorkKind::Finalize => "finalize",
+		WorkKind::Mine => "mine",
+	};
+	let (state, tx_hash) = match item.state {
+		WorkState::Queued => ("queued", None),
+		WorkState::Submitted(tx_hash) => ("submitted", Some(tx_hash)),
+		WorkState::Done => ("done", None),
+	};
+	WorkItemStatus { kind, state, tx_hash, updated_at: item.updated_at }
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_millis() as u64)
+		.unwrap_or_default()
+}
+
+impl<S: OffchainStorage + 'static> QuantumPortalApiServer for QuantumPortal<S> {
+	fn relayer_status(&self) -> RpcResult<Vec<RelayerStatus>> {
+		self.deny_unsafe.check_if_safe()?;
+		let now = now();
+		// pairs relayed on the last run, they include the chains configured on-chain since
+		let pairs = self
+			.get::<Vec<(u64, u64)>>(CHAIN_PAIRS_KEY)
+			.or_else(|| {
+				self.get::<QpConfig>(OFFCHAIN_SIGNER_CONFIG_KEY).map(|config| config.pair_vec)
+			})
+			.unwrap_or_default();
+		Ok(pairs
+			.into_iter()
+			.map(|(remote_chain, local_chain)| self.pair_status(remote_chain, local_chain, now))
+			.collect())
+	}
+
+	fn pending_transactions(&self, chain_id: u64) -> RpcResult<Vec<PendingTransactionStatus>> {
+		self.deny_unsafe.check_if_safe()?;
+		Ok(self.pending(chain_id, now()))
+	}
+
+	fn cancel_transaction(&self, chain_id: u64, tx_hash: H256) -> RpcResult<()> {
+		self.queue_command(chain_id, tx_hash, TxCommand::Cancel(tx_hash))
+	}
+
+	fn expire_transaction(&self, chain_id: u64, tx_hash: H256) -> RpcResult<()> {
+		self.queue_command(chain_id, tx_hash, TxCommand::Expire(tx_hash))
+	}
+}
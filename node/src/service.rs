@@ -396,6 +396,7 @@ pub async fn start_parachain_node(
 		let frontier_backend = frontier_backend.clone();
 		let pubsub_notification_sinks = pubsub_notification_sinks.clone();
 		let storage_override = storage_override.clone();
+		let offchain_storage = backend.offchain_storage();
 		let fee_history_cache = fee_history_cache.clone();
 		let block_data_cache = Arc::new(fc_rpc::EthBlockDataCacheTask::new(
 			task_manager.spawn_handle(),
@@ -448,6 +449,7 @@ pub async fn start_parachain_node(
 				pool: pool.clone(),
 				deny_unsafe,
 				command_sink: None,
+				offchain_storage: offchain_storage.clone(),
 				eth: eth_deps,
 			};
 			crate::rpc::create_full(
@@ -0,0 +1,72 @@
+// Copyright 2019-2024 Ferrum Inc.
+// This file is part of Ferrum.
+
+// Ferrum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Ferrum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bridge accounting. The value bridged into the custody pool of a remote chain, from the
+//! messages handled and the withdrawals recorded, is checked once per era against the pool
+//! balance the relayers observe on the chain.
+use crate::pallet::Config;
+use frame_support::pallet_prelude::*;
+use frame_system::pallet_prelude::BlockNumberFor;
+use sp_runtime::{
+	traits::{Bounded, Zero},
+	FixedPointNumber, FixedU128,
+};
+
+/// Solvency of a custody pool computed at the start of an era
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct SolvencyRecord {
+	pub era: u32,
+	/// Pool balance last reported by `FinalizerThreshold` relayers
+	pub reserves: u128,
+	/// Value bridged into the pool
+	pub liabilities: u128,
+	pub ratio: FixedU128,
+}
+
+/// Reserves over liabilities, a pool owing nothing is solvent whatever its balance
+pub fn solvency_ratio(reserves: u128, liabilities: u128) -> FixedU128 {
+	if liabilities.is_zero() {
+		return FixedU128::max_value();
+	}
+	FixedU128::checked_from_rational(reserves, liabilities).unwrap_or_else(FixedU128::max_value)
+}
+
+/// Era of `block_number`, eras are `AccountingPeriod` blocks long
+pub fn era<T: Config>(block_number: BlockNumberFor<T>) -> u32 {
+	let period = T::AccountingPeriod::get().max(1u32.into());
+	(block_number / period).try_into().unwrap_or(u32::MAX)
+}
+
+/// Whether `block_number` starts an era
+pub fn is_era_start<T: Config>(block_number: BlockNumberFor<T>) -> bool {
+	let period = T::AccountingPeriod::get().max(1u32.into());
+	(block_number % period).is_zero()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::solvency_ratio;
+	use sp_runtime::{traits::Bounded, FixedPointNumber, FixedU128};
+
+	#[test]
+	fn solvency_ratio_compares_reserves_with_liabilities() {
+		assert_eq!(solvency_ratio(150, 100), FixedU128::saturating_from_rational(3, 2));
+		assert_eq!(solvency_ratio(50, 100), FixedU128::saturating_from_rational(1, 2));
+		assert!(solvency_ratio(99, 100) < FixedU128::one());
+		// nothing bridged yet
+		assert_eq!(solvency_ratio(0, 0), FixedU128::max_value());
+	}
+}
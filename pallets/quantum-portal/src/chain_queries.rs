@@ -15,17 +15,26 @@
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use crate::chain_utils::{ChainRequestError, ChainRequestResult, ChainUtils, JsonSer, ToJson};
+use crate::{
+	chain_utils::{ChainRequestError, ChainRequestResult, ChainUtils, JsonSer, ToJson},
+	recording::{self, Recorder},
+};
 use ethereum::TransactionV2;
 use serde::{Deserialize, Deserializer, Serialize};
-use sp_core::H256;
+use sp_core::{H160, H256, U256};
 use sp_runtime::{
 	codec::{Decode, Encode},
-	offchain::{http, Duration},
+	offchain::{http, storage::StorageValueRef, Duration},
 };
 use sp_std::{prelude::*, str};
 
 const FETCH_TIMEOUT_PERIOD: u64 = 30000; // in milli-seconds
+pub const CONTRACT_CONFIG_CACHE_TTL: u64 = 3600 * 1000; // in milli-seconds
+
+// Times a read only query is sent again after a transport failure
+const MAX_RPC_RETRIES: u32 = 2;
+// Bytes of the response kept in the last failure record
+const MAX_FAILURE_PAYLOAD: usize = 2048;
 
 pub fn de_string_list_to_bytes_list<'de, D>(de: D) -> Result<Vec<Vec<u8>>, D::Error>
 where
@@ -74,7 +83,7 @@ pub struct CallResponse {
 pub enum TransactionStatus {
 	NotFound,
 	Pending,
-	Confirmed,
+	Confirmed { block_number: u64, block_hash: H256 },
 	Failed,
 }
 
@@ -139,7 +148,243 @@ impl ToJson for TransactionV2 {
 	}
 }
 
+/// Rpc calls made to an endpoint, kept in persistent offchain storage so the counters survive
+/// across offchain worker runs.
+#[derive(Debug, Default, Clone, PartialEq, Encode, Decode)]
+pub struct RpcBudget {
+	/// Block number of the offchain worker run the budget applies to
+	pub block_number: u64,
+	/// Maximum calls per run, unlimited when not set
+	pub limit: Option<u32>,
+	/// Calls made during the current run
+	pub used: u32,
+	/// Calls made since the counters were first written
+	pub total: u64,
+}
+
+impl RpcBudget {
+	/// Storage key of the budget of `url`, the url is hashed since it may carry credentials
+	pub fn storage_key(url: &str) -> Vec<u8> {
+		let url_hash = ChainUtils::keccack(url.as_bytes());
+		[b"quantum-portal::rpc_budget::".as_slice(), &ChainUtils::bytes_to_hex(url_hash.as_bytes())]
+			.concat()
+	}
+
+	pub fn get(url: &str) -> RpcBudget {
+		let key = Self::storage_key(url);
+		Recorder::track(&key);
+		StorageValueRef::persistent(key.as_slice())
+			.get()
+			.ok()
+			.flatten()
+			.unwrap_or_default()
+	}
+
+	fn set(&self, url: &str) {
+		let key = Self::storage_key(url);
+		StorageValueRef::persistent(key.as_slice()).set(self);
+	}
+
+	/// Start the budget of `url` for the offchain worker run at `block_number`
+	pub fn start(url: &str, block_number: u64, limit: Option<u32>) {
+		let mut budget = Self::get(url);
+		if budget.block_number != block_number {
+			budget.block_number = block_number;
+			budget.used = 0;
+		}
+		budget.limit = limit;
+		budget.set(url);
+	}
+
+	/// Calls left for the current run, `None` if unlimited
+	pub fn remaining(url: &str) -> Option<u32> {
+		let budget = Self::get(url);
+		budget.limit.map(|limit| limit.saturating_sub(budget.used))
+	}
+
+	/// Count a call to `url`, fails if the budget of the current run is used up
+	fn consume(url: &str) -> ChainRequestResult<()> {
+		let mut budget = Self::get(url);
+		if matches!(budget.limit, Some(limit) if budget.used >= limit) {
+			log::warn!("Rpc budget of {} calls exhausted for this run", budget.used);
+			return Err(ChainRequestError::RpcBudgetExhausted);
+		}
+		budget.used = budget.used.saturating_add(1);
+		budget.total = budget.total.saturating_add(1);
+		budget.set(url);
+		Ok(())
+	}
+}
+
+/// Last failed rpc call to an endpoint, kept in persistent offchain storage with the raw
+/// response so malformed answers can be looked into.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct RpcFailure {
+	pub method: Vec<u8>,
+	pub error: ChainRequestError,
+	/// Start of the response body, empty if no response was received
+	pub payload: Vec<u8>,
+	pub timestamp: u64,
+}
+
+impl RpcFailure {
+	/// Storage key of the last failure of `url`, the url is hashed since it may carry credentials
+	pub fn storage_key(url: &str) -> Vec<u8> {
+		let url_hash = ChainUtils::keccack(url.as_bytes());
+		[
+			b"quantum-portal::rpc_failure::".as_slice(),
+			&ChainUtils::bytes_to_hex(url_hash.as_bytes()),
+		]
+		.concat()
+	}
+
+	pub fn last(url: &str) -> Option<RpcFailure> {
+		let key = Self::storage_key(url);
+		StorageValueRef::persistent(key.as_slice()).get().ok().flatten()
+	}
+
+	fn record(url: &str, method: &[u8], error: &ChainRequestError, payload: &[u8]) {
+		let failure = RpcFailure {
+			method: method.to_vec(),
+			error: error.clone(),
+			payload: payload[..payload.len().min(MAX_FAILURE_PAYLOAD)].to_vec(),
+			timestamp: sp_io::offchain::timestamp().unix_millis(),
+		};
+		let key = Self::storage_key(url);
+		StorageValueRef::persistent(key.as_slice()).set(&failure);
+	}
+}
+
+#[derive(Debug, Encode, Decode)]
+struct CachedValue<V> {
+	value: V,
+	expires_at: u64,
+}
+
+/// Cache of immutable or slow changing rpc results in persistent offchain storage
+pub struct RpcCache;
+
+impl RpcCache {
+	/// Storage key of `item` fetched from `url`, the url is hashed since it may carry credentials
+	pub fn storage_key(url: &str, item: &[u8]) -> Vec<u8> {
+		let hash = ChainUtils::keccack(&[url.as_bytes(), b"::", item].concat());
+		[b"quantum-portal::cache::".as_slice(), &ChainUtils::bytes_to_hex(hash.as_bytes())].concat()
+	}
+
+	/// Returns the cached `item` of `url`, fetching and caching it for `ttl` milliseconds if it
+	/// is missing or expired
+	pub fn get_or_fetch<V, F>(url: &str, item: &[u8], ttl: u64, fetch: F) -> ChainRequestResult<V>
+	where
+		V: Encode + Decode,
+		F: FnOnce() -> ChainRequestResult<V>,
+	{
+		let key = Self::storage_key(url, item);
+		Recorder::track(&key);
+		let storage = StorageValueRef::persistent(key.as_slice());
+		let now = Recorder::now();
+		if let Ok(Some(cached)) = storage.get::<CachedValue<V>>() {
+			if cached.expires_at > now {
+				return Ok(cached.value);
+			}
+		}
+
+		let value = fetch()?;
+		let cached = CachedValue { value, expires_at: now.saturating_add(ttl) };
+		storage.set(&cached);
+		Ok(cached.value)
+	}
+}
+
+/// Sends the json-rpc requests of the relayer, replaced by canned responses in tests
+pub trait HttpTransport {
+	/// Post the json `body` to `url`, returns the status code and the body of the response
+	fn post(&self, url: &str, body: &[u8]) -> ChainRequestResult<(u16, Vec<u8>)>;
+}
+
+/// Transport through the offchain worker http api
+pub struct OffchainHttp;
+
+impl HttpTransport for OffchainHttp {
+	fn post(&self, url: &str, body: &[u8]) -> ChainRequestResult<(u16, Vec<u8>)> {
+		let request: http::Request<Vec<&[u8]>> = http::Request::post(url, Vec::from([body]));
+		let timeout = sp_io::offchain::timestamp().add(Duration::from_millis(FETCH_TIMEOUT_PERIOD));
+
+		let pending = request
+			// .deadline(timeout) // Setting the timeout time
+			.add_header("Content-Type", "application/json")
+			.send() // Sending the request out by the host
+			.map_err(|e| {
+				log::info!("An ERROR HAPPNED!");
+				// println!("ERRROOOORRRR {:?}", e);
+				log::error!("{:?}", e);
+				ChainRequestError::RpcTransportError
+			})?;
+
+		// By default, the http request is async from the runtime perspective. So we are asking the
+		//   runtime to wait here
+		// The returning value here is a `Result` of `Result`, so we are unwrapping it twice by two
+		// `?`   ref: https://docs.substrate.io/rustdocs/latest/sp_runtime/offchain/http/struct.PendingRequest.html#method.try_wait
+		let response_a = pending.try_wait(timeout);
+		// let response_0 = pending.wait();
+		let response_0 = match response_a {
+			Ok(r) => {
+				// println!("Result got");
+				// log::info!("Result got");
+				Ok(r)
+			},
+			Err(e) => {
+				// println!("ERRROOOORRRR AFDTER {:?}", e);
+				log::info!("An ERROR HAPPNED!");
+				log::info!("An ERROR HAPPNED UYPOOOOOOOOOOO ! {:?}", e);
+				Err(ChainRequestError::Timeout)
+			},
+		}?;
+		let response = match response_0 {
+			Ok(r) => {
+				// log::info!("Result got 2");
+				Ok(r)
+			},
+			Err(e) => {
+				log::info!("An ERROR HAPPNED 2!");
+				log::info!("An ERROR HAPPNED UYPOOOOOOOOOOO 2 ! {:?}", e);
+				match e {
+					http::Error::DeadlineReached => Err(ChainRequestError::Timeout),
+					_ => Err(ChainRequestError::RpcTransportError),
+				}
+			},
+		}?;
+		// let response = pending
+		// 	.try_wait(timeout)
+		// 	.map_err(|e| {
+		// 		log::info!("An ERROR HAPPNED!");
+		// 		log::info!("An ERROR HAPPNED UYPOOOOOOOOOOO ! {:?}", e);
+		// 		log::error!("{:?}", e);
+		// 		ChainRequestError::ErrorGettingJsonRpcResponse
+		// 	})?
+		// 	.map_err(|e| {
+		// 		log::info!("An ERROR HAPPNED22!");
+		// 		log::error!("{:?}", e);
+		// 		ChainRequestError::ErrorGettingJsonRpcResponse
+		// 	})?;
+
+		// log::info!("Response is ready!");
+		let body = response.body().collect::<Vec<u8>>();
+		Ok((response.code, body))
+	}
+}
+
+#[cfg(not(test))]
+fn with_transport<R>(f: impl FnOnce(&dyn HttpTransport) -> R) -> R {
+	recording::with_session(&OffchainHttp, f)
+}
+
+#[cfg(test)]
+fn with_transport<R>(f: impl FnOnce(&dyn HttpTransport) -> R) -> R {
+	crate::mock_http::with_mock(|mock| recording::with_session(mock, f))
+}
+
 fn fetch_json_rpc_body(base_url: &str, req: &JsonRpcRequest) -> Result<Vec<u8>, ChainRequestError> {
+	RpcBudget::consume(base_url)?;
 	let mut params = JsonSer::new();
 	req.params.iter().for_each(|p| {
 		params.arr_val(str::from_utf8(p.as_slice()).unwrap());
@@ -155,96 +400,123 @@ fn fetch_json_rpc_body(base_url: &str, req: &JsonRpcRequest) -> Result<Vec<u8>,
 		.to_vec();
 	let json_req_str = str::from_utf8(&json_req_s).unwrap();
 	log::info!("About to submit {}", json_req_str);
-	let request: http::Request<Vec<&[u8]>> =
-		http::Request::post(base_url, Vec::from([json_req_s.as_slice()]));
-	let timeout = sp_io::offchain::timestamp().add(Duration::from_millis(FETCH_TIMEOUT_PERIOD));
-
-	let pending = request
-		// .deadline(timeout) // Setting the timeout time
-		.add_header("Content-Type", "application/json")
-		.send() // Sending the request out by the host
-		.map_err(|e| {
-			log::info!("An ERROR HAPPNED!");
-			// println!("ERRROOOORRRR {:?}", e);
-			log::error!("{:?}", e);
-			ChainRequestError::ErrorGettingJsonRpcResponse
-		})?;
-
-	// By default, the http request is async from the runtime perspective. So we are asking the
-	//   runtime to wait here
-	// The returning value here is a `Result` of `Result`, so we are unwrapping it twice by two `?`
-	//   ref: https://docs.substrate.io/rustdocs/latest/sp_runtime/offchain/http/struct.PendingRequest.html#method.try_wait
-	let response_a = pending.try_wait(timeout);
-	// let response_0 = pending.wait();
-	let response_0 = match response_a {
-		Ok(r) => {
-			// println!("Result got");
-			// log::info!("Result got");
-			Ok(r)
-		},
-		Err(e) => {
-			// println!("ERRROOOORRRR AFDTER {:?}", e);
-			log::info!("An ERROR HAPPNED!");
-			log::info!("An ERROR HAPPNED UYPOOOOOOOOOOO ! {:?}", e);
-			Err(ChainRequestError::ErrorGettingJsonRpcResponse)
-		},
-	}?;
-	let response = match response_0 {
-		Ok(r) => {
-			// log::info!("Result got 2");
-			Ok(r)
-		},
-		Err(e) => {
-			log::info!("An ERROR HAPPNED 2!");
-			log::info!("An ERROR HAPPNED UYPOOOOOOOOOOO 2 ! {:?}", e);
-			Err(ChainRequestError::ErrorGettingJsonRpcResponse)
-		},
-	}?;
-	// let response = pending
-	// 	.try_wait(timeout)
-	// 	.map_err(|e| {
-	// 		log::info!("An ERROR HAPPNED!");
-	// 		log::info!("An ERROR HAPPNED UYPOOOOOOOOOOO ! {:?}", e);
-	// 		log::error!("{:?}", e);
-	// 		ChainRequestError::ErrorGettingJsonRpcResponse
-	// 	})?
-	// 	.map_err(|e| {
-	// 		log::info!("An ERROR HAPPNED22!");
-	// 		log::error!("{:?}", e);
-	// 		ChainRequestError::ErrorGettingJsonRpcResponse
-	// 	})?;
-
-	// log::info!("Response is ready!");
-	let body = response.body().collect::<Vec<u8>>();
+	let (code, body) = with_transport(|transport| transport.post(base_url, &json_req_s))?;
 	log::info!(
 		"Response code got : {}-{}",
-		&response.code,
-		str::from_utf8(body.as_slice()).unwrap()
+		&code,
+		str::from_utf8(body.as_slice()).unwrap_or("<non utf-8 body>")
 	);
 
-	if response.code != 200 {
-		log::error!("Unexpected http request status code: {}", response.code);
-		return Err(ChainRequestError::ErrorGettingJsonRpcResponse);
+	if code != 200 {
+		log::error!("Unexpected http request status code: {}", code);
+		let err = ChainRequestError::HttpStatusError(code);
+		RpcFailure::record(base_url, &req.method, &err, &body);
+		return Err(err);
 	}
 
 	Ok(body)
 }
 
+/// Fetch and decode a json-rpc response, read only queries are sent again when the failure may
+/// be transient.
 pub fn fetch_json_rpc<T>(base_url: &str, req: &JsonRpcRequest) -> Result<Box<T>, ChainRequestError>
 where
 	T: for<'de> Deserialize<'de>,
 {
-	// println!("fetchin {} : {:?}", base_url, req);
-	let body = fetch_json_rpc_body(base_url, req)?;
-	// println!("Response body got : {}", str::from_utf8(&body).unwrap());
-	// log::info!("Response body got : {}", str::from_utf8(&body).unwrap());
-	let rv: serde_json::Result<T> = serde_json::from_slice(&body);
-	match rv {
-		Err(err) => {
-			log::error!("Error while parsing json {:?}", err);
-			Err(ChainRequestError::ErrorGettingJsonRpcResponse)
+	let retries = if is_idempotent(req) { MAX_RPC_RETRIES } else { 0 };
+	let mut attempt = 0;
+	loop {
+		match try_fetch_json_rpc(base_url, req) {
+			Err(e) if attempt < retries && is_transient(&e) => {
+				attempt += 1;
+				log::warn!(
+					"Retrying {} after {:?} ({}/{})",
+					str::from_utf8(&req.method).unwrap_or_default(),
+					e,
+					attempt,
+					retries
+				);
+			},
+			res => return res,
+		}
+	}
+}
+
+fn try_fetch_json_rpc<T>(base_url: &str, req: &JsonRpcRequest) -> Result<Box<T>, ChainRequestError>
+where
+	T: for<'de> Deserialize<'de>,
+{
+	let body = match fetch_json_rpc_body(base_url, req) {
+		// recorded with the response body already
+		Err(e @ ChainRequestError::HttpStatusError(_)) => return Err(e),
+		Err(e) => {
+			RpcFailure::record(base_url, &req.method, &e, &[]);
+			return Err(e);
 		},
-		Ok(v) => Ok(Box::new(v)),
+		Ok(body) => body,
+	};
+	decode_json_rpc(&body).map_err(|e| {
+		RpcFailure::record(base_url, &req.method, &e, &body);
+		e
+	})
+}
+
+/// Decode `body`, checking the json-rpc envelope first so a node error is told apart from a
+/// response that is not json-rpc at all.
+fn decode_json_rpc<T>(body: &[u8]) -> Result<Box<T>, ChainRequestError>
+where
+	T: for<'de> Deserialize<'de>,
+{
+	let value: serde_json::Value = serde_json::from_slice(body).map_err(|err| {
+		log::error!("Response is not json {:?}", err);
+		ChainRequestError::MalformedResponse
+	})?;
+	let envelope = value.as_object().ok_or(ChainRequestError::MalformedResponse)?;
+	if let Some(error) = envelope.get("error") {
+		let code = error.get("code").and_then(|c| c.as_i64()).unwrap_or_default();
+		let message = error.get("message").and_then(|m| m.as_str()).unwrap_or_default();
+		log::error!("Json rpc error {}: {}", code, message);
+		return Err(classify_rpc_error(code, message.as_bytes().to_vec()));
+	}
+	if !envelope.contains_key("result") {
+		log::error!("Response has neither a result nor an error");
+		return Err(ChainRequestError::MalformedResponse);
+	}
+	serde_json::from_slice(body).map(Box::new).map_err(|err| {
+		log::error!("Error while parsing json {:?}", err);
+		ChainRequestError::JsonDecodeError
+	})
+}
+
+/// Queries can be sent again, transactions are only broadcast once
+fn is_idempotent(req: &JsonRpcRequest) -> bool {
+	req.method.as_slice() != b"eth_sendRawTransaction"
+}
+
+/// Failures of the transport or the endpoint that may not happen on the next attempt
+fn is_transient(err: &ChainRequestError) -> bool {
+	match err {
+		ChainRequestError::RpcTransportError |
+		ChainRequestError::Timeout |
+		ChainRequestError::MalformedResponse => true,
+		ChainRequestError::HttpStatusError(code) => *code == 429 || *code >= 500,
+		_ => false,
+	}
+}
+
+/// Map a json-rpc error message to a specific error, the messages are the ones returned by geth
+/// compatible nodes.
+pub fn classify_rpc_error(code: i64, message: Vec<u8>) -> ChainRequestError {
+	let lower = message.to_ascii_lowercase();
+	let contains = |pattern: &[u8]| lower.windows(pattern.len()).any(|w| w == pattern);
+	if contains(b"nonce too low") {
+		ChainRequestError::NonceTooLow
+	} else if contains(b"insufficient funds") {
+		ChainRequestError::InsufficientFunds
+	} else if contains(b"execution reverted") {
+		ChainRequestError::Reverted(message)
+	} else {
+		ChainRequestError::NodeError(code, message)
 	}
 }
 
@@ -270,18 +542,108 @@ pub struct GetTransactionReceiptResponse {
 	result: Option<GetTransactionReceiptResponseData>,
 }
 
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetTransactionResponseData {
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	from: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	nonce: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	gasPrice: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	to: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	value: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	gas: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	input: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetTransactionResponse {
+	result: Option<GetTransactionResponseData>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetLogsResponseData {
+	#[serde(deserialize_with = "de_string_list_to_bytes_list")]
+	topics: Vec<Vec<u8>>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	data: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	blockNumber: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	transactionHash: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetLogsResponse {
+	result: Vec<GetLogsResponseData>,
+}
+
+/// A contract event returned by `eth_getLogs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventLog {
+	pub topics: Vec<H256>,
+	pub data: Vec<u8>,
+	pub block_number: u64,
+	pub transaction_hash: H256,
+}
+
+/// A transaction known to the node, with what is needed to send it again
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionDetails {
+	pub from: H160,
+	pub nonce: U256,
+	pub gas_price: U256,
+	pub to: H160,
+	pub value: U256,
+	pub gas: U256,
+	pub input: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetBlockResponseData {
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	hash: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetBlockResponse {
+	result: Option<GetBlockResponseData>,
+}
+
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetBlockHeaderResponseData {
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	number: Vec<u8>,
+	#[serde(deserialize_with = "de_string_to_bytes")]
+	timestamp: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Encode, Decode)]
+pub struct GetBlockHeaderResponse {
+	result: Option<GetBlockHeaderResponseData>,
+}
+
 pub struct ChainQueries /* <T: Config> */ {}
 
 impl ChainQueries {
 	#[allow(dead_code)]
 	pub fn chain_id(url: &str) -> Result<u32, ChainRequestError> {
-		log::info!("About to get chain_id {}", url);
-		let req = JsonRpcRequest { id: 1, params: Vec::new(), method: b"eth_chainId".to_vec() };
-		// log::info!("Have request {:?}", &req);
-		let res: Box<GetChainIdResponse> = fetch_json_rpc(url, &req)?;
-		log::info!("Result is {:?}", &res);
-		let chain_id = ChainUtils::hex_to_u64(&res.result)?;
-		Ok(chain_id as u32)
+		// the chain id of an endpoint never changes
+		RpcCache::get_or_fetch(url, b"chain_id", u64::MAX, || {
+			log::info!("About to get chain_id {}", url);
+			let req = JsonRpcRequest { id: 1, params: Vec::new(), method: b"eth_chainId".to_vec() };
+			// log::info!("Have request {:?}", &req);
+			let res: Box<GetChainIdResponse> = fetch_json_rpc(url, &req)?;
+			log::info!("Result is {:?}", &res);
+			let chain_id = ChainUtils::hex_to_u64(&res.result)?;
+			Ok(chain_id as u32)
+		})
 	}
 
 	pub fn get_transaction_receipt(
@@ -307,22 +669,329 @@ impl ChainQueries {
 		Ok(res.result)
 	}
 
+	/// Returns the details of the transaction, `None` if the node does not know it
+	pub fn get_transaction(
+		url: &str,
+		tx_id: &H256,
+	) -> ChainRequestResult<Option<TransactionDetails>> {
+		let tx_id = ChainUtils::h256_to_hex_0x(tx_id);
+		let req = JsonRpcRequest {
+			id: 1,
+			params: vec![ChainUtils::wrap_in_quotes(tx_id.as_slice()).to_vec()],
+			method: b"eth_getTransactionByHash".to_vec(),
+		};
+		let res: Box<GetTransactionResponse> = fetch_json_rpc(url, &req)?;
+		match res.result {
+			None => Ok(None),
+			Some(tx) => Ok(Some(TransactionDetails {
+				from: H160::from_slice(ChainUtils::hex_to_bytes(tx.from.as_slice())?.as_slice()),
+				nonce: ChainUtils::hex_to_u256(tx.nonce.as_slice())?,
+				gas_price: ChainUtils::hex_to_u256(tx.gasPrice.as_slice())?,
+				to: H160::from_slice(ChainUtils::hex_to_bytes(tx.to.as_slice())?.as_slice()),
+				value: ChainUtils::hex_to_u256(tx.value.as_slice())?,
+				gas: ChainUtils::hex_to_u256(tx.gas.as_slice())?,
+				input: ChainUtils::hex_to_bytes(tx.input.as_slice())?,
+			})),
+		}
+	}
+
+	/// Returns the events of the contract at `address` matching `topics`, in the blocks
+	/// `from_block` to `to_block` included
+	pub fn get_logs(
+		url: &str,
+		address: H160,
+		topics: &[H256],
+		from_block: u64,
+		to_block: u64,
+	) -> ChainRequestResult<Vec<EventLog>> {
+		let topics: Vec<Vec<u8>> = topics
+			.iter()
+			.map(|topic| ChainUtils::wrap_in_quotes(ChainUtils::h256_to_hex_0x(topic).as_slice()))
+			.collect();
+		let filter = JsonSer::new()
+			.start()
+			.string("address", str::from_utf8(&ChainUtils::address_to_hex(address)).unwrap())
+			.string("fromBlock", str::from_utf8(&ChainUtils::u64_to_hex_0x(from_block)).unwrap())
+			.string("toBlock", str::from_utf8(&ChainUtils::u64_to_hex_0x(to_block)).unwrap())
+			.arr("topics", str::from_utf8(&topics.join(&b',')).unwrap())
+			.end()
+			.to_vec();
+		let req = JsonRpcRequest { id: 1, params: vec![filter], method: b"eth_getLogs".to_vec() };
+		let res: Box<GetLogsResponse> = fetch_json_rpc(url, &req)?;
+		res.result
+			.into_iter()
+			.map(|log| {
+				Ok(EventLog {
+					topics: log
+						.topics
+						.iter()
+						.map(|topic| {
+							Ok(H256::from_slice(ChainUtils::hex_to_bytes(topic)?.as_slice()))
+						})
+						.collect::<ChainRequestResult<_>>()?,
+					data: ChainUtils::hex_to_bytes(log.data.as_slice())?,
+					block_number: ChainUtils::hex_to_u64(log.blockNumber.as_slice())?,
+					transaction_hash: H256::from_slice(
+						ChainUtils::hex_to_bytes(log.transactionHash.as_slice())?.as_slice(),
+					),
+				})
+			})
+			.collect()
+	}
+
+	pub fn block_number(url: &str) -> ChainRequestResult<u64> {
+		let req = JsonRpcRequest { id: 1, params: Vec::new(), method: b"eth_blockNumber".to_vec() };
+		let res: Box<CallResponse> = fetch_json_rpc(url, &req)?;
+		ChainUtils::hex_to_u64(res.result.as_slice())
+	}
+
+	/// Returns the hash of the canonical block at `block_number`, `None` if there is no such
+	/// block yet.
+	pub fn block_hash(url: &str, block_number: u64) -> ChainRequestResult<Option<H256>> {
+		let number = ChainUtils::u64_to_hex_0x(block_number);
+		let req = JsonRpcRequest {
+			id: 1,
+			params: vec![ChainUtils::wrap_in_quotes(number.as_slice()).to_vec(), b"false".to_vec()],
+			method: b"eth_getBlockByNumber".to_vec(),
+		};
+		let res: Box<GetBlockResponse> = fetch_json_rpc(url, &req)?;
+		match res.result {
+			None => Ok(None),
+			Some(block) => Ok(Some(H256::from_slice(
+				ChainUtils::hex_to_bytes(block.hash.as_slice())?.as_slice(),
+			))),
+		}
+	}
+
+	/// Returns the number and timestamp of `block`, a block number in hex or a block tag such
+	/// as `finalized`, `None` if there is no such block.
+	pub fn block_header(url: &str, block: &[u8]) -> ChainRequestResult<Option<(u64, u64)>> {
+		let req = JsonRpcRequest {
+			id: 1,
+			params: vec![ChainUtils::wrap_in_quotes(block), b"false".to_vec()],
+			method: b"eth_getBlockByNumber".to_vec(),
+		};
+		let res: Box<GetBlockHeaderResponse> = fetch_json_rpc(url, &req)?;
+		match res.result {
+			None => Ok(None),
+			Some(block) => Ok(Some((
+				ChainUtils::hex_to_u64(block.number.as_slice())?,
+				ChainUtils::hex_to_u64(block.timestamp.as_slice())?,
+			))),
+		}
+	}
+
+	/// Returns the status of the transaction, a successful transaction is only reported as
+	/// confirmed once its block is at least `confirmation_depth` blocks deep.
 	pub fn get_transaction_status(
 		url: &str,
 		tx_id: &H256,
+		confirmation_depth: u64,
 	) -> ChainRequestResult<TransactionStatus> {
 		let rv = Self::get_transaction_receipt(url, tx_id)?;
 		let res = match rv {
 			None => TransactionStatus::NotFound,
 			Some(tx) => {
 				let status = ChainUtils::hex_to_u64(tx.status.as_slice())?;
-				if status == 1 {
-					TransactionStatus::Confirmed
-				} else {
+				let block_number = ChainUtils::hex_to_u64(tx.blockNumber.as_slice())?;
+				let block_hash =
+					H256::from_slice(ChainUtils::hex_to_bytes(tx.blockHash.as_slice())?.as_slice());
+				if status != 1 {
 					TransactionStatus::Failed
+				} else if confirmation_depth == 0 {
+					TransactionStatus::Confirmed { block_number, block_hash }
+				} else {
+					let latest = Self::block_number(url)?;
+					if block_number.saturating_add(confirmation_depth) <= latest {
+						TransactionStatus::Confirmed { block_number, block_hash }
+					} else {
+						TransactionStatus::Pending
+					}
 				}
 			},
 		};
 		Ok(res)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		chain_queries::{fetch_json_rpc, CallResponse, ChainQueries, JsonRpcRequest, RpcFailure},
+		chain_utils::ChainRequestError,
+		mock_http,
+	};
+	use sp_core::{
+		offchain::{testing::TestOffchainExt, OffchainDbExt, OffchainWorkerExt},
+		H160, H256, U256,
+	};
+
+	const URL: &str = "http://localhost:8545";
+
+	fn with_offchain(f: impl FnOnce()) {
+		let (offchain, _state) = TestOffchainExt::new();
+		let mut ext = sp_io::TestExternalities::default();
+		ext.register_extension(OffchainDbExt::new(offchain.clone()));
+		ext.register_extension(OffchainWorkerExt::new(offchain));
+		mock_http::reset();
+		ext.execute_with(f);
+	}
+
+	fn receipt(status: &str, block_number: &str) -> String {
+		format!(
+			"{{\"blockHash\":\"0x{}\",\"blockNumber\":\"{}\",\"status\":\"{}\"}}",
+			"11".repeat(32),
+			block_number,
+			status
+		)
+	}
+
+	#[test]
+	fn transaction_is_confirmed_once_deep_enough() {
+		with_offchain(|| {
+			mock_http::respond("eth_getTransactionReceipt", &receipt("0x01", "0x10"));
+			mock_http::respond("eth_blockNumber", "\"0x11\"");
+			let status = ChainQueries::get_transaction_status(URL, &H256::zero(), 2).unwrap();
+			assert!(matches!(status, super::TransactionStatus::Pending));
+
+			mock_http::respond("eth_getTransactionReceipt", &receipt("0x01", "0x10"));
+			mock_http::respond("eth_blockNumber", "\"0x12\"");
+			let status = ChainQueries::get_transaction_status(URL, &H256::zero(), 2).unwrap();
+			assert!(matches!(
+				status,
+				super::TransactionStatus::Confirmed { block_number: 16, block_hash }
+					if block_hash == H256::repeat_byte(0x11)
+			));
+		});
+	}
+
+	#[test]
+	fn transaction_details_are_decoded() {
+		with_offchain(|| {
+			mock_http::respond(
+				"eth_getTransactionByHash",
+				&format!(
+					"{{\"from\":\"0x{}\",\"nonce\":\"0x07\",\"gasPrice\":\"0x3b9aca00\",\"to\":\"0x{}\",\"value\":\"0x0\",\"gas\":\"0x5208\",\"input\":\"0x0102\"}}",
+					"22".repeat(20),
+					"33".repeat(20)
+				),
+			);
+			mock_http::respond("eth_getTransactionByHash", "null");
+			let tx = ChainQueries::get_transaction(URL, &H256::zero()).unwrap().unwrap();
+			assert_eq!(tx.from, H160::repeat_byte(0x22));
+			assert_eq!(tx.nonce, U256::from(7));
+			assert_eq!(tx.gas_price, U256::from(1_000_000_000_u64));
+			assert_eq!(tx.to, H160::repeat_byte(0x33));
+			assert_eq!(tx.gas, U256::from(21_000));
+			assert_eq!(tx.input, vec![1, 2]);
+			assert_eq!(ChainQueries::get_transaction(URL, &H256::zero()).unwrap(), None);
+		});
+	}
+
+	#[test]
+	fn logs_are_decoded() {
+		with_offchain(|| {
+			mock_http::respond(
+				"eth_getLogs",
+				&format!(
+					"[{{\"topics\":[\"0x{}\"],\"data\":\"0x0102\",\"blockNumber\":\"0x10\",\"transactionHash\":\"0x{}\"}}]",
+					"33".repeat(32),
+					"44".repeat(32)
+				),
+			);
+			let logs =
+				ChainQueries::get_logs(URL, H160::zero(), &[H256::repeat_byte(0x33)], 16, 20)
+					.unwrap();
+			assert_eq!(
+				logs,
+				vec![super::EventLog {
+					topics: vec![H256::repeat_byte(0x33)],
+					data: vec![1, 2],
+					block_number: 16,
+					transaction_hash: H256::repeat_byte(0x44),
+				}]
+			);
+		});
+	}
+
+	#[test]
+	fn block_header_is_decoded() {
+		with_offchain(|| {
+			mock_http::respond(
+				"eth_getBlockByNumber",
+				"{\"number\":\"0x10\",\"timestamp\":\"0x65f4\",\"hash\":\"0x00\"}",
+			);
+			mock_http::respond("eth_getBlockByNumber", "null");
+			assert_eq!(ChainQueries::block_header(URL, b"finalized").unwrap(), Some((16, 26100)));
+			assert_eq!(ChainQueries::block_header(URL, b"safe").unwrap(), None);
+		});
+	}
+
+	#[test]
+	fn missing_receipt_is_not_found() {
+		with_offchain(|| {
+			mock_http::respond("eth_getTransactionReceipt", "null");
+			let status = ChainQueries::get_transaction_status(URL, &H256::zero(), 2).unwrap();
+			assert!(matches!(status, super::TransactionStatus::NotFound));
+		});
+	}
+
+	#[test]
+	fn reverted_receipt_is_failed() {
+		with_offchain(|| {
+			mock_http::respond("eth_getTransactionReceipt", &receipt("0x00", "0x10"));
+			let status = ChainQueries::get_transaction_status(URL, &H256::zero(), 2).unwrap();
+			assert!(matches!(status, super::TransactionStatus::Failed));
+			// a failed transaction does not need the chain head
+			assert_eq!(mock_http::requests(), vec!["eth_getTransactionReceipt"]);
+		});
+	}
+
+	#[test]
+	fn node_errors_are_classified_and_not_retried() {
+		with_offchain(|| {
+			mock_http::respond_raw(
+				"eth_blockNumber",
+				200,
+				"{\"jsonrpc\":\"2.0\",\"id\":1,\"error\":{\"code\":-32000,\"message\":\"nonce too low\"}}",
+			);
+			assert_eq!(ChainQueries::block_number(URL), Err(ChainRequestError::NonceTooLow));
+			assert_eq!(mock_http::requests().len(), 1);
+		});
+	}
+
+	#[test]
+	fn malformed_responses_are_retried_and_recorded() {
+		with_offchain(|| {
+			for _ in 0..3 {
+				mock_http::respond_raw("eth_blockNumber", 200, "<html>Bad gateway</html>");
+			}
+			assert_eq!(ChainQueries::block_number(URL), Err(ChainRequestError::MalformedResponse));
+			assert_eq!(mock_http::requests().len(), 3);
+
+			let failure = RpcFailure::last(URL).unwrap();
+			assert_eq!(failure.method, b"eth_blockNumber".to_vec());
+			assert_eq!(failure.error, ChainRequestError::MalformedResponse);
+			assert_eq!(failure.payload, b"<html>Bad gateway</html>".to_vec());
+
+			mock_http::respond_raw("eth_blockNumber", 200, "<html>Bad gateway</html>");
+			mock_http::respond("eth_blockNumber", "\"0x2a\"");
+			assert_eq!(ChainQueries::block_number(URL), Ok(42));
+		});
+	}
+
+	#[test]
+	fn transactions_are_sent_once() {
+		with_offchain(|| {
+			mock_http::respond_raw("eth_sendRawTransaction", 502, "");
+			let req = JsonRpcRequest {
+				id: 1,
+				params: vec![b"\"0x00\"".to_vec()],
+				method: b"eth_sendRawTransaction".to_vec(),
+			};
+			let res = fetch_json_rpc::<CallResponse>(URL, &req).map(|_| ());
+			assert_eq!(res, Err(ChainRequestError::HttpStatusError(502)));
+			assert_eq!(mock_http::requests().len(), 1);
+		});
+	}
+}
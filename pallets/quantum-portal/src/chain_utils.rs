@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
 use ethereum::{LegacyTransaction, LegacyTransactionMessage, TransactionSignature};
-use parity_scale_codec::Encode;
+use parity_scale_codec::{Decode, Encode};
 
 pub struct ChainUtils;
 use ethabi_nostd::{Address, H256, U256};
@@ -23,9 +23,25 @@ use numtoa::NumToA;
 use sp_std::{prelude::*, str};
 use tiny_keccak::{Hasher, Keccak};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Encode, Decode)]
 pub enum ChainRequestError {
-	ErrorGettingJsonRpcResponse,
+	/// The http request could not be sent or the connection failed
+	RpcTransportError,
+	/// The rpc endpoint answered with a non 200 status code
+	HttpStatusError(u16),
+	/// The response could not be decoded
+	JsonDecodeError,
+	/// The response is not a json-rpc envelope
+	MalformedResponse,
+	/// The node answered with a json-rpc error not mapped to a specific error, holds its code
+	/// and message
+	NodeError(i64, Vec<u8>),
+	/// The request did not complete before the deadline
+	Timeout,
+	/// The transaction or call reverted, holds the node error message
+	Reverted(Vec<u8>),
+	NonceTooLow,
+	InsufficientFunds,
 	BadRemoteData,
 	ConversionError,
 	ErrorCreatingTransaction(TransactionCreationError),
@@ -34,9 +50,19 @@ pub enum ChainRequestError {
 	InvalidHexCharacter,
 	SlotNotAvailable,
 	MinedBlockVerificationError,
+	/// No client is configured for the chain
+	ChainNotConfigured(u64),
+	/// The rpc call budget of the endpoint is used up for this offchain worker run
+	RpcBudgetExhausted,
+	/// A verification endpoint returned a source block different from the primary endpoint
+	ProviderDataMismatch,
+	/// Not enough verification endpoints agreed on the source block
+	VerificationQuorumNotReached,
+	/// The contract is not on the allowlist of the chain, no transaction is sent to it
+	ContractNotAllowed(Address),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Encode, Decode)]
 pub enum TransactionCreationError {
 	NoSignerFound,
 	SigningFailed,
@@ -45,6 +71,20 @@ pub enum TransactionCreationError {
 	CannotFindContractAddress,
 }
 
+/// Identifies the `process_pair` run a log line belongs to
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TraceContext {
+	pub trace_id: u64,
+	pub remote_chain: u64,
+	pub local_chain: u64,
+}
+
+impl sp_std::fmt::Display for TraceContext {
+	fn fmt(&self, f: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+		write!(f, "[trace={:016x} {}->{}]", self.trace_id, self.remote_chain, self.local_chain)
+	}
+}
+
 impl From<&[u8]> for ChainRequestError {
 	fn from(msg: &[u8]) -> Self {
 		ChainRequestError::JsonRpcError(Vec::from(msg))
@@ -216,6 +256,13 @@ impl ChainUtils {
 		zx
 	}
 
+	/// Hex quantity as expected by json-rpc, without leading zeros
+	pub fn u64_to_hex_0x(i: u64) -> Vec<u8> {
+		let hex = Self::bytes_to_hex(i.to_be_bytes().as_slice());
+		let start = hex.iter().position(|c| *c != b'0').unwrap_or(hex.len() - 1);
+		Self::hex_add_0x(&hex[start..])
+	}
+
 	pub fn h256_to_hex_0x(i: &H256) -> Vec<u8> {
 		let fmted = i.0.as_slice();
 		Self::hex_add_0x(Self::bytes_to_hex(fmted).as_slice())
@@ -14,18 +14,28 @@
 // You should have received a copy of the GNU General Public License
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
-	chain_queries::{fetch_json_rpc, CallResponse, JsonRpcRequest},
-	chain_utils::{ChainRequestError, ChainUtils, JsonSer, TransactionCreationError},
+	chain_queries::{
+		fetch_json_rpc, CallResponse, JsonRpcRequest, RpcCache, TransactionDetails,
+		CONTRACT_CONFIG_CACHE_TTL,
+	},
+	chain_utils::{
+		ChainRequestError, ChainRequestResult, ChainUtils, JsonSer, TraceContext,
+		TransactionCreationError,
+	},
+	qp_types::GasEstimation,
+	recording::Recorder,
 };
 use ethabi_nostd::{encoder, Address, Token};
 use ethereum::{LegacyTransaction, TransactionAction};
 use ferrum_primitives::OFFCHAIN_SIGNER_KEY_TYPE;
-use parity_scale_codec::Encode;
+use parity_scale_codec::{Decode, Encode};
 use rlp::Encodable;
 use serde::Deserialize;
 use sp_core::{ecdsa, H160, H256, U256};
 use sp_io::crypto;
+use sp_runtime::offchain::storage::StorageValueRef;
 use sp_std::{
+	cell::Cell,
 	ops::{Div, Mul},
 	prelude::*,
 	str,
@@ -39,9 +49,26 @@ pub struct ContractClient {
 	pub ledger_manager_address: Option<Address>,
 	pub authority_manager_address: Option<Address>,
 	pub miner_manager_address: Option<Address>,
+	/// Build, sign and estimate transactions without broadcasting them
+	pub dry_run: bool,
+	/// Run the client is currently used by, carried in the logs
+	pub trace: Cell<TraceContext>,
 }
 
-// #[derive(Clone)]
+/// Write ahead journal entry of a transaction, recorded before it is broadcast so a worker that
+/// stopped half way can reconcile it against the chain on the next run.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct JournalEntry {
+	pub from: Address,
+	pub nonce: U256,
+	pub gas_limit: U256,
+	pub raw_tx: Vec<u8>,
+	pub tx_hash: H256,
+	/// Set once the node accepted the transaction
+	pub broadcast: bool,
+}
+
+#[derive(Clone)]
 pub struct ContractClientSignature {
 	pub from: Address,
 	pub _signer: ecdsa::Public,
@@ -54,6 +81,9 @@ impl ContractClientSignature {
 
 	pub fn signer(&self, hash: &H256) -> Result<ecdsa::Signature, TransactionCreationError> {
 		log::info!("Signer address is : {:?}", self.from);
+		if Recorder::replaying() {
+			return Ok(Recorder::replay_signature());
+		}
 		// TODO : We should handle this properly, if the signing is not possible maybe propogate the
 		// error upstream
 		let signed: Result<ecdsa::Signature, TransactionCreationError> =
@@ -89,6 +119,88 @@ impl From<ecdsa::Public> for ContractClientSignature {
 	}
 }
 
+/// Relayer keys transactions are submitted from, in turns, so a single key nonce does not hold
+/// back the submissions. Keys without the balance to pay for a transaction on top of
+/// `gas_reserve` leave the rotation until they are topped up.
+pub struct SignerPool {
+	pub signers: Vec<ContractClientSignature>,
+	pub gas_reserve: Option<U256>,
+}
+
+impl SignerPool {
+	pub fn new(signers: Vec<ContractClientSignature>, gas_reserve: Option<U256>) -> Self {
+		SignerPool { signers, gas_reserve }
+	}
+
+	fn cursor_key(client: &ContractClient) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(client.chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::signer_cursor::".as_slice(), chain.as_slice()].concat()
+	}
+
+	fn nonce_key(client: &ContractClient, from: Address) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(client.chain_id.to_be_bytes().as_slice());
+		[
+			b"quantum-portal::signer_nonce::".as_slice(),
+			chain.as_slice(),
+			b"::",
+			ChainUtils::bytes_to_hex(from.as_bytes()).as_slice(),
+		]
+		.concat()
+	}
+
+	/// Returns the next key in turn with the balance to pay `cost` and the nonce to submit with
+	pub fn next(
+		&self,
+		client: &ContractClient,
+		cost: U256,
+	) -> ChainRequestResult<(&ContractClientSignature, U256)> {
+		let required = cost.saturating_add(self.gas_reserve.unwrap_or_default());
+		let cursor_key = Self::cursor_key(client);
+		Recorder::track(&cursor_key);
+		let cursor = StorageValueRef::persistent(cursor_key.as_slice());
+		let start = cursor.get::<u32>().ok().flatten().unwrap_or_default() as usize;
+		for i in 0..self.signers.len() {
+			let index = (start + i) % self.signers.len();
+			let signer = &self.signers[index];
+			let balance = client.balance(signer.from)?;
+			if balance < required {
+				log::warn!(
+					"{} Relayer key {:?} balance {} does not cover the transaction cost {} and gas reserve, skipping",
+					client.trace.get(),
+					signer.from,
+					balance,
+					cost,
+				);
+				continue;
+			}
+			cursor.set(&(((index + 1) % self.signers.len()) as u32));
+			return Ok((signer, self.nonce(client, signer.from)?));
+		}
+		Err(b"No relayer key has the balance to pay for the transaction".as_slice().into())
+	}
+
+	/// Nonce of `from`, ahead of the chain when transactions it sent are not mined yet
+	fn nonce(&self, client: &ContractClient, from: Address) -> ChainRequestResult<U256> {
+		let chain_nonce = client.nonce(from)?;
+		Recorder::track(&Self::nonce_key(client, from));
+		let tracked = StorageValueRef::persistent(Self::nonce_key(client, from).as_slice())
+			.get::<U256>()
+			.ok()
+			.flatten()
+			.unwrap_or_default();
+		Ok(chain_nonce.max(tracked))
+	}
+
+	/// Record the nonce of a transaction submitted from `from`
+	pub fn record_nonce(&self, client: &ContractClient, from: Address, nonce: U256) {
+		if client.dry_run {
+			return;
+		}
+		StorageValueRef::persistent(Self::nonce_key(client, from).as_slice())
+			.set(&nonce.saturating_add(U256::one()));
+	}
+}
+
 impl ContractClient {
 	pub fn new(http_api: Vec<u8>, gateway_contract_address: &Address, chain_id: u64) -> Self {
 		ContractClient {
@@ -98,11 +210,20 @@ impl ContractClient {
 			ledger_manager_address: None,
 			authority_manager_address: None,
 			miner_manager_address: None,
+			dry_run: false,
+			trace: Cell::new(TraceContext::default()),
 		}
 	}
 
 	pub fn get_ledger_manager_address(&self) -> Result<H160, ChainRequestError> {
-		// no cache, we fetch from the gateway contract
+		// use the configured address if any, otherwise we fetch from the gateway contract
+		if let Some(address) = self.ledger_manager_address {
+			return Ok(address);
+		}
+		self.cached(b"ledger_manager", || self.fetch_ledger_manager_address())
+	}
+
+	fn fetch_ledger_manager_address(&self) -> Result<H160, ChainRequestError> {
 		let signature = b"quantumPortalLedgerMgr()";
 		let res: Box<CallResponse> =
 			self.call(signature, &[], Some(self.gateway_contract_address))?;
@@ -114,9 +235,12 @@ impl ContractClient {
 	}
 
 	pub fn get_miner_manager_address(&self) -> Result<(H160, Vec<u8>, Vec<u8>), ChainRequestError> {
+		self.cached(b"miner_manager", || self.fetch_miner_manager_address())
+	}
+
+	fn fetch_miner_manager_address(&self) -> Result<(H160, Vec<u8>, Vec<u8>), ChainRequestError> {
 		let ledger_manager_address = self.get_ledger_manager_address()?;
 
-		// no cache, we fetch from the gateway contract
 		let signature = b"minerMgr()";
 		let res: Box<CallResponse> = self.call(signature, &[], Some(ledger_manager_address))?;
 		log::info!("Miner manager response is : {:?}", res);
@@ -138,10 +262,15 @@ impl ContractClient {
 
 	pub fn get_authority_manager_address(
 		&self,
+	) -> Result<(H160, Vec<u8>, Vec<u8>), ChainRequestError> {
+		self.cached(b"authority_manager", || self.fetch_authority_manager_address())
+	}
+
+	fn fetch_authority_manager_address(
+		&self,
 	) -> Result<(H160, Vec<u8>, Vec<u8>), ChainRequestError> {
 		let ledger_manager_address = self.get_ledger_manager_address()?;
 
-		// no cache, we fetch from the gateway contract
 		let signature = b"authorityMgr()";
 		let res: Box<CallResponse> = self.call(signature, &[], Some(ledger_manager_address))?;
 		log::info!("Authority manager response is : {:?}", res);
@@ -161,6 +290,17 @@ impl ContractClient {
 		Ok((address, version.to_vec(), name.to_vec()))
 	}
 
+	/// Contract configuration rarely changes, we cache it per endpoint and gateway contract
+	fn cached<V, F>(&self, item: &[u8], fetch: F) -> ChainRequestResult<V>
+	where
+		V: Encode + Decode,
+		F: FnOnce() -> ChainRequestResult<V>,
+	{
+		let http_api = str::from_utf8(&self.http_api[..]).unwrap();
+		let item = [item, b"::", self.gateway_contract_address.as_bytes()].concat();
+		RpcCache::get_or_fetch(http_api, &item, CONTRACT_CONFIG_CACHE_TTL, fetch)
+	}
+
 	pub fn get_miner_for_block(
 		&self,
 		block_hash: H256,
@@ -200,8 +340,9 @@ impl ContractClient {
 	where
 		T: for<'de> Deserialize<'de>,
 	{
-		log::info!("CALL : method_signature {:?}", method_signature);
-		log::info!("CALL : inputs {:?}", inputs);
+		let trace = self.trace.get();
+		log::info!("{} CALL : method_signature {:?}", trace, method_signature);
+		log::info!("{} CALL : inputs {:?}", trace, inputs);
 		let encoded_bytes = encoder::encode_function_u8(method_signature, inputs);
 		let encoded_bytes_0x = ChainUtils::bytes_to_hex(encoded_bytes.as_slice());
 		let encoded_bytes_slice = encoded_bytes_0x.as_slice();
@@ -285,7 +426,107 @@ impl ContractClient {
 		tx.signature = sig;
 
 		let raw_tx = tx.rlp_bytes();
-		let hex_tx = ChainUtils::bytes_to_hex(&raw_tx);
+		let tx_hash = ChainUtils::keccack(&raw_tx);
+		if self.dry_run {
+			let estimated_gas =
+				self.estimate_gas(encoded_bytes_slice.as_slice(), &value, from, recipient_address)?;
+			log::info!(
+				"{} Simulation: not broadcasting tx {:?} to {:?} on chain {}, nonce {}, gas limit {}, estimated gas {}",
+				self.trace.get(),
+				tx_hash,
+				recipient_address,
+				self.chain_id,
+				nonce_val,
+				gas_limit_val,
+				estimated_gas,
+			);
+			return Ok(tx_hash);
+		}
+
+		let mut entry = JournalEntry {
+			from,
+			nonce: nonce_val,
+			gas_limit: gas_limit_val,
+			raw_tx: raw_tx.to_vec(),
+			tx_hash,
+			broadcast: false,
+		};
+		self.write_journal(&entry);
+		let res = self.broadcast(&raw_tx)?;
+		entry.broadcast = true;
+		self.write_journal(&entry);
+		Ok(res)
+	}
+
+	/// Replace the transaction sent by `signing` at `nonce` with a zero value transfer to itself,
+	/// `gas_price` has to be high enough for the node to accept the replacement.
+	pub fn cancel(
+		&self,
+		nonce: U256,
+		gas_price: U256,
+		signing: &ContractClientSignature,
+	) -> Result<H256, ChainRequestError> {
+		let tx = LegacyTransaction {
+			nonce,
+			gas_price,
+			gas_limit: U256::from(21_000_u32),
+			action: TransactionAction::Call(signing.from),
+			value: U256::zero(),
+			input: Vec::new(),
+			signature: ChainUtils::empty_signature(),
+		};
+		self.replace(tx, signing)
+	}
+
+	/// Send the transaction described by `details` again at `gas_price`, at the same nonce so
+	/// only one of them is mined. `gas_price` has to be high enough for the node to accept the
+	/// replacement.
+	pub fn resend(
+		&self,
+		details: &TransactionDetails,
+		gas_price: U256,
+		signing: &ContractClientSignature,
+	) -> Result<H256, ChainRequestError> {
+		let tx = LegacyTransaction {
+			nonce: details.nonce,
+			gas_price,
+			gas_limit: details.gas,
+			action: TransactionAction::Call(details.to),
+			value: details.value,
+			input: details.input.clone(),
+			signature: ChainUtils::empty_signature(),
+		};
+		self.replace(tx, signing)
+	}
+
+	/// Sign and broadcast `tx`, replacing the transaction sent by `signing` at the same nonce
+	fn replace(
+		&self,
+		mut tx: LegacyTransaction,
+		signing: &ContractClientSignature,
+	) -> Result<H256, ChainRequestError> {
+		let hash = ChainUtils::tx_hash_to_sign(&tx, self.chain_id);
+		let sig_bytes: ecdsa::Signature = signing.signer(&hash)?;
+		tx.signature = ChainUtils::decode_transaction_signature(&sig_bytes.0, self.chain_id)?;
+
+		let raw_tx = tx.rlp_bytes();
+		let tx_hash = ChainUtils::keccack(&raw_tx);
+		if self.dry_run {
+			log::info!(
+				"{} Simulation: not broadcasting replacement {:?} of nonce {} on chain {}",
+				self.trace.get(),
+				tx_hash,
+				tx.nonce,
+				self.chain_id,
+			);
+			return Ok(tx_hash);
+		}
+		self.broadcast(&raw_tx)
+	}
+
+	/// Submit a signed raw transaction
+	pub fn broadcast(&self, raw_tx: &[u8]) -> Result<H256, ChainRequestError> {
+		let hex_tx = ChainUtils::bytes_to_hex(raw_tx);
 		let hex_tx_fmtd =
 			ChainUtils::wrap_in_quotes(ChainUtils::hex_add_0x(hex_tx.as_slice()).as_slice());
 		let req = JsonRpcRequest {
@@ -296,10 +537,29 @@ impl ContractClient {
 		// log::info!("Have request {:?}", &req);
 		let http_api = str::from_utf8(&self.http_api[..]).unwrap();
 		let rv: Box<CallResponse> = fetch_json_rpc(http_api, &req)?;
-		log::info!("Have response {:?}", &rv);
+		log::info!("{} Have response {:?}", self.trace.get(), &rv);
 		Ok(H256::from_slice(ChainUtils::hex_to_bytes(rv.result.as_slice())?.as_slice()))
 	}
 
+	fn journal_key(&self) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(self.chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::journal::".as_slice(), chain.as_slice()].concat()
+	}
+
+	fn write_journal(&self, entry: &JournalEntry) {
+		StorageValueRef::persistent(self.journal_key().as_slice()).set(entry);
+	}
+
+	/// Returns the journaled transaction of this chain that is not tracked yet, if any
+	pub fn journal_entry(&self) -> Option<JournalEntry> {
+		StorageValueRef::persistent(self.journal_key().as_slice()).get().ok().flatten()
+	}
+
+	/// Drop the journaled transaction once it is tracked as pending
+	pub fn clear_journal(&self) {
+		StorageValueRef::persistent(self.journal_key().as_slice()).clear();
+	}
+
 	pub fn nonce(&self, from: Address) -> Result<U256, ChainRequestError> {
 		let req = JsonRpcRequest {
 			id: 1,
@@ -315,6 +575,20 @@ impl ContractClient {
 		Ok(U256::from(nonce))
 	}
 
+	pub fn balance(&self, address: Address) -> Result<U256, ChainRequestError> {
+		let req = JsonRpcRequest {
+			id: 1,
+			params: Vec::from([
+				ChainUtils::wrap_in_quotes(ChainUtils::address_to_hex(address).as_slice()),
+				b"\"latest\"".to_vec(),
+			]),
+			method: b"eth_getBalance".to_vec(),
+		};
+		let http_api = str::from_utf8(&self.http_api[..]).unwrap();
+		let rv: Box<CallResponse> = fetch_json_rpc(http_api, &req)?;
+		ChainUtils::hex_to_u256(rv.result.as_slice())
+	}
+
 	pub fn gas_price(&self) -> Result<U256, ChainRequestError> {
 		let req = JsonRpcRequest { id: 1, params: Vec::new(), method: b"eth_gasPrice".to_vec() };
 		let http_api = str::from_utf8(&self.http_api[..]).unwrap();
@@ -323,6 +597,34 @@ impl ContractClient {
 		Ok(gp)
 	}
 
+	/// Gas limit for the call derived from `eth_estimateGas` as set by `estimation`, its fallback
+	/// is used when the estimation fails.
+	pub fn estimate_gas_limit(
+		&self,
+		method_signature: &[u8],
+		inputs: &[Token],
+		from: Address,
+		recipient_address: Address,
+		estimation: &GasEstimation,
+	) -> U256 {
+		let encoded_bytes = encoder::encode_function_u8(method_signature, inputs);
+		let encoded_bytes_0x =
+			ChainUtils::hex_add_0x(ChainUtils::bytes_to_hex(encoded_bytes.as_slice()).as_slice());
+		match self.estimate_gas(encoded_bytes_0x.as_slice(), &U256::zero(), from, recipient_address)
+		{
+			Ok(estimate) => estimation.gas_limit(estimate),
+			Err(e) => {
+				log::warn!(
+					"{} Gas estimation failed, using the fallback gas limit {} : {:?}",
+					self.trace.get(),
+					estimation.fallback,
+					e
+				);
+				U256::from(estimation.fallback)
+			},
+		}
+	}
+
 	pub fn estimate_gas(
 		&self,
 		encoded: &[u8],
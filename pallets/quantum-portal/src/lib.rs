@@ -14,31 +14,57 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 pub use pallet::*;
+pub mod accounting;
 mod chain_queries;
 mod chain_utils;
 mod contract_client;
 mod eip_712_utils;
+pub mod migrations;
+mod mock;
+#[cfg(test)]
+mod mock_http;
 pub mod qp_types;
 mod quantum_portal_client;
 pub mod quantum_portal_service;
+pub mod recording;
+mod tests;
+pub mod weights;
+
+pub use weights::WeightInfo;
 
 #[frame_support::pallet]
 pub mod pallet {
 	// Re-import necessary modules for pallet.
 	use crate::{
+		accounting::{self, SolvencyRecord},
+		chain_queries::RpcBudget,
 		chain_utils::{ChainRequestError, ChainUtils},
-		contract_client::{ContractClient, ContractClientSignature},
+		contract_client::{ContractClient, ContractClientSignature, SignerPool},
 		qp_types,
-		qp_types::{BlockNumber, ChainId, QpConfig, QpNetworkItem, Role},
+		qp_types::{
+			BlockNumber, ChainConfig, ChainId, CustodyPool, InboundMessagesPayload, MessageState,
+			MessageStatePayload, PendingTransactionsPayload, QpConfig, QpNetworkItem,
+			ReservesPayload, RewardsPayload, Role, WithdrawalReceiptsPayload, CHAIN_PAIRS_KEY,
+			MAX_PAYLOADS_PER_BLOCK, MAX_SIGNING_PAYLOAD_LEN, REPLAY_KEY, REPLAY_REPORT_KEY,
+			SIMULATION_MODE_KEY,
+		},
 		quantum_portal_client::QuantumPortalClient,
-		quantum_portal_service::QuantumPortalService,
+		quantum_portal_service::{PendingTransaction, QuantumPortalService},
+		recording::{PairRecording, Recorder},
+		WeightInfo,
 	};
 
 	// Re-import necessary items from core and other external crates.
 	use crate::qp_types::MAX_PAIRS_TO_MINE;
 	use core::convert::TryInto;
-	use ferrum_primitives::{OFFCHAIN_SIGNER_CONFIG_KEY, OFFCHAIN_SIGNER_CONFIG_PREFIX};
-	use frame_support::{pallet_prelude::*, traits::UnixTime};
+	use ferrum_primitives::{
+		InboundMessageHandler, RewardsHandler, SignatureReceiver, SignatureRequester,
+		WithdrawalReleaser, OFFCHAIN_SIGNER_CONFIG_KEY, OFFCHAIN_SIGNER_CONFIG_PREFIX,
+	};
+	use frame_support::{
+		pallet_prelude::*,
+		traits::{Currency, OnUnbalanced, ReservableCurrency, UnixTime},
+	};
 	use frame_system::{
 		offchain::{
 			AppCrypto, CreateSignedTransaction, SendSignedTransaction, SendUnsignedTransaction,
@@ -46,10 +72,14 @@ pub mod pallet {
 		},
 		pallet_prelude::*,
 	};
-	use sp_core::crypto::KeyTypeId;
-	use sp_runtime::offchain::{
-		storage::StorageValueRef,
-		storage_lock::{StorageLock, Time},
+	use sp_core::{crypto::KeyTypeId, ecdsa, H160, H256};
+	use sp_io::hashing::keccak_256;
+	use sp_runtime::{
+		offchain::{
+			storage::StorageValueRef,
+			storage_lock::{StorageLock, Time},
+		},
+		FixedPointNumber, FixedU128, SaturatedConversion,
 	};
 	use sp_std::{prelude::*, str};
 
@@ -62,9 +92,74 @@ pub mod pallet {
 		type RuntimeCall: From<frame_system::Call<Self>>;
 
 		type Timestamp: UnixTime;
+
+		/// The origin which may update the chain configuration and slash relayers.
+		type UpdateOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+		/// The currency relayers bond their stake in.
+		type Currency: ReservableCurrency<Self::AccountId>;
+
+		/// The stake reserved from an account to register as relayer.
+		#[pallet::constant]
+		type RelayerStake: Get<BalanceOf<Self>>;
+
+		/// Number of blocks the stake of a relayer that left stays reserved, and can be slashed,
+		/// before it is withdrawn.
+		#[pallet::constant]
+		type UnbondingPeriod: Get<BlockNumberFor<Self>>;
+
+		/// The maximum number of finalizers registered for a chain.
+		#[pallet::constant]
+		type MaxFinalizers: Get<u32>;
+
+		/// Handler for the slashed relayer stakes.
+		type Slash: OnUnbalanced<NegativeImbalanceOf<Self>>;
+
+		/// Signing service the relayers can request quorum signatures from, it reports the
+		/// signatures back through the `SignatureReceiver` impl of the pallet. With `()` the
+		/// requests are rejected.
+		type SignatureRequester: SignatureRequester;
+
+		/// Pays out the withdrawals processed on the remote chains.
+		type WithdrawalReleaser: WithdrawalReleaser<Self::AccountId>;
+
+		/// The chain id of this chain, the messages registered on the remote chains for it are
+		/// passed to the `InboundMessageHandler`.
+		#[pallet::constant]
+		type LocalChainId: Get<u64>;
+
+		/// Reacts to the messages registered on the remote chains for this chain.
+		type InboundMessageHandler: InboundMessageHandler;
+
+		/// Number of blocks of an accounting era, the solvency of the custody pools is recorded
+		/// at the start of every era.
+		#[pallet::constant]
+		type AccountingPeriod: Get<BlockNumberFor<Self>>;
+
+		/// Pays out locally the rewards distributed by the master staking contracts of the
+		/// remote chains.
+		type RewardsHandler: RewardsHandler;
+
+		/// Number of blocks the reports of an item stay counted while short of
+		/// `FinalizerThreshold`, they are dropped after.
+		#[pallet::constant]
+		type AttestationPeriod: Get<BlockNumberFor<Self>>;
+
+		/// Weight information for the extrinsics of the pallet.
+		type WeightInfo: WeightInfo;
 	}
 
+	pub type BalanceOf<T> =
+		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+	pub type NegativeImbalanceOf<T> = <<T as Config>::Currency as Currency<
+		<T as frame_system::Config>::AccountId,
+	>>::NegativeImbalance;
+
+	/// The in-code storage version.
+	const STORAGE_VERSION: StorageVersion = StorageVersion::new(4);
+
 	#[pallet::pallet]
+	#[pallet::storage_version(STORAGE_VERSION)]
 	#[pallet::without_storage_info]
 	pub struct Pallet<T>(_);
 
@@ -72,7 +167,33 @@ pub mod pallet {
 	pub enum Error<T> {
 		/// A finalizer was not found
 		FinalizerNotFound,
+		/// The chain has `MaxFinalizers` finalizers registered already
+		TooManyFinalizers,
 		OffchainUnsignedTxSignedPayload,
+		/// No configuration exists for the chain
+		ChainConfigNotFound,
+		/// The account is already registered as relayer
+		RelayerAlreadyRegistered,
+		/// The signer address is already used by another relayer
+		RelayerSignerInUse,
+		/// The account is not a registered relayer
+		RelayerNotFound,
+		/// The stake of the relayer is still unbonding
+		RelayerUnbonding,
+		/// The account has no unbonding stake
+		NothingUnbonding,
+		/// No withdrawal was recorded for the message
+		WithdrawalNotFound,
+		/// The withdrawal was already paid out
+		WithdrawalAlreadyReleased,
+		/// The caller is not the beneficiary of the withdrawal
+		NotWithdrawalBeneficiary,
+		/// The contract is not allowed on the chain
+		ContractNotAllowed,
+		/// No custody pool is set for the chain
+		CustodyPoolNotFound,
+		/// No master staking contract is set for the chain
+		RewardsSourceNotFound,
 	}
 
 	pub enum OffchainErr {
@@ -103,55 +224,470 @@ pub mod pallet {
 
 	/// Current registered finalizers by chainId
 	#[pallet::storage]
-	pub type RegisteredFinalizers<T> =
-		StorageMap<_, Blake2_128Concat, ChainId, Vec<<T as frame_system::Config>::AccountId>>;
+	pub type RegisteredFinalizers<T: Config> =
+		StorageMap<_, Blake2_128Concat, ChainId, BoundedVec<T::AccountId, T::MaxFinalizers>>;
 
 	/// Current finalizer signature thresholds by chainId
 	#[pallet::storage]
 	pub type FinalizerThreshold<T> = StorageMap<_, Blake2_128Concat, ChainId, u32>;
 
+	/// Chain configuration by chainId, read by the offchain worker to build its clients
+	#[pallet::storage]
+	#[pallet::getter(fn chain_config)]
+	pub type ChainConfigs<T> = StorageMap<_, Blake2_128Concat, ChainId, ChainConfig>;
+
+	/// Contracts the relayers may send transactions to, by chain. A relayer sends nothing to a
+	/// contract missing from here, whatever the chain config or the remote data points it to.
+	#[pallet::storage]
+	pub type AllowedContracts<T> =
+		StorageDoubleMap<_, Blake2_128Concat, ChainId, Blake2_128Concat, H160, ()>;
+
+	/// Registered relayers, with the evm address their offchain worker signs with and the
+	/// stake bonded
+	#[pallet::storage]
+	#[pallet::getter(fn relayer)]
+	pub type Relayers<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, (H160, BalanceOf<T>)>;
+
+	/// Stake of the relayers that left, with the block it can be withdrawn from
+	#[pallet::storage]
+	#[pallet::getter(fn unbonding)]
+	pub type Unbonding<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, (BalanceOf<T>, BlockNumberFor<T>)>;
+
+	/// Relayer account by offchain signer address
+	#[pallet::storage]
+	pub type RelayerSigners<T: Config> = StorageMap<_, Blake2_128Concat, H160, T::AccountId>;
+
+	/// Pending quorum signature requests, with the requesting relayer and the payload hash
+	#[pallet::storage]
+	pub type QuorumSignatureRequests<T: Config> =
+		StorageMap<_, Twox64Concat, u64, (T::AccountId, H256)>;
+
+	/// Quorum signatures ready to be used by the relayers, by request id
+	#[pallet::storage]
+	#[pallet::getter(fn quorum_signature)]
+	pub type QuorumSignatures<T> = StorageMap<_, Twox64Concat, u64, (H256, Vec<u8>)>;
+
+	/// State of the cross-chain messages, by remote chain and message id
+	#[pallet::storage]
+	#[pallet::getter(fn message_state)]
+	pub type MessageStates<T> =
+		StorageDoubleMap<_, Blake2_128Concat, ChainId, Blake2_128Concat, H256, MessageState>;
+
+	/// Pending transactions last mirrored by a relayer, by chainId, with the block number they
+	/// were reported at
+	#[pallet::storage]
+	#[pallet::getter(fn relayer_backlog)]
+	pub type RelayerBacklogs<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		ChainId,
+		Blake2_128Concat,
+		T::AccountId,
+		(Vec<PendingTransaction>, u64),
+	>;
+
+	/// Withdrawals processed on the remote chains, by remote chain and message id, with the
+	/// beneficiary, the amount and whether it was paid out
+	#[pallet::storage]
+	#[pallet::getter(fn withdrawal)]
+	pub type Withdrawals<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		ChainId,
+		Blake2_128Concat,
+		H256,
+		(T::AccountId, u128, bool),
+	>;
+
+	/// Inbound messages passed to the `InboundMessageHandler`, by remote chain and message id,
+	/// with whether the handler succeeded
+	#[pallet::storage]
+	#[pallet::getter(fn inbound_message)]
+	pub type InboundMessages<T> =
+		StorageDoubleMap<_, Blake2_128Concat, ChainId, Blake2_128Concat, H256, bool>;
+
+	/// Custody pool holding the tokens bridged from this chain, by remote chain
+	#[pallet::storage]
+	#[pallet::getter(fn custody_pool)]
+	pub type CustodyPools<T> = StorageMap<_, Blake2_128Concat, ChainId, CustodyPool>;
+
+	/// Value bridged into the custody pool of a remote chain, the amounts of the inbound
+	/// messages less the withdrawals processed on the chain
+	#[pallet::storage]
+	pub type Liabilities<T> = StorageMap<_, Blake2_128Concat, ChainId, u128, ValueQuery>;
+
+	/// Last custody pool balance reported by `FinalizerThreshold` relayers, with the block it was
+	/// reported at
+	#[pallet::storage]
+	pub type ObservedReserves<T> = StorageMap<_, Blake2_128Concat, ChainId, (u128, u64)>;
+
+	/// Solvency of the custody pools, recorded at the start of the last era
+	#[pallet::storage]
+	#[pallet::getter(fn solvency)]
+	pub type Solvency<T> = StorageMap<_, Blake2_128Concat, ChainId, SolvencyRecord>;
+
+	/// Master staking contract of a remote chain, its reward distributions are passed to the
+	/// `RewardsHandler`
+	#[pallet::storage]
+	#[pallet::getter(fn rewards_source)]
+	pub type RewardsSources<T> = StorageMap<_, Blake2_128Concat, ChainId, H160>;
+
+	/// Reward distributions passed to the `RewardsHandler`, by remote chain and distribution id,
+	/// with whether the handler succeeded
+	#[pallet::storage]
+	#[pallet::getter(fn reward_distribution)]
+	pub type RewardDistributions<T> =
+		StorageDoubleMap<_, Blake2_128Concat, ChainId, Twox64Concat, u64, bool>;
+
+	/// Finalizers that reported a message state, a withdrawal receipt, an inbound message, a
+	/// reward distribution or the custody pool balance of a remote chain, by remote chain and item
+	/// hash, with the block the reports expire at, until `FinalizerThreshold` of them agree
+	#[pallet::storage]
+	pub type Attestations<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		ChainId,
+		Identity,
+		H256,
+		(BlockNumberFor<T>, Vec<T::AccountId>),
+	>;
+
+	/// Attestations by the block they expire at, pruned at the start of the block
+	#[pallet::storage]
+	pub type AttestationExpiries<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, BlockNumberFor<T>, Blake2_128Concat, (ChainId, H256), ()>;
+
+	/// Number of blocks a payload signed by a relayer stays valid
+	pub const RELAYER_PAYLOAD_LONGEVITY: u64 = 10;
+
+	/// Priority of the unsigned transactions carrying a payload signed by a relayer, they do
+	/// not pay fees and leave room for the transactions paying for their priority
+	pub const RELAYER_PAYLOAD_PRIORITY: TransactionPriority = TransactionPriority::MAX / 2;
+
 	pub type OffchainResult<A> = Result<A, OffchainErr>;
 
 	impl<T: Config> Pallet<T> {
+		/// Record the solvency of every custody pool for the era starting at `block_number`, a
+		/// pool under a ratio of one is reported with `Undercollateralized`
+		fn record_solvency(block_number: BlockNumberFor<T>) -> Weight {
+			let era = accounting::era::<T>(block_number);
+			let chains: Vec<ChainId> = CustodyPools::<T>::iter_keys().collect();
+			for chain_id in chains.iter().copied() {
+				let reserves =
+					ObservedReserves::<T>::get(chain_id).map_or(0, |(balance, _)| balance);
+				let liabilities = Liabilities::<T>::get(chain_id);
+				let ratio = accounting::solvency_ratio(reserves, liabilities);
+				Solvency::<T>::insert(
+					chain_id,
+					SolvencyRecord { era, reserves, liabilities, ratio },
+				);
+				Self::deposit_event(Event::SolvencyRecorded { chain_id, era, ratio });
+				if ratio < FixedU128::one() {
+					log::warn!(
+						"Custody pool of chain {} holds {} for {} bridged",
+						chain_id,
+						reserves,
+						liabilities
+					);
+					Self::deposit_event(Event::Undercollateralized {
+						chain_id,
+						era,
+						reserves,
+						liabilities,
+					});
+				}
+			}
+			let chains = chains.len() as u64;
+			T::DbWeight::get().reads_writes(1 + 3 * chains, chains)
+		}
+
+		/// Record that `relayer` reported `item` of `remote_chain`, true once `FinalizerThreshold`
+		/// distinct finalizers of the chain reported it within `AttestationPeriod` blocks. Nothing
+		/// is attested for a chain without a threshold, nor by a relayer that is not one of its
+		/// finalizers. Items only differing in content are attested separately, so a single
+		/// relayer cannot alter one.
+		fn attest<I: Encode>(
+			tag: &'static str,
+			remote_chain: ChainId,
+			item: &I,
+			relayer: &T::AccountId,
+		) -> bool {
+			let threshold = match FinalizerThreshold::<T>::get(remote_chain) {
+				Some(threshold) if threshold > 0 => threshold,
+				_ => return false,
+			};
+			let is_finalizer = RegisteredFinalizers::<T>::get(remote_chain)
+				.map_or(false, |finalizers| finalizers.contains(relayer));
+			if !is_finalizer {
+				return false;
+			}
+			let item_hash = H256(keccak_256(&(tag, item).encode()));
+			let (expiry, mut attesters) = Attestations::<T>::get(remote_chain, item_hash)
+				.unwrap_or_else(|| {
+					let expiry = frame_system::Pallet::<T>::block_number()
+						.saturating_add(T::AttestationPeriod::get());
+					AttestationExpiries::<T>::insert(expiry, (remote_chain, item_hash), ());
+					(expiry, Vec::new())
+				});
+			if !attesters.contains(relayer) {
+				attesters.push(relayer.clone());
+			}
+			if attesters.len() as u32 >= threshold {
+				Attestations::<T>::remove(remote_chain, item_hash);
+				AttestationExpiries::<T>::remove(expiry, (remote_chain, item_hash));
+				return true;
+			}
+			let attestations = attesters.len() as u32;
+			Attestations::<T>::insert(remote_chain, item_hash, (expiry, attesters));
+			Self::deposit_event(Event::ItemAttested {
+				remote_chain,
+				item_hash,
+				relayer: relayer.clone(),
+				attestations,
+			});
+			false
+		}
+
+		/// Drop the attestations expiring at `block_number`, still short of their threshold
+		fn prune_attestations(block_number: BlockNumberFor<T>) -> Weight {
+			let mut pruned = 0u64;
+			for ((remote_chain, item_hash), ()) in
+				AttestationExpiries::<T>::drain_prefix(block_number)
+			{
+				Attestations::<T>::remove(remote_chain, item_hash);
+				Self::deposit_event(Event::AttestationExpired { remote_chain, item_hash });
+				pruned += 1;
+			}
+			T::DbWeight::get().reads_writes(1 + pruned, 2 * pruned)
+		}
+
+		/// Address of the offchain signer that signed `hash`
+		pub fn payload_signer(hash: H256, signature: &ecdsa::Signature) -> Option<H160> {
+			let public = sp_io::crypto::secp256k1_ecdsa_recover(&signature.0, &hash.0).ok()?;
+			Some(H160::from_slice(&keccak_256(&public)[12..]))
+		}
+
+		/// Validity of an unsigned transaction carrying a payload signed by a relayer, once per
+		/// `sequence` of the relayer for `chain_id` at `block_number`. The relayers split large
+		/// reports over up to `MAX_PAYLOADS_PER_BLOCK` payloads of the same block.
+		fn validate_payload(
+			tag_prefix: &'static str,
+			hash: H256,
+			signature: &ecdsa::Signature,
+			block_number: u64,
+			chain_id: ChainId,
+			sequence: u8,
+		) -> TransactionValidity {
+			let signer =
+				Self::payload_signer(hash, signature).ok_or(InvalidTransaction::BadProof)?;
+			if !RelayerSigners::<T>::contains_key(signer) {
+				return InvalidTransaction::BadSigner.into();
+			}
+			let current_block: u64 =
+				frame_system::Pallet::<T>::block_number().try_into().unwrap_or_default();
+			if block_number > current_block {
+				return InvalidTransaction::Future.into();
+			}
+			if current_block - block_number > RELAYER_PAYLOAD_LONGEVITY {
+				return InvalidTransaction::Stale.into();
+			}
+			if sequence >= MAX_PAYLOADS_PER_BLOCK {
+				return InvalidTransaction::ExhaustsResources.into();
+			}
+			ValidTransaction::with_tag_prefix(tag_prefix)
+				.priority(RELAYER_PAYLOAD_PRIORITY)
+				.and_provides((signer, chain_id, block_number, sequence))
+				.longevity(RELAYER_PAYLOAD_LONGEVITY)
+				.propagate(true)
+				.build()
+		}
+
 		pub fn configure_network(
 			block_number: u64,
-			network_item: QpNetworkItem,
+			config: ChainConfig,
 			signer_public_key: Vec<u8>,
+			relayer_public_keys: &[Vec<u8>],
+			simulation: bool,
 		) -> QuantumPortalClient<T> {
-			let rpc_endpoint = network_item.url;
-			let id = network_item.id;
-
-			let signer = ChainUtils::hex_to_ecdsa_pub_key(&signer_public_key[..]);
-			let gateway_contract =
-				ChainUtils::hex_to_address(&network_item.gateway_contract_address[..]);
-			let client = ContractClient::new(rpc_endpoint, &gateway_contract, id);
+			let signer = ContractClientSignature::from(ChainUtils::hex_to_ecdsa_pub_key(
+				&signer_public_key[..],
+			));
+			let submitters = SignerPool::new(
+				sp_std::iter::once(signer.clone())
+					.chain(relayer_public_keys.iter().map(|key| {
+						ContractClientSignature::from(ChainUtils::hex_to_ecdsa_pub_key(&key[..]))
+					}))
+					.collect(),
+				config.gas_reserve,
+			);
+			let mut client = ContractClient::new(
+				config.rpc_url.clone(),
+				&config.gateway_contract_address,
+				config.chain_id,
+			);
+			client.ledger_manager_address = config.ledger_manager_address;
+			client.dry_run = simulation;
+			RpcBudget::start(
+				str::from_utf8(&config.rpc_url[..]).unwrap_or_default(),
+				block_number,
+				config.max_rpc_calls,
+			);
 			QuantumPortalClient::new(
 				client,
-				ContractClientSignature::from(signer),
+				signer,
+				submitters,
 				sp_io::offchain::timestamp().unix_millis(),
 				block_number,
+				AllowedContracts::<T>::iter_key_prefix(config.chain_id).collect(),
+				config,
 			)
 		}
 
+		/// Merge the on-chain chain configuration with the locally configured networks, local
+		/// rpc endpoints take precedence since they may carry private credentials.
+		pub fn chain_configs(network_vec: Vec<QpNetworkItem>) -> Vec<ChainConfig> {
+			let mut configs: Vec<ChainConfig> = ChainConfigs::<T>::iter_values().collect();
+			for item in network_vec {
+				match configs.iter_mut().find(|c| c.chain_id == item.id) {
+					Some(config) => config.rpc_url = item.url,
+					None => configs.push(item.into()),
+				}
+			}
+			configs
+		}
+
+		/// Pairs to relay: the configured pairs whose chains both have a configuration, or every
+		/// pair of configured chains when no pair is configured. Chains added on-chain are
+		/// picked up on the next run, without restarting the node.
+		pub fn chain_pairs(pair_vec: Vec<(u64, u64)>, configs: &[ChainConfig]) -> Vec<(u64, u64)> {
+			let configured = |chain_id: u64| configs.iter().any(|c| c.chain_id == chain_id);
+			let pairs: Vec<(u64, u64)> = if pair_vec.is_empty() {
+				configs
+					.iter()
+					.flat_map(|remote| {
+						configs
+							.iter()
+							.filter(move |local| local.chain_id != remote.chain_id)
+							.map(move |local| (remote.chain_id, local.chain_id))
+					})
+					.collect()
+			} else {
+				pair_vec
+					.into_iter()
+					.filter(|(remote_chain, local_chain)| {
+						configured(*remote_chain) && configured(*local_chain)
+					})
+					.collect()
+			};
+
+			let last_pairs = StorageValueRef::persistent(CHAIN_PAIRS_KEY);
+			if last_pairs.get::<Vec<(u64, u64)>>().ok().flatten().as_ref() != Some(&pairs) {
+				log::info!("Relaying chain pairs {:?}", pairs);
+				last_pairs.set(&pairs);
+			}
+			pairs
+		}
+
 		pub fn test_qp(
 			block_number: u64,
 			qp_config_item: qp_types::QpConfig,
 		) -> OffchainResult<()> {
-			let client_vec: Vec<_> = qp_config_item
-				.network_vec
+			// only registered relayers submit transactions
+			let signer = ChainUtils::hex_to_ecdsa_pub_key(&qp_config_item.signer_public_key[..]);
+			let signer_address = ContractClientSignature::from(signer).from;
+			if !RelayerSigners::<T>::contains_key(signer_address) {
+				log::info!("Signer {:?} is not a registered relayer, skipping", signer_address);
+				return Ok(());
+			}
+
+			let simulation = StorageValueRef::persistent(SIMULATION_MODE_KEY)
+				.get::<bool>()
+				.ok()
+				.flatten()
+				.unwrap_or_default();
+			if simulation {
+				log::info!("Simulation mode enabled, transactions will not be broadcast");
+			}
+
+			let configs = Self::chain_configs(qp_config_item.network_vec);
+			let pairs = Self::chain_pairs(qp_config_item.pair_vec, &configs);
+			// ensure pairs configured are within limit
+			if pairs.len() > MAX_PAIRS_TO_MINE {
+				log::info!("Too many pairs configured, this may lead to performance issues, maximum allowed is {:?}, Exiting", MAX_PAIRS_TO_MINE);
+				return Ok(());
+			}
+
+			// a recorded run put in storage is replayed instead of relaying, for the block and
+			// time it was recorded at
+			let mut replay = StorageValueRef::persistent(REPLAY_KEY);
+			let recording = replay.get::<PairRecording>().ok().flatten();
+			if recording.is_some() {
+				replay.clear();
+			}
+			let block_number = recording.as_ref().map_or(block_number, |r| r.block_number);
+			let simulation = recording.as_ref().map_or(simulation, |r| r.simulation);
+
+			let mut client_vec: Vec<_> = configs
 				.into_iter()
-				.map(|item| {
+				.map(|config| {
 					Self::configure_network(
 						block_number,
-						item,
+						config,
 						qp_config_item.signer_public_key.clone(),
+						&qp_config_item.relayer_public_keys,
+						simulation,
 					)
 				})
 				.collect();
 
+			if let Some(recording) = recording {
+				for client in client_vec.iter_mut() {
+					client.now = recording.now;
+				}
+				let report = QuantumPortalService::<T>::new(client_vec).replay(recording);
+				StorageValueRef::persistent(REPLAY_REPORT_KEY).set(&report);
+				return Ok(());
+			}
+
+			// each pair runs every `run_interval` blocks, at its own offset
+			let pairs: Vec<(u64, u64)> = pairs
+				.into_iter()
+				.enumerate()
+				.filter(|(index, _)| qp_config_item.runs_pair_at(*index, block_number))
+				.map(|(_, pair)| pair)
+				.collect();
+			if pairs.is_empty() {
+				log::info!("No pair scheduled at block {}", block_number);
+				return Ok(());
+			}
+
 			let svc = QuantumPortalService::<T>::new(client_vec);
-			let _res: Vec<_> = qp_config_item
-				.pair_vec
+
+			// drop a recording left by a worker that stopped half way through a pair
+			Recorder::finish();
+			svc.recover_journals();
+
+			// check the pending transactions of every pair first, so they get the rpc budget
+			// before any new work is started
+			let mut local_chains: Vec<u64> =
+				pairs.iter().map(|(_, local_chain)| *local_chain).collect();
+			local_chains.sort_unstable();
+			local_chains.dedup();
+			svc.check_reorgs(&local_chains);
+			svc.run_tx_commands(&local_chains);
+			svc.refresh_pending_transactions(&local_chains);
+			svc.check_relayer_balances(&local_chains);
+			svc.mirror_pending_transactions(&local_chains);
+			svc.ingest_inbound_messages(T::LocalChainId::get());
+			svc.report_reserves(
+				&CustodyPools::<T>::iter().collect::<Vec<_>>(),
+				accounting::era::<T>(block_number.saturated_into()),
+			);
+			svc.ingest_reward_distributions(&RewardsSources::<T>::iter().collect::<Vec<_>>());
+
+			let _res: Vec<_> = pairs
 				.into_iter()
 				.map(|(remote_chain, local_chain)| {
 					let proces_pair_res = svc.process_pair_with_lock(
@@ -160,7 +696,12 @@ pub mod pallet {
 						qp_config_item.role.clone(),
 					);
 					if let Err(e) = proces_pair_res {
-						log::warn!("Error : {:?}", e,)
+						log::warn!(
+							"Error processing pair {} -> {} : {:?}",
+							remote_chain,
+							local_chain,
+							e,
+						)
 					}
 				})
 				.collect();
@@ -170,6 +711,14 @@ pub mod pallet {
 
 	#[pallet::hooks]
 	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_initialize(block_number: BlockNumberFor<T>) -> Weight {
+			let weight = Self::prune_attestations(block_number);
+			if !accounting::is_era_start::<T>(block_number) {
+				return weight;
+			}
+			weight.saturating_add(Self::record_solvency(block_number))
+		}
+
 		fn offchain_worker(block_number: BlockNumberFor<T>) {
 			log::info!("OffchainWorker : Start Execution");
 			log::info!("Reading configuration from storage");
@@ -199,12 +748,6 @@ pub mod pallet {
 						return;
 					}
 
-					// ensure pairs configured are within limit
-					if config.pair_vec.len() > MAX_PAIRS_TO_MINE {
-						log::info!("Too many pairs configured, this may lead to performance issues, maximum allowed is {:?}, Exiting", MAX_PAIRS_TO_MINE);
-						return;
-					}
-
 					let now = block_number.try_into().map_or(0_u64, |f| f);
 					log::info!("Current block: {:?}", block_number);
 					if let Err(e) = Self::test_qp(now, config) {
@@ -237,39 +780,122 @@ pub mod pallet {
 		},
 		/// Finalizer threshold set
 		FinalizerThresholdSet { chain_id: ChainId, threshold: u32 },
+		/// Chain configuration set
+		ChainConfigSet { chain_id: ChainId },
+		/// Chain configuration removed
+		ChainConfigRemoved { chain_id: ChainId },
+		/// A relayer bonded its stake
+		RelayerRegistered { relayer: T::AccountId, signer: H160, stake: BalanceOf<T> },
+		/// A relayer left, its stake can be withdrawn from `release_block`
+		RelayerUnregistered { relayer: T::AccountId, release_block: BlockNumberFor<T> },
+		/// A relayer withdrew its unbonded stake
+		StakeWithdrawn { relayer: T::AccountId, amount: BalanceOf<T> },
+		/// A relayer requested a quorum signature
+		QuorumSignatureRequested { request_id: u64, relayer: T::AccountId, payload_hash: H256 },
+		/// A requested quorum signature is ready
+		QuorumSignatureReady { request_id: u64, payload_hash: H256 },
+		/// A relayer reported a new state for a cross-chain message
+		MessageStateUpdated { remote_chain: ChainId, message_id: H256, state: MessageState },
+		/// A relayer mirrored its pending transactions on a chain
+		PendingTransactionsMirrored { relayer: T::AccountId, chain_id: ChainId, count: u32 },
+		/// A relayer reported an item of a remote chain still short of `FinalizerThreshold`
+		/// attestations
+		ItemAttested {
+			remote_chain: ChainId,
+			item_hash: H256,
+			relayer: T::AccountId,
+			attestations: u32,
+		},
+		/// The reports of an item of a remote chain expired short of `FinalizerThreshold`
+		AttestationExpired { remote_chain: ChainId, item_hash: H256 },
+		/// A withdrawal processed on a remote chain was recorded
+		WithdrawalRecorded {
+			remote_chain: ChainId,
+			message_id: H256,
+			beneficiary: T::AccountId,
+			amount: u128,
+		},
+		/// A withdrawal was paid out to its beneficiary
+		WithdrawalReleased {
+			remote_chain: ChainId,
+			message_id: H256,
+			beneficiary: T::AccountId,
+			amount: u128,
+		},
+		/// A relayer was slashed for submitting an invalid block
+		RelayerSlashed {
+			relayer: T::AccountId,
+			chain_id: ChainId,
+			block_nonce: u64,
+			amount: BalanceOf<T>,
+		},
+		/// An inbound message was handled by the `InboundMessageHandler`
+		InboundMessageHandled { remote_chain: ChainId, message_id: H256 },
+		/// The `InboundMessageHandler` failed on an inbound message, its effects were rolled
+		/// back
+		InboundMessageFailed { remote_chain: ChainId, message_id: H256, error: DispatchError },
+		/// Relayers may send transactions to the contract
+		ContractAllowed { chain_id: ChainId, contract: H160 },
+		/// Relayers no longer send transactions to the contract
+		ContractDisallowed { chain_id: ChainId, contract: H160 },
+		/// Custody pool of the chain set
+		CustodyPoolSet { chain_id: ChainId, pool: CustodyPool },
+		/// Custody pool of the chain removed
+		CustodyPoolRemoved { chain_id: ChainId },
+		/// The balance of a custody pool reported by the relayers was recorded
+		ReservesReported { chain_id: ChainId, balance: u128 },
+		/// The solvency of a custody pool was recorded for the era
+		SolvencyRecorded { chain_id: ChainId, era: u32, ratio: FixedU128 },
+		/// A custody pool holds less than the value bridged into it
+		Undercollateralized { chain_id: ChainId, era: u32, reserves: u128, liabilities: u128 },
+		/// Master staking contract of the chain set
+		RewardsSourceSet { chain_id: ChainId, contract: H160 },
+		/// Master staking contract of the chain removed
+		RewardsSourceRemoved { chain_id: ChainId },
+		/// A reward distribution was paid out by the `RewardsHandler`
+		RewardsDistributed { remote_chain: ChainId, distribution_id: u64, accounts: u32 },
+		/// The `RewardsHandler` failed on a reward distribution, its effects were rolled back
+		RewardsDistributionFailed {
+			remote_chain: ChainId,
+			distribution_id: u64,
+			error: DispatchError,
+		},
 	}
 
 	#[pallet::call]
 	impl<T: Config> Pallet<T> {
 		#[pallet::call_index(0)]
-		#[pallet::weight(0)]
+		#[pallet::weight(T::WeightInfo::register_finalizer())]
 		pub fn register_finalizer(
 			origin: OriginFor<T>,
 			chain_id: ChainId,
 			finalizer: T::AccountId,
 		) -> DispatchResult {
-			let caller = ensure_signed(origin);
+			T::UpdateOrigin::ensure_origin(origin)?;
 
 			RegisteredFinalizers::<T>::try_mutate(
 				chain_id,
 				|current_finalizers| -> DispatchResult {
 					let current_finalizers =
 						current_finalizers.get_or_insert_with(Default::default);
-					current_finalizers.push(finalizer.clone());
-					//Self::deposit_event(Event::FinalizerAdded { chain_id, finalizer });
+					current_finalizers
+						.try_push(finalizer.clone())
+						.map_err(|_| Error::<T>::TooManyFinalizers)?;
 					Ok(())
 				},
-			)
+			)?;
+			Self::deposit_event(Event::FinalizerAdded { chain_id, finalizer });
+			Ok(())
 		}
 
 		#[pallet::call_index(1)]
-		#[pallet::weight(0)]
+		#[pallet::weight(T::WeightInfo::remove_finalizer())]
 		pub fn remove_finalizer(
 			origin: OriginFor<T>,
 			chain_id: ChainId,
 			finalizer: T::AccountId,
 		) -> DispatchResult {
-			let caller = ensure_signed(origin);
+			T::UpdateOrigin::ensure_origin(origin)?;
 
 			RegisteredFinalizers::<T>::try_mutate(
 				chain_id,
@@ -281,14 +907,15 @@ pub mod pallet {
 						.position(|x| *x == finalizer.clone())
 						.ok_or(Error::<T>::FinalizerNotFound)?;
 					current_finalizers.remove(index);
-					//Self::deposit_event(Event::FinalizerRemoved { chain_id, finalizer });
 					Ok(())
 				},
-			)
+			)?;
+			Self::deposit_event(Event::FinalizerRemoved { chain_id, finalizer });
+			Ok(())
 		}
 
 		#[pallet::call_index(2)]
-		#[pallet::weight(0)]
+		#[pallet::weight(T::WeightInfo::submit_signature(signature.len() as u32))]
 		pub fn submit_signature(
 			origin: OriginFor<T>,
 			chain_id: ChainId,
@@ -321,16 +948,580 @@ pub mod pallet {
 		}
 
 		#[pallet::call_index(3)]
-		#[pallet::weight(0)]
+		#[pallet::weight(T::WeightInfo::set_finalizer_threshold())]
 		pub fn set_finalizer_threshold(
 			origin: OriginFor<T>,
 			chain_id: ChainId,
 			threshold: u32,
 		) -> DispatchResult {
-			let caller = ensure_signed(origin);
+			T::UpdateOrigin::ensure_origin(origin)?;
 			FinalizerThreshold::<T>::insert(chain_id, threshold);
 			Self::deposit_event(Event::FinalizerThresholdSet { chain_id, threshold });
 			Ok(())
 		}
+
+		#[pallet::call_index(4)]
+		#[pallet::weight(T::WeightInfo::set_chain_config())]
+		pub fn set_chain_config(origin: OriginFor<T>, config: ChainConfig) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			let chain_id = config.chain_id;
+			ChainConfigs::<T>::insert(chain_id, config);
+			Self::deposit_event(Event::ChainConfigSet { chain_id });
+			Ok(())
+		}
+
+		#[pallet::call_index(5)]
+		#[pallet::weight(T::WeightInfo::remove_chain_config())]
+		pub fn remove_chain_config(origin: OriginFor<T>, chain_id: ChainId) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			ensure!(ChainConfigs::<T>::contains_key(chain_id), Error::<T>::ChainConfigNotFound);
+			ChainConfigs::<T>::remove(chain_id);
+			Self::deposit_event(Event::ChainConfigRemoved { chain_id });
+			Ok(())
+		}
+
+		/// Bond `RelayerStake` to relay with the offchain signer at `signer`
+		#[pallet::call_index(6)]
+		#[pallet::weight(T::WeightInfo::register_relayer())]
+		pub fn register_relayer(origin: OriginFor<T>, signer: H160) -> DispatchResult {
+			let relayer = ensure_signed(origin)?;
+			ensure!(!Relayers::<T>::contains_key(&relayer), Error::<T>::RelayerAlreadyRegistered);
+			ensure!(!Unbonding::<T>::contains_key(&relayer), Error::<T>::RelayerUnbonding);
+			ensure!(!RelayerSigners::<T>::contains_key(signer), Error::<T>::RelayerSignerInUse);
+
+			let stake = T::RelayerStake::get();
+			T::Currency::reserve(&relayer, stake)?;
+			Relayers::<T>::insert(&relayer, (signer, stake));
+			RelayerSigners::<T>::insert(signer, &relayer);
+			Self::deposit_event(Event::RelayerRegistered { relayer, signer, stake });
+			Ok(())
+		}
+
+		/// Stop relaying, the stake stays reserved for `UnbondingPeriod` blocks before it can be
+		/// withdrawn with `withdraw_unbonded`
+		#[pallet::call_index(7)]
+		#[pallet::weight(T::WeightInfo::unregister_relayer())]
+		pub fn unregister_relayer(origin: OriginFor<T>) -> DispatchResult {
+			let relayer = ensure_signed(origin)?;
+			let (signer, stake) =
+				Relayers::<T>::take(&relayer).ok_or(Error::<T>::RelayerNotFound)?;
+			RelayerSigners::<T>::remove(signer);
+			let release_block =
+				frame_system::Pallet::<T>::block_number().saturating_add(T::UnbondingPeriod::get());
+			Unbonding::<T>::insert(&relayer, (stake, release_block));
+			Self::deposit_event(Event::RelayerUnregistered { relayer, release_block });
+			Ok(())
+		}
+
+		/// Slash the stake of a relayer that submitted an invalid block and remove it, the stake
+		/// of a relayer that left is slashed until it is withdrawn
+		#[pallet::call_index(8)]
+		#[pallet::weight(T::WeightInfo::report_relayer())]
+		pub fn report_relayer(
+			origin: OriginFor<T>,
+			relayer: T::AccountId,
+			chain_id: ChainId,
+			block_nonce: u64,
+		) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			let stake = match Relayers::<T>::take(&relayer) {
+				Some((signer, stake)) => {
+					RelayerSigners::<T>::remove(signer);
+					stake
+				},
+				None => Unbonding::<T>::take(&relayer)
+					.map(|(stake, _)| stake)
+					.ok_or(Error::<T>::RelayerNotFound)?,
+			};
+			let (imbalance, _) = T::Currency::slash_reserved(&relayer, stake);
+			T::Slash::on_unbalanced(imbalance);
+			Self::deposit_event(Event::RelayerSlashed {
+				relayer,
+				chain_id,
+				block_nonce,
+				amount: stake,
+			});
+			Ok(())
+		}
+
+		/// Request a signature of `payload`, e.g. an outbound evm transaction hash, from the
+		/// validator quorum instead of a locally held key
+		#[pallet::call_index(9)]
+		#[pallet::weight(T::WeightInfo::request_quorum_signature(payload.len() as u32))]
+		pub fn request_quorum_signature(
+			origin: OriginFor<T>,
+			payload: BoundedVec<u8, ConstU32<MAX_SIGNING_PAYLOAD_LEN>>,
+		) -> DispatchResult {
+			let relayer = ensure_signed(origin)?;
+			ensure!(Relayers::<T>::contains_key(&relayer), Error::<T>::RelayerNotFound);
+
+			let payload_hash = H256::from(keccak_256(&payload));
+			let request_id = T::SignatureRequester::request_signature(payload.into_inner())?;
+			QuorumSignatureRequests::<T>::insert(request_id, (relayer.clone(), payload_hash));
+			Self::deposit_event(Event::QuorumSignatureRequested {
+				request_id,
+				relayer,
+				payload_hash,
+			});
+			Ok(())
+		}
+
+		/// Record the message states reported by a relayer, once `FinalizerThreshold` relayers
+		/// reported the same state of a message. The payload is signed by the offchain signer of
+		/// the relayer, checked in `validate_unsigned`.
+		#[pallet::call_index(10)]
+		#[pallet::weight(T::WeightInfo::update_message_states(payload.updates.len() as u32))]
+		pub fn update_message_states(
+			origin: OriginFor<T>,
+			payload: MessageStatePayload,
+			signature: ecdsa::Signature,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			let signer = Self::payload_signer(payload.signing_hash(), &signature)
+				.ok_or(Error::<T>::OffchainUnsignedTxSignedPayload)?;
+			let relayer = RelayerSigners::<T>::get(signer).ok_or(Error::<T>::RelayerNotFound)?;
+
+			let remote_chain = payload.remote_chain;
+			for (message_id, state) in payload.updates {
+				let allowed = match MessageStates::<T>::get(remote_chain, message_id) {
+					Some(current) => current.can_transition_to(state),
+					None => true,
+				};
+				if !allowed ||
+					!Self::attest(
+						"QuantumPortalMessageStates",
+						remote_chain,
+						&(message_id, state),
+						&relayer,
+					) {
+					continue;
+				}
+				MessageStates::<T>::insert(remote_chain, message_id, state);
+				Self::deposit_event(Event::MessageStateUpdated { remote_chain, message_id, state });
+			}
+			Ok(())
+		}
+
+		/// Mirror the pending transactions of a relayer on `payload.chain_id`, so its backlog
+		/// is visible on chain. The payload is signed by the offchain signer of the relayer,
+		/// checked in `validate_unsigned`.
+		#[pallet::call_index(11)]
+		#[pallet::weight(T::WeightInfo::mirror_pending_transactions(payload.pending.len() as u32))]
+		pub fn mirror_pending_transactions(
+			origin: OriginFor<T>,
+			payload: PendingTransactionsPayload,
+			signature: ecdsa::Signature,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			let signer = Self::payload_signer(payload.signing_hash(), &signature)
+				.ok_or(Error::<T>::OffchainUnsignedTxSignedPayload)?;
+			let relayer = RelayerSigners::<T>::get(signer).ok_or(Error::<T>::RelayerNotFound)?;
+
+			let chain_id = payload.chain_id;
+			let count = payload.pending.len() as u32;
+			if payload.pending.is_empty() {
+				RelayerBacklogs::<T>::remove(chain_id, &relayer);
+			} else {
+				RelayerBacklogs::<T>::insert(
+					chain_id,
+					&relayer,
+					(payload.pending.into_inner(), payload.block_number),
+				);
+			}
+			Self::deposit_event(Event::PendingTransactionsMirrored { relayer, chain_id, count });
+			Ok(())
+		}
+
+		/// Record the withdrawals processed on `payload.remote_chain` and pay them out. Only the
+		/// receipts of messages reported as finalized are recorded, once `FinalizerThreshold`
+		/// relayers reported them, a withdrawal that cannot be paid out right away is left for its
+		/// beneficiary to `claim_withdrawal`. The payload is signed by the offchain signer of the
+		/// relayer, checked in `validate_unsigned`.
+		#[pallet::call_index(12)]
+		#[pallet::weight(T::WeightInfo::record_withdrawal_receipts(payload.receipts.len() as u32))]
+		pub fn record_withdrawal_receipts(
+			origin: OriginFor<T>,
+			payload: WithdrawalReceiptsPayload<T::AccountId>,
+			signature: ecdsa::Signature,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			let signer = Self::payload_signer(payload.signing_hash(), &signature)
+				.ok_or(Error::<T>::OffchainUnsignedTxSignedPayload)?;
+			let relayer = RelayerSigners::<T>::get(signer).ok_or(Error::<T>::RelayerNotFound)?;
+
+			let remote_chain = payload.remote_chain;
+			for receipt in payload.receipts {
+				let message_id = receipt.message_id;
+				let finalized = MessageStates::<T>::get(remote_chain, message_id) ==
+					Some(MessageState::Finalized);
+				if !finalized ||
+					Withdrawals::<T>::contains_key(remote_chain, message_id) ||
+					!Self::attest(
+						"QuantumPortalWithdrawalReceipts",
+						remote_chain,
+						&receipt,
+						&relayer,
+					) {
+					continue;
+				}
+				Self::deposit_event(Event::WithdrawalRecorded {
+					remote_chain,
+					message_id,
+					beneficiary: receipt.beneficiary.clone(),
+					amount: receipt.amount,
+				});
+				Liabilities::<T>::mutate(remote_chain, |bridged| {
+					*bridged = bridged.saturating_sub(receipt.amount)
+				});
+				// a failed release is rolled back and left to be claimed
+				let released = frame_support::storage::with_storage_layer(|| {
+					T::WithdrawalReleaser::release(&receipt.beneficiary, receipt.amount)
+				})
+				.is_ok();
+				Withdrawals::<T>::insert(
+					remote_chain,
+					message_id,
+					(receipt.beneficiary.clone(), receipt.amount, released),
+				);
+				if released {
+					Self::deposit_event(Event::WithdrawalReleased {
+						remote_chain,
+						message_id,
+						beneficiary: receipt.beneficiary,
+						amount: receipt.amount,
+					});
+				}
+			}
+			Ok(())
+		}
+
+		/// Pay out a recorded withdrawal that was not paid out when its receipt was recorded.
+		#[pallet::call_index(13)]
+		#[pallet::weight(T::WeightInfo::claim_withdrawal())]
+		pub fn claim_withdrawal(
+			origin: OriginFor<T>,
+			remote_chain: ChainId,
+			message_id: H256,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let (beneficiary, amount, released) = Withdrawals::<T>::get(remote_chain, message_id)
+				.ok_or(Error::<T>::WithdrawalNotFound)?;
+			ensure!(who == beneficiary, Error::<T>::NotWithdrawalBeneficiary);
+			ensure!(!released, Error::<T>::WithdrawalAlreadyReleased);
+
+			T::WithdrawalReleaser::release(&beneficiary, amount)?;
+			Withdrawals::<T>::insert(remote_chain, message_id, (beneficiary.clone(), amount, true));
+			Self::deposit_event(Event::WithdrawalReleased {
+				remote_chain,
+				message_id,
+				beneficiary,
+				amount,
+			});
+			Ok(())
+		}
+
+		/// Pass the messages registered on `payload.remote_chain` for this chain to the
+		/// `InboundMessageHandler`, once per message reported by `FinalizerThreshold` relayers. A
+		/// failed message is rolled back and not retried. The payload is signed by the offchain
+		/// signer of the relayer, checked in `validate_unsigned`.
+		#[pallet::call_index(14)]
+		#[pallet::weight(T::WeightInfo::record_inbound_messages(
+			payload.messages.len() as u32,
+			payload.methods_len(),
+		))]
+		pub fn record_inbound_messages(
+			origin: OriginFor<T>,
+			payload: InboundMessagesPayload,
+			signature: ecdsa::Signature,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			let signer = Self::payload_signer(payload.signing_hash(), &signature)
+				.ok_or(Error::<T>::OffchainUnsignedTxSignedPayload)?;
+			let relayer = RelayerSigners::<T>::get(signer).ok_or(Error::<T>::RelayerNotFound)?;
+
+			let remote_chain = payload.remote_chain;
+			for message in payload.messages {
+				let message_id = message.message_id;
+				if message.remote_chain != remote_chain ||
+					InboundMessages::<T>::contains_key(remote_chain, message_id) ||
+					!Self::attest(
+						"QuantumPortalInboundMessages",
+						remote_chain,
+						&message,
+						&relayer,
+					) {
+					continue;
+				}
+				// the value is in the custody pool whether the handler succeeds or not
+				Liabilities::<T>::mutate(remote_chain, |bridged| {
+					*bridged = bridged.saturating_add(message.amount)
+				});
+				let handled = frame_support::storage::with_storage_layer(|| {
+					T::InboundMessageHandler::on_message(&message)
+				});
+				InboundMessages::<T>::insert(remote_chain, message_id, handled.is_ok());
+				match handled {
+					Ok(()) => Self::deposit_event(Event::InboundMessageHandled {
+						remote_chain,
+						message_id,
+					}),
+					Err(error) => Self::deposit_event(Event::InboundMessageFailed {
+						remote_chain,
+						message_id,
+						error,
+					}),
+				}
+			}
+			Ok(())
+		}
+
+		/// Let the relayers send transactions to `contract` on `chain_id`
+		#[pallet::call_index(15)]
+		#[pallet::weight(T::WeightInfo::allow_contract())]
+		pub fn allow_contract(
+			origin: OriginFor<T>,
+			chain_id: ChainId,
+			contract: H160,
+		) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			AllowedContracts::<T>::insert(chain_id, contract, ());
+			Self::deposit_event(Event::ContractAllowed { chain_id, contract });
+			Ok(())
+		}
+
+		/// Stop the relayers from sending transactions to `contract` on `chain_id`
+		#[pallet::call_index(16)]
+		#[pallet::weight(T::WeightInfo::disallow_contract())]
+		pub fn disallow_contract(
+			origin: OriginFor<T>,
+			chain_id: ChainId,
+			contract: H160,
+		) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			ensure!(
+				AllowedContracts::<T>::contains_key(chain_id, contract),
+				Error::<T>::ContractNotAllowed
+			);
+			AllowedContracts::<T>::remove(chain_id, contract);
+			Self::deposit_event(Event::ContractDisallowed { chain_id, contract });
+			Ok(())
+		}
+
+		/// Set the custody pool holding the tokens bridged to `chain_id`, its balance is
+		/// reported by the relayers and checked against the value bridged every era
+		#[pallet::call_index(17)]
+		#[pallet::weight(T::WeightInfo::set_custody_pool())]
+		pub fn set_custody_pool(
+			origin: OriginFor<T>,
+			chain_id: ChainId,
+			pool: CustodyPool,
+		) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			CustodyPools::<T>::insert(chain_id, pool);
+			Self::deposit_event(Event::CustodyPoolSet { chain_id, pool });
+			Ok(())
+		}
+
+		#[pallet::call_index(18)]
+		#[pallet::weight(T::WeightInfo::remove_custody_pool())]
+		pub fn remove_custody_pool(origin: OriginFor<T>, chain_id: ChainId) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			ensure!(CustodyPools::<T>::contains_key(chain_id), Error::<T>::CustodyPoolNotFound);
+			CustodyPools::<T>::remove(chain_id);
+			ObservedReserves::<T>::remove(chain_id);
+			Solvency::<T>::remove(chain_id);
+			Self::deposit_event(Event::CustodyPoolRemoved { chain_id });
+			Ok(())
+		}
+
+		/// Record the custody pool balance observed on `payload.chain_id`, once
+		/// `FinalizerThreshold` relayers reported the same balance in the era of
+		/// `payload.block_number`. The payload is signed by the offchain signer of the relayer,
+		/// checked in `validate_unsigned`.
+		#[pallet::call_index(19)]
+		#[pallet::weight(T::WeightInfo::report_reserves())]
+		pub fn report_reserves(
+			origin: OriginFor<T>,
+			payload: ReservesPayload,
+			signature: ecdsa::Signature,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			let signer = Self::payload_signer(payload.signing_hash(), &signature)
+				.ok_or(Error::<T>::OffchainUnsignedTxSignedPayload)?;
+			let relayer = RelayerSigners::<T>::get(signer).ok_or(Error::<T>::RelayerNotFound)?;
+			let chain_id = payload.chain_id;
+			ensure!(CustodyPools::<T>::contains_key(chain_id), Error::<T>::CustodyPoolNotFound);
+
+			let era = accounting::era::<T>(payload.block_number.saturated_into());
+			if !Self::attest("QuantumPortalReserves", chain_id, &(era, payload.balance), &relayer) {
+				return Ok(());
+			}
+			ObservedReserves::<T>::insert(chain_id, (payload.balance, payload.block_number));
+			Self::deposit_event(Event::ReservesReported { chain_id, balance: payload.balance });
+			Ok(())
+		}
+
+		/// Set the master staking contract of `chain_id`, the relayers report its reward
+		/// distributions to be paid out by the `RewardsHandler`
+		#[pallet::call_index(20)]
+		#[pallet::weight(T::WeightInfo::set_rewards_source())]
+		pub fn set_rewards_source(
+			origin: OriginFor<T>,
+			chain_id: ChainId,
+			contract: H160,
+		) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			RewardsSources::<T>::insert(chain_id, contract);
+			Self::deposit_event(Event::RewardsSourceSet { chain_id, contract });
+			Ok(())
+		}
+
+		#[pallet::call_index(21)]
+		#[pallet::weight(T::WeightInfo::remove_rewards_source())]
+		pub fn remove_rewards_source(origin: OriginFor<T>, chain_id: ChainId) -> DispatchResult {
+			T::UpdateOrigin::ensure_origin(origin)?;
+			ensure!(RewardsSources::<T>::contains_key(chain_id), Error::<T>::RewardsSourceNotFound);
+			RewardsSources::<T>::remove(chain_id);
+			Self::deposit_event(Event::RewardsSourceRemoved { chain_id });
+			Ok(())
+		}
+
+		/// Pass the reward distributions of the master staking contract of
+		/// `payload.remote_chain` to the `RewardsHandler`, once per distribution reported by
+		/// `FinalizerThreshold` relayers. A failed distribution is rolled back and not retried. The
+		/// payload is signed by the offchain signer of the relayer, checked in `validate_unsigned`.
+		#[pallet::call_index(22)]
+		#[pallet::weight(T::WeightInfo::record_reward_distributions(
+			payload.distributions.len() as u32,
+			payload.rewards_count(),
+		))]
+		pub fn record_reward_distributions(
+			origin: OriginFor<T>,
+			payload: RewardsPayload,
+			signature: ecdsa::Signature,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+			let signer = Self::payload_signer(payload.signing_hash(), &signature)
+				.ok_or(Error::<T>::OffchainUnsignedTxSignedPayload)?;
+			let relayer = RelayerSigners::<T>::get(signer).ok_or(Error::<T>::RelayerNotFound)?;
+			let remote_chain = payload.remote_chain;
+			ensure!(
+				RewardsSources::<T>::contains_key(remote_chain),
+				Error::<T>::RewardsSourceNotFound
+			);
+
+			for distribution in payload.distributions {
+				let distribution_id = distribution.distribution_id;
+				if distribution.remote_chain != remote_chain ||
+					RewardDistributions::<T>::contains_key(remote_chain, distribution_id) ||
+					!Self::attest("QuantumPortalRewards", remote_chain, &distribution, &relayer)
+				{
+					continue;
+				}
+				let handled = frame_support::storage::with_storage_layer(|| {
+					T::RewardsHandler::on_rewards(&distribution)
+				});
+				RewardDistributions::<T>::insert(remote_chain, distribution_id, handled.is_ok());
+				match handled {
+					Ok(()) => Self::deposit_event(Event::RewardsDistributed {
+						remote_chain,
+						distribution_id,
+						accounts: distribution.rewards.len() as u32,
+					}),
+					Err(error) => Self::deposit_event(Event::RewardsDistributionFailed {
+						remote_chain,
+						distribution_id,
+						error,
+					}),
+				}
+			}
+			Ok(())
+		}
+
+		/// Get back the stake of a relayer that left, once its `UnbondingPeriod` is over
+		#[pallet::call_index(23)]
+		#[pallet::weight(T::WeightInfo::withdraw_unbonded())]
+		pub fn withdraw_unbonded(origin: OriginFor<T>) -> DispatchResult {
+			let relayer = ensure_signed(origin)?;
+			let (amount, release_block) =
+				Unbonding::<T>::get(&relayer).ok_or(Error::<T>::NothingUnbonding)?;
+			ensure!(
+				frame_system::Pallet::<T>::block_number() >= release_block,
+				Error::<T>::RelayerUnbonding
+			);
+			Unbonding::<T>::remove(&relayer);
+			T::Currency::unreserve(&relayer, amount);
+			Self::deposit_event(Event::StakeWithdrawn { relayer, amount });
+			Ok(())
+		}
+	}
+
+	#[pallet::validate_unsigned]
+	impl<T: Config> ValidateUnsigned for Pallet<T> {
+		type Call = Call<T>;
+
+		fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+			match call {
+				Call::update_message_states { payload, signature } => Self::validate_payload(
+					"QuantumPortalMessageStates",
+					payload.signing_hash(),
+					signature,
+					payload.block_number,
+					payload.remote_chain,
+					payload.sequence,
+				),
+				Call::mirror_pending_transactions { payload, signature } => Self::validate_payload(
+					"QuantumPortalPendingTransactions",
+					payload.signing_hash(),
+					signature,
+					payload.block_number,
+					payload.chain_id,
+					0,
+				),
+				Call::record_withdrawal_receipts { payload, signature } => Self::validate_payload(
+					"QuantumPortalWithdrawalReceipts",
+					payload.signing_hash(),
+					signature,
+					payload.block_number,
+					payload.remote_chain,
+					payload.sequence,
+				),
+				Call::record_inbound_messages { payload, signature } => Self::validate_payload(
+					"QuantumPortalInboundMessages",
+					payload.signing_hash(),
+					signature,
+					payload.block_number,
+					payload.remote_chain,
+					payload.sequence,
+				),
+				Call::report_reserves { payload, signature } => Self::validate_payload(
+					"QuantumPortalReserves",
+					payload.signing_hash(),
+					signature,
+					payload.block_number,
+					payload.chain_id,
+					0,
+				),
+				Call::record_reward_distributions { payload, signature } => Self::validate_payload(
+					"QuantumPortalRewards",
+					payload.signing_hash(),
+					signature,
+					payload.block_number,
+					payload.remote_chain,
+					payload.sequence,
+				),
+				_ => InvalidTransaction::Call.into(),
+			}
+		}
+	}
+
+	impl<T: Config> SignatureReceiver for Pallet<T> {
+		fn on_signature_ready(request_id: u64, signature: Vec<u8>) {
+			// ignore signatures we did not request
+			if let Some((_, payload_hash)) = QuorumSignatureRequests::<T>::take(request_id) {
+				QuorumSignatures::<T>::insert(request_id, (payload_hash, signature));
+				Self::deposit_event(Event::QuorumSignatureReady { request_id, payload_hash });
+			}
+		}
 	}
 }
@@ -0,0 +1,422 @@
+// Copyright 2019-2024 Ferrum Inc.
+// This file is part of Ferrum.
+
+// Ferrum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Ferrum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage migrations of the pallet, one module per storage version. A migration only runs when
+//! the on-chain storage version is the one it migrates from, so it can stay in the runtime
+//! migrations until it is removed.
+use crate::pallet::{Config, Pallet};
+use frame_support::{
+	pallet_prelude::*,
+	traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion},
+};
+#[cfg(feature = "try-runtime")]
+use sp_runtime::TryRuntimeError;
+use sp_std::prelude::*;
+
+pub mod v1 {
+	use super::*;
+	use crate::pallet::RegisteredFinalizers;
+
+	/// Bounds the finalizers registered for each chain to `MaxFinalizers`, the finalizers over
+	/// the bound are dropped
+	pub struct MigrateToV1<T>(PhantomData<T>);
+
+	impl<T: Config> OnRuntimeUpgrade for MigrateToV1<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let on_chain = Pallet::<T>::on_chain_storage_version();
+			if on_chain != 0 {
+				log::info!("MigrateToV1 skipped, storage version is {:?}", on_chain);
+				return T::DbWeight::get().reads(1);
+			}
+
+			let max_finalizers = T::MaxFinalizers::get() as usize;
+			let mut translated = 0_u64;
+			RegisteredFinalizers::<T>::translate::<Vec<T::AccountId>, _>(|chain_id, finalizers| {
+				translated += 1;
+				if finalizers.len() > max_finalizers {
+					log::warn!(
+						"Dropping {} finalizers of chain {} over the bound",
+						finalizers.len() - max_finalizers,
+						chain_id
+					);
+				}
+				Some(BoundedVec::truncate_from(finalizers))
+			});
+
+			StorageVersion::new(1).put::<Pallet<T>>();
+			log::info!("MigrateToV1 bounded the finalizers of {} chains", translated);
+			T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+			let chains = RegisteredFinalizers::<T>::iter_keys().count() as u32;
+			Ok(chains.encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+			ensure!(
+				Pallet::<T>::on_chain_storage_version() == 1,
+				"storage version should be 1 after the migration"
+			);
+			let chains = u32::decode(&mut state.as_slice())
+				.map_err(|_| "the pre-upgrade state should decode")?;
+			let keys: Vec<_> = RegisteredFinalizers::<T>::iter_keys().collect();
+			ensure!(keys.len() as u32 == chains, "no chain should lose its finalizers");
+			ensure!(
+				keys.into_iter()
+					.all(|chain_id| RegisteredFinalizers::<T>::try_get(chain_id).is_ok()),
+				"the finalizers of every chain should decode"
+			);
+			Ok(())
+		}
+	}
+}
+
+pub mod v2 {
+	use super::*;
+	use crate::qp_types::{ChainId, GasEstimation, DEFAULT_SPEED_UP_PERCENT};
+	use frame_support::storage_alias;
+	use sp_core::{H160, U256};
+
+	/// `ChainConfig` as stored before the speed up settings
+	#[derive(Decode)]
+	struct ChainConfigV1 {
+		chain_id: ChainId,
+		rpc_url: Vec<u8>,
+		gateway_contract_address: H160,
+		ledger_manager_address: Option<H160>,
+		confirmation_depth: u64,
+		reorg_depth: u64,
+		mine_gas_limit: Option<u64>,
+		finalize_gas_limit: Option<u64>,
+		mine_gas: GasEstimation,
+		finalize_gas: GasEstimation,
+		max_mine_batch_size: u32,
+		mine_batch_gas_budget: Option<u64>,
+		max_rpc_calls: Option<u32>,
+		verification_rpc_urls: Vec<Vec<u8>>,
+		verification_quorum: u32,
+		gas_reserve: Option<U256>,
+		pending_timeout: u64,
+		poll_interval: u64,
+		max_pending_age: Option<u64>,
+		catch_up_threshold: Option<u64>,
+		catch_up_batch_size: u32,
+	}
+
+	/// `ChainConfig` as stored in storage version 2
+	#[derive(Encode, Decode)]
+	pub(super) struct ChainConfigV2 {
+		pub(super) chain_id: ChainId,
+		pub(super) rpc_url: Vec<u8>,
+		pub(super) gateway_contract_address: H160,
+		pub(super) ledger_manager_address: Option<H160>,
+		pub(super) confirmation_depth: u64,
+		pub(super) reorg_depth: u64,
+		pub(super) mine_gas_limit: Option<u64>,
+		pub(super) finalize_gas_limit: Option<u64>,
+		pub(super) mine_gas: GasEstimation,
+		pub(super) finalize_gas: GasEstimation,
+		pub(super) max_mine_batch_size: u32,
+		pub(super) mine_batch_gas_budget: Option<u64>,
+		pub(super) max_rpc_calls: Option<u32>,
+		pub(super) verification_rpc_urls: Vec<Vec<u8>>,
+		pub(super) verification_quorum: u32,
+		pub(super) gas_reserve: Option<U256>,
+		pub(super) pending_timeout: u64,
+		pub(super) poll_interval: u64,
+		pub(super) max_pending_age: Option<u64>,
+		pub(super) catch_up_threshold: Option<u64>,
+		pub(super) catch_up_batch_size: u32,
+		pub(super) speed_up_after: Option<u64>,
+		pub(super) speed_up_percent: u32,
+		pub(super) max_gas_price: Option<U256>,
+	}
+
+	/// The chain configs in the storage version 2 layout
+	#[storage_alias]
+	type ChainConfigs<T: Config> = StorageMap<Pallet<T>, Blake2_128Concat, ChainId, ChainConfigV2>;
+
+	/// Adds the speed up settings to the chain configs, pending transactions are not sped up
+	/// until they are configured
+	pub struct MigrateToV2<T>(PhantomData<T>);
+
+	impl<T: Config> OnRuntimeUpgrade for MigrateToV2<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let on_chain = Pallet::<T>::on_chain_storage_version();
+			if on_chain != 1 {
+				log::info!("MigrateToV2 skipped, storage version is {:?}", on_chain);
+				return T::DbWeight::get().reads(1);
+			}
+
+			let mut translated = 0_u64;
+			ChainConfigs::<T>::translate::<ChainConfigV1, _>(|_, old| {
+				translated += 1;
+				Some(ChainConfigV2 {
+					chain_id: old.chain_id,
+					rpc_url: old.rpc_url,
+					gateway_contract_address: old.gateway_contract_address,
+					ledger_manager_address: old.ledger_manager_address,
+					confirmation_depth: old.confirmation_depth,
+					reorg_depth: old.reorg_depth,
+					mine_gas_limit: old.mine_gas_limit,
+					finalize_gas_limit: old.finalize_gas_limit,
+					mine_gas: old.mine_gas,
+					finalize_gas: old.finalize_gas,
+					max_mine_batch_size: old.max_mine_batch_size,
+					mine_batch_gas_budget: old.mine_batch_gas_budget,
+					max_rpc_calls: old.max_rpc_calls,
+					verification_rpc_urls: old.verification_rpc_urls,
+					verification_quorum: old.verification_quorum,
+					gas_reserve: old.gas_reserve,
+					pending_timeout: old.pending_timeout,
+					poll_interval: old.poll_interval,
+					max_pending_age: old.max_pending_age,
+					catch_up_threshold: old.catch_up_threshold,
+					catch_up_batch_size: old.catch_up_batch_size,
+					speed_up_after: None,
+					speed_up_percent: DEFAULT_SPEED_UP_PERCENT,
+					max_gas_price: None,
+				})
+			});
+
+			StorageVersion::new(2).put::<Pallet<T>>();
+			log::info!("MigrateToV2 translated {} chain configs", translated);
+			T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+			let configs = ChainConfigs::<T>::iter_keys().count() as u32;
+			Ok(configs.encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+			ensure!(
+				Pallet::<T>::on_chain_storage_version() == 2,
+				"storage version should be 2 after the migration"
+			);
+			let configs = u32::decode(&mut state.as_slice())
+				.map_err(|_| "the pre-upgrade state should decode")?;
+			let keys: Vec<_> = ChainConfigs::<T>::iter_keys().collect();
+			ensure!(keys.len() as u32 == configs, "no chain config should be lost");
+			ensure!(
+				keys.into_iter().all(|chain_id| ChainConfigs::<T>::try_get(chain_id).is_ok()),
+				"every chain config should decode"
+			);
+			Ok(())
+		}
+	}
+}
+
+pub mod v3 {
+	use super::{v2::ChainConfigV2, *};
+	use crate::qp_types::{ChainId, GasEstimation};
+	use frame_support::storage_alias;
+	use sp_core::{H160, U256};
+
+	/// `ChainConfig` as stored in storage version 3
+	#[derive(Encode, Decode)]
+	pub(super) struct ChainConfigV3 {
+		pub(super) chain_id: ChainId,
+		pub(super) rpc_url: Vec<u8>,
+		pub(super) gateway_contract_address: H160,
+		pub(super) ledger_manager_address: Option<H160>,
+		pub(super) confirmation_depth: u64,
+		pub(super) reorg_depth: u64,
+		pub(super) mine_gas_limit: Option<u64>,
+		pub(super) finalize_gas_limit: Option<u64>,
+		pub(super) mine_gas: GasEstimation,
+		pub(super) finalize_gas: GasEstimation,
+		pub(super) max_mine_batch_size: u32,
+		pub(super) mine_batch_gas_budget: Option<u64>,
+		pub(super) max_rpc_calls: Option<u32>,
+		pub(super) verification_rpc_urls: Vec<Vec<u8>>,
+		pub(super) verification_quorum: u32,
+		pub(super) gas_reserve: Option<U256>,
+		pub(super) pending_timeout: u64,
+		pub(super) poll_interval: u64,
+		pub(super) max_pending_age: Option<u64>,
+		pub(super) catch_up_threshold: Option<u64>,
+		pub(super) catch_up_batch_size: u32,
+		pub(super) speed_up_after: Option<u64>,
+		pub(super) speed_up_percent: u32,
+		pub(super) max_gas_price: Option<U256>,
+		pub(super) low_balance_threshold: Option<U256>,
+	}
+
+	/// The chain configs in the storage version 3 layout
+	#[storage_alias]
+	type ChainConfigs<T: Config> = StorageMap<Pallet<T>, Blake2_128Concat, ChainId, ChainConfigV3>;
+
+	/// Adds the low balance threshold to the chain configs, relayer keys are not reported until
+	/// it is configured
+	pub struct MigrateToV3<T>(PhantomData<T>);
+
+	impl<T: Config> OnRuntimeUpgrade for MigrateToV3<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let on_chain = Pallet::<T>::on_chain_storage_version();
+			if on_chain != 2 {
+				log::info!("MigrateToV3 skipped, storage version is {:?}", on_chain);
+				return T::DbWeight::get().reads(1);
+			}
+
+			let mut translated = 0_u64;
+			ChainConfigs::<T>::translate::<ChainConfigV2, _>(|_, old| {
+				translated += 1;
+				Some(ChainConfigV3 {
+					chain_id: old.chain_id,
+					rpc_url: old.rpc_url,
+					gateway_contract_address: old.gateway_contract_address,
+					ledger_manager_address: old.ledger_manager_address,
+					confirmation_depth: old.confirmation_depth,
+					reorg_depth: old.reorg_depth,
+					mine_gas_limit: old.mine_gas_limit,
+					finalize_gas_limit: old.finalize_gas_limit,
+					mine_gas: old.mine_gas,
+					finalize_gas: old.finalize_gas,
+					max_mine_batch_size: old.max_mine_batch_size,
+					mine_batch_gas_budget: old.mine_batch_gas_budget,
+					max_rpc_calls: old.max_rpc_calls,
+					verification_rpc_urls: old.verification_rpc_urls,
+					verification_quorum: old.verification_quorum,
+					gas_reserve: old.gas_reserve,
+					pending_timeout: old.pending_timeout,
+					poll_interval: old.poll_interval,
+					max_pending_age: old.max_pending_age,
+					catch_up_threshold: old.catch_up_threshold,
+					catch_up_batch_size: old.catch_up_batch_size,
+					speed_up_after: old.speed_up_after,
+					speed_up_percent: old.speed_up_percent,
+					max_gas_price: old.max_gas_price,
+					low_balance_threshold: None,
+				})
+			});
+
+			StorageVersion::new(3).put::<Pallet<T>>();
+			log::info!("MigrateToV3 translated {} chain configs", translated);
+			T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+			let configs = ChainConfigs::<T>::iter_keys().count() as u32;
+			Ok(configs.encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+			ensure!(
+				Pallet::<T>::on_chain_storage_version() == 3,
+				"storage version should be 3 after the migration"
+			);
+			let configs = u32::decode(&mut state.as_slice())
+				.map_err(|_| "the pre-upgrade state should decode")?;
+			let keys: Vec<_> = ChainConfigs::<T>::iter_keys().collect();
+			ensure!(keys.len() as u32 == configs, "no chain config should be lost");
+			ensure!(
+				keys.into_iter().all(|chain_id| ChainConfigs::<T>::try_get(chain_id).is_ok()),
+				"every chain config should decode"
+			);
+			Ok(())
+		}
+	}
+}
+
+pub mod v4 {
+	use super::{v3::ChainConfigV3, *};
+	use crate::{
+		pallet::ChainConfigs,
+		qp_types::{ChainConfig, Finality},
+	};
+
+	/// Adds the finality mode to the chain configs, the remote blocks stay final once they are
+	/// `confirmation_depth` deep until another mode is configured
+	pub struct MigrateToV4<T>(PhantomData<T>);
+
+	impl<T: Config> OnRuntimeUpgrade for MigrateToV4<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let on_chain = Pallet::<T>::on_chain_storage_version();
+			if on_chain != 3 {
+				log::info!("MigrateToV4 skipped, storage version is {:?}", on_chain);
+				return T::DbWeight::get().reads(1);
+			}
+
+			let mut translated = 0_u64;
+			ChainConfigs::<T>::translate::<ChainConfigV3, _>(|_, old| {
+				translated += 1;
+				Some(ChainConfig {
+					chain_id: old.chain_id,
+					rpc_url: old.rpc_url,
+					gateway_contract_address: old.gateway_contract_address,
+					ledger_manager_address: old.ledger_manager_address,
+					confirmation_depth: old.confirmation_depth,
+					reorg_depth: old.reorg_depth,
+					mine_gas_limit: old.mine_gas_limit,
+					finalize_gas_limit: old.finalize_gas_limit,
+					mine_gas: old.mine_gas,
+					finalize_gas: old.finalize_gas,
+					max_mine_batch_size: old.max_mine_batch_size,
+					mine_batch_gas_budget: old.mine_batch_gas_budget,
+					max_rpc_calls: old.max_rpc_calls,
+					verification_rpc_urls: old.verification_rpc_urls,
+					verification_quorum: old.verification_quorum,
+					gas_reserve: old.gas_reserve,
+					pending_timeout: old.pending_timeout,
+					poll_interval: old.poll_interval,
+					max_pending_age: old.max_pending_age,
+					catch_up_threshold: old.catch_up_threshold,
+					catch_up_batch_size: old.catch_up_batch_size,
+					speed_up_after: old.speed_up_after,
+					speed_up_percent: old.speed_up_percent,
+					max_gas_price: old.max_gas_price,
+					low_balance_threshold: old.low_balance_threshold,
+					finality: Finality::ConfirmationDepth,
+				})
+			});
+
+			StorageVersion::new(4).put::<Pallet<T>>();
+			log::info!("MigrateToV4 translated {} chain configs", translated);
+			T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+			let configs = ChainConfigs::<T>::iter_keys().count() as u32;
+			Ok(configs.encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+			ensure!(
+				Pallet::<T>::on_chain_storage_version() == 4,
+				"storage version should be 4 after the migration"
+			);
+			let configs = u32::decode(&mut state.as_slice())
+				.map_err(|_| "the pre-upgrade state should decode")?;
+			let keys: Vec<_> = ChainConfigs::<T>::iter_keys().collect();
+			ensure!(keys.len() as u32 == configs, "no chain config should be lost");
+			ensure!(
+				keys.into_iter().all(|chain_id| ChainConfigs::<T>::try_get(chain_id).is_ok()),
+				"every chain config should decode"
+			);
+			Ok(())
+		}
+	}
+}
@@ -0,0 +1,179 @@
+// Copyright 2019-2024 Ferrum Inc.
+// This file is part of Ferrum.
+
+// Ferrum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Ferrum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
+#![cfg(test)]
+use super::*;
+use ferrum_primitives::{SignatureRequester, WithdrawalReleaser};
+use frame_support::{
+	construct_runtime, derive_impl, parameter_types,
+	traits::{ConstU32, ConstU64, UnixTime},
+};
+use frame_system::EnsureRoot;
+use sp_runtime::{
+	testing::{TestSignature, TestXt, UintAuthorityId},
+	traits::Extrinsic as ExtrinsicT,
+	BuildStorage, DispatchError,
+};
+
+pub type AccountId = u64;
+pub type Balance = u64;
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+pub const CHARLIE: AccountId = 3;
+pub const LOCAL_CHAIN: u64 = 26100;
+pub const RELAYER_STAKE: Balance = 100;
+pub const UNBONDING_PERIOD: u64 = 10;
+pub const ATTESTATION_PERIOD: u64 = 5;
+
+mod quantum_portal {
+	pub use super::super::*;
+}
+
+#[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+impl frame_system::Config for Runtime {
+	type Block = Block;
+	type AccountData = pallet_balances::AccountData<Balance>;
+}
+
+#[derive_impl(pallet_balances::config_preludes::TestDefaultConfig)]
+impl pallet_balances::Config for Runtime {
+	type AccountStore = System;
+}
+
+/// Time source of the pallet, the offchain worker is not run in the tests
+pub struct MockTime;
+
+impl UnixTime for MockTime {
+	fn now() -> core::time::Duration {
+		core::time::Duration::ZERO
+	}
+}
+
+parameter_types! {
+	/// Whether `MockReleaser` fails the releases
+	pub static ReleaseFails: bool = false;
+	/// Withdrawals paid out by `MockReleaser`
+	pub static Released: Vec<(AccountId, u128)> = vec![];
+	/// Payloads queued for signing with `MockSignatureRequester`, request `n` is at `n - 1`
+	pub static SignatureRequests: Vec<Vec<u8>> = vec![];
+}
+
+/// Records the released withdrawals, or fails them while `ReleaseFails` is set
+pub struct MockReleaser;
+
+impl WithdrawalReleaser<AccountId> for MockReleaser {
+	fn release(beneficiary: &AccountId, amount: u128) -> Result<(), DispatchError> {
+		if ReleaseFails::get() {
+			return Err(DispatchError::Other("release failed"));
+		}
+		Released::mutate(|released| released.push((*beneficiary, amount)));
+		Ok(())
+	}
+}
+
+/// Queues the payloads in `SignatureRequests`, the tests report the signatures back
+pub struct MockSignatureRequester;
+
+impl SignatureRequester for MockSignatureRequester {
+	fn request_signature(payload: Vec<u8>) -> Result<u64, DispatchError> {
+		SignatureRequests::mutate(|requests| {
+			requests.push(payload);
+			Ok(requests.len() as u64)
+		})
+	}
+}
+
+impl Config for Runtime {
+	type RuntimeEvent = RuntimeEvent;
+	type RuntimeCall = RuntimeCall;
+	type Timestamp = MockTime;
+	type UpdateOrigin = EnsureRoot<AccountId>;
+	type Currency = Balances;
+	type RelayerStake = ConstU64<RELAYER_STAKE>;
+	type UnbondingPeriod = ConstU64<UNBONDING_PERIOD>;
+	type MaxFinalizers = ConstU32<2>;
+	type Slash = ();
+	type SignatureRequester = MockSignatureRequester;
+	type WithdrawalReleaser = MockReleaser;
+	type LocalChainId = ConstU64<LOCAL_CHAIN>;
+	type InboundMessageHandler = ();
+	type AccountingPeriod = ConstU64<10>;
+	type RewardsHandler = ();
+	type AttestationPeriod = ConstU64<ATTESTATION_PERIOD>;
+	type WeightInfo = ();
+}
+
+pub type Extrinsic = TestXt<RuntimeCall, ()>;
+
+impl frame_system::offchain::SigningTypes for Runtime {
+	type Public = UintAuthorityId;
+	type Signature = TestSignature;
+}
+
+impl<LocalCall> frame_system::offchain::SendTransactionTypes<LocalCall> for Runtime
+where
+	RuntimeCall: From<LocalCall>,
+{
+	type OverarchingCall = RuntimeCall;
+	type Extrinsic = Extrinsic;
+}
+
+impl<LocalCall> frame_system::offchain::CreateSignedTransaction<LocalCall> for Runtime
+where
+	RuntimeCall: From<LocalCall>,
+{
+	fn create_transaction<C: frame_system::offchain::AppCrypto<Self::Public, Self::Signature>>(
+		call: RuntimeCall,
+		_public: UintAuthorityId,
+		_account: AccountId,
+		nonce: <Runtime as frame_system::Config>::Nonce,
+	) -> Option<(RuntimeCall, <Extrinsic as ExtrinsicT>::SignaturePayload)> {
+		Some((call, (nonce.into(), ())))
+	}
+}
+
+type Block = frame_system::mocking::MockBlock<Runtime>;
+
+construct_runtime!(
+	pub enum Runtime
+	{
+		System: frame_system,
+		Balances: pallet_balances,
+		QuantumPortal: quantum_portal,
+	}
+);
+
+pub struct ExtBuilder;
+
+impl Default for ExtBuilder {
+	fn default() -> Self {
+		ExtBuilder
+	}
+}
+
+impl ExtBuilder {
+	pub fn build(self) -> sp_io::TestExternalities {
+		let mut t = frame_system::GenesisConfig::<Runtime>::default().build_storage().unwrap();
+		pallet_balances::GenesisConfig::<Runtime> {
+			balances: vec![(ALICE, 1_000), (BOB, 1_000), (CHARLIE, 1_000)],
+		}
+		.assimilate_storage(&mut t)
+		.unwrap();
+
+		let mut ext: sp_io::TestExternalities = t.into();
+		ext.execute_with(|| System::set_block_number(1));
+		ext
+	}
+}
@@ -0,0 +1,116 @@
+// Copyright 2019-2024 Ferrum Inc.
+// This file is part of Ferrum.
+
+// Ferrum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Ferrum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Canned json-rpc responses served to the offchain code in tests, in place of the offchain
+//! http api.
+use crate::{
+	chain_queries::HttpTransport,
+	chain_utils::{ChainRequestError, ChainRequestResult, ChainUtils},
+};
+use std::cell::RefCell;
+
+/// A canned response, served once to the first request it matches
+struct Fixture {
+	method: String,
+	/// Selector the `eth_call` data must start with, any call matches when not set
+	selector: Option<String>,
+	status: u16,
+	body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct MockHttp {
+	fixtures: Vec<Fixture>,
+	/// Json-rpc method of every request served
+	requests: Vec<String>,
+}
+
+thread_local! {
+	static MOCK: RefCell<MockHttp> = RefCell::new(MockHttp::default());
+}
+
+/// Drop the fixtures and requests of a previous test
+pub fn reset() {
+	MOCK.with(|mock| *mock.borrow_mut() = MockHttp::default());
+}
+
+/// Answer the next `method` request with the json `result`
+pub fn respond(method: &str, result: &str) {
+	respond_raw(method, 200, &envelope(result));
+}
+
+/// Answer the next `eth_call` to `signature` with the abi encoded `result`
+pub fn respond_call(signature: &[u8], result: &[u8]) {
+	let selector = ChainUtils::bytes_to_hex(&ChainUtils::keccack(signature).as_bytes()[..4]);
+	let result = ChainUtils::bytes_to_hex(result);
+	push(Fixture {
+		method: "eth_call".into(),
+		selector: Some(format!("0x{}", std::str::from_utf8(&selector).unwrap())),
+		status: 200,
+		body: envelope(&format!("\"0x{}\"", std::str::from_utf8(&result).unwrap())).into_bytes(),
+	});
+}
+
+/// Answer the next `method` request with `status` and `body` as is
+pub fn respond_raw(method: &str, status: u16, body: &str) {
+	push(Fixture { method: method.into(), selector: None, status, body: body.into() });
+}
+
+/// Json-rpc methods of the requests served so far
+pub fn requests() -> Vec<String> {
+	MOCK.with(|mock| mock.borrow().requests.clone())
+}
+
+pub fn with_mock<R>(f: impl FnOnce(&dyn HttpTransport) -> R) -> R {
+	f(&MockTransport)
+}
+
+fn envelope(result: &str) -> String {
+	format!("{{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{}}}", result)
+}
+
+fn push(fixture: Fixture) {
+	MOCK.with(|mock| mock.borrow_mut().fixtures.push(fixture));
+}
+
+struct MockTransport;
+
+impl HttpTransport for MockTransport {
+	fn post(&self, _url: &str, body: &[u8]) -> ChainRequestResult<(u16, Vec<u8>)> {
+		let request: serde_json::Value =
+			serde_json::from_slice(body).expect("requests are valid json");
+		let method = request["method"].as_str().unwrap_or_default().to_string();
+		let data = request["params"][0]["data"].as_str().unwrap_or_default().to_string();
+		MOCK.with(|mock| {
+			let mut mock = mock.borrow_mut();
+			mock.requests.push(method.clone());
+			let index = mock.fixtures.iter().position(|fixture| {
+				fixture.method == method &&
+					fixture.selector.as_ref().map_or(true, |s| data.starts_with(s.as_str()))
+			});
+			match index {
+				Some(index) => {
+					let fixture = mock.fixtures.remove(index);
+					Ok((fixture.status, fixture.body))
+				},
+				None => {
+					log::error!("No fixture for {} {}", method, data);
+					Err(ChainRequestError::RpcTransportError)
+				},
+			}
+		})
+	}
+}
@@ -13,10 +13,13 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
+use crate::quantum_portal_service::PendingTransaction;
 use ethabi_nostd::{Address, Token};
+use ferrum_primitives::{InboundMessage, RewardDistribution};
+use frame_support::{traits::ConstU32, BoundedVec};
 use parity_scale_codec::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use sp_core::{H256, U256};
+use sp_core::{H160, H256, U256};
 use sp_std::{prelude::*, str};
 
 pub type ChainId = u64;
@@ -26,6 +29,56 @@ pub type BlockNumber = u64;
 // The current limit is 6, means mining both ways on 3 seperate chains
 pub const MAX_PAIRS_TO_MINE: usize = 6;
 
+// Offchain storage key of the simulation flag, when set to true the relayer builds and estimates
+// transactions without broadcasting them
+pub const SIMULATION_MODE_KEY: &[u8] = b"quantum-portal::simulation_mode";
+
+// Offchain storage key of the recording flag, when set to true every pair run is recorded for
+// replay
+pub const RECORDING_MODE_KEY: &[u8] = b"quantum-portal::recording_mode";
+
+// Offchain storage key of a recorded pair run to replay, the worker replays it on its next run
+// instead of relaying and writes the outcome to `REPLAY_REPORT_KEY`
+pub const REPLAY_KEY: &[u8] = b"quantum-portal::replay";
+
+pub const REPLAY_REPORT_KEY: &[u8] = b"quantum-portal::replay_report";
+
+// Offchain storage key of the chain pairs relayed on the last run
+pub const CHAIN_PAIRS_KEY: &[u8] = b"quantum-portal::chain_pairs";
+
+// Gas limit used for mine transactions when the estimation fails
+pub const DEFAULT_MINE_GAS_LIMIT: u64 = 1_000_000;
+// Gas limit used for finalize transactions when the estimation fails
+pub const DEFAULT_FINALIZE_GAS_LIMIT: u64 = 2_000_000;
+// Margin applied to the gas estimates, in percent of the estimate
+pub const DEFAULT_GAS_MULTIPLIER_PERCENT: u32 = 130;
+// Upper bound of the estimated gas limits
+pub const DEFAULT_GAS_CAP: u64 = 8_000_000;
+// Number of blocks mined per transaction when batching is not configured
+pub const DEFAULT_MAX_MINE_BATCH_SIZE: u32 = 1;
+// Number of blocks mined per transaction while catching up on a backlog
+pub const DEFAULT_CATCH_UP_BATCH_SIZE: u32 = 10;
+// Number of blocks confirmed transactions are checked for reorgs when the chain config does not
+// override it
+pub const DEFAULT_REORG_DEPTH: u64 = 64;
+// Milliseconds a transaction missing from the chain is waited for before it is timed out
+pub const DEFAULT_PENDING_TIMEOUT: u64 = 3600 * 1000;
+// Gas price increase of a sped up transaction, in percent, nodes only accept a replacement
+// paying at least 10% more
+pub const DEFAULT_SPEED_UP_PERCENT: u32 = 20;
+// Maximum number of blocks scanned for events in a single `eth_getLogs` request
+pub const MAX_LOG_BLOCK_RANGE: u64 = 1000;
+// Maximum number of items in a payload signed by a relayer, larger reports are split
+pub const MAX_PAYLOAD_ITEMS: u32 = 128;
+// Maximum number of payloads of a kind a relayer signs for a chain in a block, the rest of a
+// report waits for the next block
+pub const MAX_PAYLOADS_PER_BLOCK: u8 = 16;
+// Maximum length of a payload a quorum signature is requested for
+pub const MAX_SIGNING_PAYLOAD_LEN: u32 = 2048;
+
+/// Items of a payload signed by a relayer
+pub type PayloadItems<T> = BoundedVec<T, ConstU32<MAX_PAYLOAD_ITEMS>>;
+
 #[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct QpTransaction {
@@ -40,6 +93,45 @@ pub struct QpTransaction {
 	pub fixed_fee: U256,
 }
 
+impl QpTransaction {
+	// hash of the abi encoded transaction, used as merkle leaf
+	pub fn hash(&self) -> H256 {
+		let data_to_hash: Vec<Token> = vec![
+			Token::Uint(U256::from(self.timestamp)),
+			Token::Address(self.remote_contract),
+			Token::Address(self.source_msg_sender),
+			Token::Address(self.source_beneficiary),
+			Token::Address(self.token),
+			Token::Uint(self.amount),
+			Token::Bytes(self.method.clone()),
+			Token::Uint(self.gas),
+			Token::Uint(self.fixed_fee),
+		];
+
+		crate::chain_utils::ChainUtils::keccack(&ethabi_nostd::encode(&data_to_hash))
+	}
+}
+
+/// Merkle root of the transactions of a block, an odd node is paired with itself
+pub fn transactions_root(txs: &[QpTransaction]) -> H256 {
+	let mut layer: Vec<H256> = txs.iter().map(|t| t.hash()).collect();
+	if layer.is_empty() {
+		return H256::zero();
+	}
+	while layer.len() > 1 {
+		layer = layer
+			.chunks(2)
+			.map(|pair| {
+				let right = pair.get(1).unwrap_or(&pair[0]);
+				crate::chain_utils::ChainUtils::keccack(
+					&[pair[0].as_bytes(), right.as_bytes()].concat(),
+				)
+			})
+			.collect();
+	}
+	layer[0]
+}
+
 #[derive(Debug)]
 pub struct QpLocalBlock {
 	pub chain_id: u64,
@@ -84,7 +176,20 @@ pub struct QpConfig {
 	pub network_vec: Vec<QpNetworkItem>,
 	pub pair_vec: Vec<(u64, u64)>,
 	pub signer_public_key: Vec<u8>,
+	/// Additional keys transactions are submitted from, in turns with the signer key
+	pub relayer_public_keys: Vec<Vec<u8>>,
 	pub role: Role,
+	/// The pairs are relayed every `run_interval` blocks, every block when 0 or 1
+	pub run_interval: u32,
+}
+
+impl QpConfig {
+	/// Whether the pair at `index` of the relayed pairs runs at `block_number`. Each pair is
+	/// offset by its index, so the pairs are spread over the blocks of the interval.
+	pub fn runs_pair_at(&self, index: usize, block_number: u64) -> bool {
+		let interval = u64::from(self.run_interval.max(1));
+		(block_number + index as u64) % interval == 0
+	}
 }
 
 #[derive(
@@ -98,6 +203,362 @@ pub struct QpNetworkItem {
 	pub id: u64,
 }
 
+/// Lifecycle of a cross-chain message as reported by the relayers
+#[derive(
+	Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Decode, Encode, Debug, scale_info::TypeInfo,
+)]
+pub enum MessageState {
+	/// Seen in a source block waiting to be mined
+	Discovered,
+	/// Mined on the destination chain
+	Mined,
+	/// The block mined on the destination chain is finalized
+	Finalized,
+	/// The mined block failed verification
+	Failed,
+}
+
+impl MessageState {
+	/// States only move forward, `Finalized` and `Failed` are final
+	pub fn can_transition_to(&self, next: MessageState) -> bool {
+		match self {
+			MessageState::Finalized | MessageState::Failed => false,
+			_ => next > *self,
+		}
+	}
+}
+
+/// Message state updates for the messages of `remote_chain`, signed by the offchain signer of a
+/// registered relayer
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct MessageStatePayload {
+	pub remote_chain: ChainId,
+	/// Message id, the hash of the remote transaction, and its new state
+	pub updates: PayloadItems<(H256, MessageState)>,
+	/// Block number of the local chain the payload was created at
+	pub block_number: u64,
+	/// Index of the payload among the ones of the relayer for the chain at `block_number`
+	pub sequence: u8,
+}
+
+impl MessageStatePayload {
+	/// Hash signed by the relayer
+	pub fn signing_hash(&self) -> H256 {
+		H256(sp_io::hashing::keccak_256(&self.encode()))
+	}
+}
+
+/// Pending transactions of a relayer on `chain_id` mirrored on chain, signed by the offchain
+/// signer of a registered relayer
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct PendingTransactionsPayload {
+	pub chain_id: ChainId,
+	pub pending: PayloadItems<PendingTransaction>,
+	/// Block number of the local chain the payload was created at
+	pub block_number: u64,
+}
+
+impl PendingTransactionsPayload {
+	/// Hash signed by the relayer
+	pub fn signing_hash(&self) -> H256 {
+		H256(sp_io::hashing::keccak_256(&self.encode()))
+	}
+}
+
+/// A withdrawal processed on a remote chain, paid out to `beneficiary` on this chain
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct WithdrawalReceipt<AccountId> {
+	/// Id of the message carrying the withdrawal, the hash of the remote transaction
+	pub message_id: H256,
+	pub beneficiary: AccountId,
+	pub amount: u128,
+}
+
+/// Withdrawal receipts of `remote_chain`, signed by the offchain signer of a registered relayer
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct WithdrawalReceiptsPayload<AccountId> {
+	pub remote_chain: ChainId,
+	pub receipts: PayloadItems<WithdrawalReceipt<AccountId>>,
+	/// Block number of the local chain the payload was created at
+	pub block_number: u64,
+	/// Index of the payload among the ones of the relayer for the chain at `block_number`
+	pub sequence: u8,
+}
+
+impl<AccountId: Encode> WithdrawalReceiptsPayload<AccountId> {
+	/// Hash signed by the relayer
+	pub fn signing_hash(&self) -> H256 {
+		H256(sp_io::hashing::keccak_256(&self.encode()))
+	}
+}
+
+/// Messages registered on `remote_chain` for this chain, signed by the offchain signer of a
+/// registered relayer
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct InboundMessagesPayload {
+	pub remote_chain: ChainId,
+	pub messages: PayloadItems<InboundMessage>,
+	/// Block number of the local chain the payload was created at
+	pub block_number: u64,
+	/// Index of the payload among the ones of the relayer for the chain at `block_number`
+	pub sequence: u8,
+}
+
+impl InboundMessagesPayload {
+	/// Hash signed by the relayer
+	pub fn signing_hash(&self) -> H256 {
+		H256(sp_io::hashing::keccak_256(&self.encode()))
+	}
+
+	/// Total length of the methods of the messages
+	pub fn methods_len(&self) -> u32 {
+		self.messages.iter().map(|message| message.method.len() as u32).sum()
+	}
+}
+
+/// Reward distributions of the master staking contract of `remote_chain`, signed by the offchain
+/// signer of a registered relayer
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct RewardsPayload {
+	pub remote_chain: ChainId,
+	pub distributions: PayloadItems<RewardDistribution>,
+	/// Block number of the local chain the payload was created at
+	pub block_number: u64,
+	/// Index of the payload among the ones of the relayer for the chain at `block_number`
+	pub sequence: u8,
+}
+
+impl RewardsPayload {
+	/// Hash signed by the relayer
+	pub fn signing_hash(&self) -> H256 {
+		H256(sp_io::hashing::keccak_256(&self.encode()))
+	}
+
+	/// Total number of accounts rewarded by the distributions
+	pub fn rewards_count(&self) -> u32 {
+		self.distributions
+			.iter()
+			.map(|distribution| distribution.rewards.len() as u32)
+			.sum()
+	}
+}
+
+/// Account holding the tokens bridged from this chain on a remote chain
+#[derive(Clone, Copy, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct CustodyPool {
+	pub address: H160,
+	/// ERC20 token held, the native token when zero
+	pub token: H160,
+}
+
+/// Balance of the custody pool of `chain_id` observed on the chain, signed by the offchain signer
+/// of a registered relayer
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct ReservesPayload {
+	pub chain_id: ChainId,
+	pub balance: u128,
+	/// Block number of the local chain the payload was created at
+	pub block_number: u64,
+}
+
+impl ReservesPayload {
+	/// Hash signed by the relayer
+	pub fn signing_hash(&self) -> H256 {
+		H256(sp_io::hashing::keccak_256(&self.encode()))
+	}
+}
+
+/// Progress of a relayer catching up on the remote blocks of a pair that piled up while it was
+/// offline, kept in persistent offchain storage until the backlog clears
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct CatchUpProgress {
+	pub started_at: u64,
+	/// Blocks waiting to be mined when the catch-up started
+	pub initial_backlog: u64,
+	/// Blocks waiting to be mined on the last run
+	pub backlog: u64,
+}
+
+impl CatchUpProgress {
+	/// Storage key of the catch-up of the `remote_chain -> local_chain` pair
+	pub fn storage_key(remote_chain: u64, local_chain: u64) -> Vec<u8> {
+		let remote = crate::chain_utils::ChainUtils::bytes_to_hex(&remote_chain.to_be_bytes());
+		let local = crate::chain_utils::ChainUtils::bytes_to_hex(&local_chain.to_be_bytes());
+		[b"quantum-portal::catch_up::".as_slice(), remote.as_slice(), b"::", local.as_slice()]
+			.concat()
+	}
+}
+
+/// How the gas limit of a transaction is derived from `eth_estimateGas`
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct GasEstimation {
+	/// Gas limit in percent of the estimate, 100 uses the estimate as is
+	pub multiplier_percent: u32,
+	/// Maximum gas limit, whatever the estimate
+	pub cap: u64,
+	/// Gas limit used when the estimation fails
+	pub fallback: u64,
+}
+
+impl GasEstimation {
+	pub fn new(fallback: u64) -> Self {
+		GasEstimation {
+			multiplier_percent: DEFAULT_GAS_MULTIPLIER_PERCENT,
+			cap: DEFAULT_GAS_CAP,
+			fallback,
+		}
+	}
+
+	/// Applies the margin to `estimate`, bounded by the cap
+	pub fn gas_limit(&self, estimate: U256) -> U256 {
+		let gas_limit = estimate.saturating_mul(U256::from(self.multiplier_percent)) / 100;
+		gas_limit.min(U256::from(self.cap))
+	}
+
+	/// The estimation of a transaction batching `count` of these, bounded by `budget` if set
+	pub fn for_batch(&self, count: u64, budget: Option<u64>) -> Self {
+		let cap = budget.unwrap_or_else(|| self.cap.saturating_mul(count));
+		GasEstimation {
+			multiplier_percent: self.multiplier_percent,
+			cap,
+			fallback: self.fallback.saturating_mul(count).min(cap),
+		}
+	}
+}
+
+/// When a block of a remote chain is final, only the transactions of final blocks are mined
+#[derive(Clone, Copy, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub enum Finality {
+	/// Blocks `confirmation_depth` deep are final
+	ConfirmationDepth,
+	/// Blocks up to the `safe` block of the node are final
+	Safe,
+	/// Blocks up to the `finalized` block of the node are final
+	Finalized,
+}
+
+impl Finality {
+	/// Block tag of the mode, `None` for the confirmation depth heuristic
+	pub fn block_tag(&self) -> Option<&'static [u8]> {
+		match self {
+			Finality::ConfirmationDepth => None,
+			Finality::Safe => Some(b"safe"),
+			Finality::Finalized => Some(b"finalized"),
+		}
+	}
+}
+
+/// Latest final block of a remote chain seen by the relayer, kept in persistent offchain storage
+#[derive(Clone, Copy, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct SafeBlock {
+	pub number: u64,
+	/// Timestamp of the block, in seconds
+	pub timestamp: u64,
+}
+
+impl SafeBlock {
+	/// Storage key of the latest final block of `chain_id`
+	pub fn storage_key(chain_id: ChainId) -> Vec<u8> {
+		let chain = crate::chain_utils::ChainUtils::bytes_to_hex(&chain_id.to_be_bytes());
+		[b"quantum-portal::safe_block::".as_slice(), chain.as_slice()].concat()
+	}
+}
+
+/// On-chain configuration of a chain the relayer interacts with
+#[derive(Clone, Eq, PartialEq, Decode, Encode, Debug, scale_info::TypeInfo)]
+pub struct ChainConfig {
+	pub chain_id: ChainId,
+	/// Default rpc endpoint, a local network item with the same id takes precedence
+	pub rpc_url: Vec<u8>,
+	pub gateway_contract_address: H160,
+	/// Known ledger manager address, fetched from the gateway contract when not set
+	pub ledger_manager_address: Option<H160>,
+	/// Number of blocks a transaction receipt must be buried under before it is confirmed
+	pub confirmation_depth: u64,
+	/// Number of blocks a confirmed transaction is checked for reorgs, not checked when zero
+	pub reorg_depth: u64,
+	/// Gas limit for mine transactions, estimated when not set
+	pub mine_gas_limit: Option<u64>,
+	/// Gas limit for finalize transactions, estimated when not set
+	pub finalize_gas_limit: Option<u64>,
+	/// Estimation of the mine transactions gas limit
+	pub mine_gas: GasEstimation,
+	/// Estimation of the finalize transactions gas limit
+	pub finalize_gas: GasEstimation,
+	/// Maximum number of blocks mined in a single multicall transaction
+	pub max_mine_batch_size: u32,
+	/// Total gas a batched mine transaction may use, unlimited when not set
+	pub mine_batch_gas_budget: Option<u64>,
+	/// Maximum rpc calls to the endpoint per offchain worker run, unlimited when not set
+	pub max_rpc_calls: Option<u32>,
+	/// Additional rpc endpoints the source blocks are checked against before finalizing
+	pub verification_rpc_urls: Vec<Vec<u8>>,
+	/// Number of endpoints, the primary one included, that must agree on a source block
+	pub verification_quorum: u32,
+	/// Balance a relayer key keeps for gas, keys under it are not submitted from
+	pub gas_reserve: Option<U256>,
+	/// Milliseconds a transaction missing from the chain is waited for before it is timed out
+	pub pending_timeout: u64,
+	/// Milliseconds between two status checks of a pending transaction, checked on every run
+	/// when zero
+	pub poll_interval: u64,
+	/// Milliseconds a transaction may stay pending before it is dropped, waited for
+	/// indefinitely when not set
+	pub max_pending_age: Option<u64>,
+	/// Number of remote blocks waiting to be mined above which the relayer catches up, never
+	/// when not set
+	pub catch_up_threshold: Option<u64>,
+	/// Maximum number of blocks mined in a single transaction while catching up
+	pub catch_up_batch_size: u32,
+	/// Milliseconds a transaction may wait for inclusion before it is sent again at a higher
+	/// gas price, never when not set
+	pub speed_up_after: Option<u64>,
+	/// Gas price increase of a sped up transaction, in percent of its gas price
+	pub speed_up_percent: u32,
+	/// Gas price a sped up transaction does not go over, unlimited when not set
+	pub max_gas_price: Option<U256>,
+	/// Balance under which a relayer key is reported for top up, never when not set
+	pub low_balance_threshold: Option<U256>,
+	/// How the final blocks of the chain are told apart, the confirmation depth is used when
+	/// the node does not support the block tag
+	pub finality: Finality,
+}
+
+impl From<QpNetworkItem> for ChainConfig {
+	fn from(item: QpNetworkItem) -> Self {
+		let gateway_contract_address =
+			crate::chain_utils::ChainUtils::hex_to_address(&item.gateway_contract_address[..]);
+		ChainConfig {
+			chain_id: item.id,
+			rpc_url: item.url,
+			gateway_contract_address,
+			ledger_manager_address: None,
+			confirmation_depth: 0,
+			reorg_depth: DEFAULT_REORG_DEPTH,
+			mine_gas_limit: None,
+			finalize_gas_limit: None,
+			mine_gas: GasEstimation::new(DEFAULT_MINE_GAS_LIMIT),
+			finalize_gas: GasEstimation::new(DEFAULT_FINALIZE_GAS_LIMIT),
+			max_mine_batch_size: DEFAULT_MAX_MINE_BATCH_SIZE,
+			mine_batch_gas_budget: None,
+			max_rpc_calls: None,
+			verification_rpc_urls: Vec::new(),
+			verification_quorum: 1,
+			gas_reserve: None,
+			pending_timeout: DEFAULT_PENDING_TIMEOUT,
+			poll_interval: 0,
+			max_pending_age: None,
+			catch_up_threshold: None,
+			catch_up_batch_size: DEFAULT_CATCH_UP_BATCH_SIZE,
+			speed_up_after: None,
+			speed_up_percent: DEFAULT_SPEED_UP_PERCENT,
+			max_gas_price: None,
+			low_balance_threshold: None,
+			finality: Finality::ConfirmationDepth,
+		}
+	}
+}
+
 #[allow(non_camel_case_types)]
 #[derive(
 	Clone,
@@ -15,30 +15,50 @@
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
 #![cfg_attr(not(feature = "std"), no_std)]
 use crate::{
-	chain_queries::CallResponse,
+	chain_queries::{CallResponse, ChainQueries},
 	chain_utils::{ChainRequestError, ChainRequestResult, ChainUtils, TransactionCreationError},
-	contract_client::{ContractClient, ContractClientSignature},
+	contract_client::{ContractClient, ContractClientSignature, SignerPool},
 	eip_712_utils::EIP712Utils,
-	qp_types::{QpLocalBlock, QpRemoteBlock, QpTransaction},
+	qp_types::{
+		transactions_root, CatchUpProgress, ChainConfig, CustodyPool, GasEstimation, MessageState,
+		MessageStatePayload, PayloadItems, QpLocalBlock, QpRemoteBlock, QpTransaction, SafeBlock,
+		DEFAULT_MINE_GAS_LIMIT, MAX_LOG_BLOCK_RANGE, MAX_PAYLOADS_PER_BLOCK, MAX_PAYLOAD_ITEMS,
+	},
+	recording::Recorder,
 	Config, Error, FinalizerThreshold, PendingFinalizeSignatures,
 };
-use ethabi_nostd::{decoder::decode, ParamKind, Token};
+use ethabi_nostd::{decoder::decode, encoder, Address, ParamKind, Token};
+use ferrum_primitives::{InboundMessage, RewardDistribution};
 use frame_system::offchain::{
 	AppCrypto, CreateSignedTransaction, SendSignedTransaction, SendUnsignedTransaction,
 	SignedPayload, Signer, SigningTypes, SubmitTransaction,
 };
 use sp_core::{H256, U256};
+use sp_runtime::offchain::storage::StorageValueRef;
 use sp_std::{marker::PhantomData, prelude::*};
 
 #[allow(dead_code)]
 const DUMMY_HASH: H256 = H256::zero();
 const ZERO_HASH: H256 = H256::zero();
+const MINE_METHOD_SIGNATURE: &[u8] = b"mineRemoteBlock(uint64,uint64,(uint64,address,address,address,address,uint256,bytes,uint256,uint256)[],bytes32,uint64,bytes)";
+const MULTICALL_METHOD_SIGNATURE: &[u8] = b"multicall(bytes[])";
+// Event of the ledger manager for a message registered for a remote chain, the remote chain id is
+// the indexed first topic
+const REMOTE_TRANSACTION_EVENT_SIGNATURE: &[u8] = b"RemoteTransactionRegistered(uint64,uint64,address,address,address,address,uint256,bytes,uint256,uint256)";
+// Event of the master staking contract for the rewards distributed to its stakers
+const REWARDS_DISTRIBUTED_EVENT_SIGNATURE: &[u8] =
+	b"RewardsDistributed(uint64,address[],uint256[])";
 
 pub struct QuantumPortalClient<T: Config> {
 	pub contract: ContractClient,
 	pub signer: ContractClientSignature,
+	/// Keys the transactions are submitted from
+	pub submitters: SignerPool,
 	pub now: u64,
 	pub block_number: u64,
+	pub config: ChainConfig,
+	/// Contracts transactions may be sent to, as allowed on-chain
+	pub allowed_contracts: Vec<Address>,
 	_phantom: PhantomData<T>,
 }
 
@@ -86,15 +106,15 @@ where
 			mined_block_tuple,
 			ParamKind::Array(Box::new(ParamKind::Tuple(vec![
 				// RemoteTransaction[]
-				Box::new(ParamKind::Uint(256)),                         // timestamp
-				Box::new(ParamKind::Address),                           // remoteContract
-				Box::new(ParamKind::Address),                           // sourceMsgSender
-				Box::new(ParamKind::Address),                           // sourceBeneficiary
-				Box::new(ParamKind::Address),                           // token
-				Box::new(ParamKind::Uint(256)),                         // amount
+				Box::new(ParamKind::Uint(256)), // timestamp
+				Box::new(ParamKind::Address),   // remoteContract
+				Box::new(ParamKind::Address),   // sourceMsgSender
+				Box::new(ParamKind::Address),   // sourceBeneficiary
+				Box::new(ParamKind::Address),   // token
+				Box::new(ParamKind::Uint(256)), // amount
 				Box::new(ParamKind::Array(Box::new(ParamKind::Bytes))), // method
-				Box::new(ParamKind::Uint(256)),                         // gas
-				Box::new(ParamKind::Uint(256)),                         // fixedFee
+				Box::new(ParamKind::Uint(256)), // gas
+				Box::new(ParamKind::Uint(256)), // fixedFee
 			]))),
 		],
 		ChainUtils::hex_to_bytes(data)?.as_slice(),
@@ -159,14 +179,110 @@ fn decode_remote_transaction_from_tuple(dec: &[Token]) -> ChainRequestResult<QpT
 	}
 }
 
+/// Decode the message of a `RemoteTransactionRegistered` event of `remote_chain`
+fn decode_inbound_message(remote_chain: u64, data: &[u8]) -> ChainRequestResult<InboundMessage> {
+	let dec = decode(
+		&[
+			ParamKind::Uint(64),  // timestamp
+			ParamKind::Address,   // remoteContract
+			ParamKind::Address,   // sourceMsgSender
+			ParamKind::Address,   // sourceBeneficiary
+			ParamKind::Address,   // token
+			ParamKind::Uint(256), // amount
+			ParamKind::Bytes,     // method
+			ParamKind::Uint(256), // gas
+			ParamKind::Uint(256), // fixedFee
+		],
+		data,
+	)
+	.map_err(|_| {
+		ChainRequestError::from(b"Could not decode remote transaction event".as_slice())
+	})?;
+	let tx = decode_remote_transaction_from_tuple(dec.as_slice())?;
+	Ok(InboundMessage {
+		remote_chain,
+		message_id: tx.hash(),
+		remote_contract: tx.remote_contract,
+		source_msg_sender: tx.source_msg_sender,
+		source_beneficiary: tx.source_beneficiary,
+		token: tx.token,
+		amount: u128::try_from(tx.amount)
+			.map_err(|_| ChainRequestError::from(b"Amount out of range".as_slice()))?,
+		method: tx.method,
+	})
+}
+
+/// Decode the distribution of a `RewardsDistributed` event of `remote_chain`
+fn decode_reward_distribution(
+	remote_chain: u64,
+	data: &[u8],
+) -> ChainRequestResult<RewardDistribution> {
+	let dec = decode(
+		&[
+			ParamKind::Uint(64),                              // distributionId
+			ParamKind::Array(Box::new(ParamKind::Address)),   // accounts
+			ParamKind::Array(Box::new(ParamKind::Uint(256))), // amounts
+		],
+		data,
+	)
+	.map_err(|_| {
+		ChainRequestError::from(b"Could not decode rewards distribution event".as_slice())
+	})?;
+	match dec.as_slice() {
+		[distribution_id, accounts, amounts] => {
+			let distribution_id = distribution_id.clone().to_uint().unwrap().as_u64();
+			let accounts = accounts.clone().to_array().unwrap();
+			let amounts = amounts.clone().to_array().unwrap();
+			if accounts.len() != amounts.len() {
+				return Err(b"Accounts and amounts differ in length".as_slice().into());
+			}
+			let rewards = accounts
+				.into_iter()
+				.zip(amounts)
+				.map(|(account, amount)| {
+					let amount = u128::try_from(amount.to_uint().unwrap())
+						.map_err(|_| ChainRequestError::from(b"Amount out of range".as_slice()))?;
+					Ok((account.to_address().unwrap(), amount))
+				})
+				.collect::<ChainRequestResult<_>>()?;
+			Ok(RewardDistribution { remote_chain, distribution_id, rewards })
+		},
+		_ => Err(b"Unexpected output. Could not decode rewards distribution".as_slice().into()),
+	}
+}
+
 impl<T: Config> QuantumPortalClient<T> {
 	pub fn new(
 		contract: ContractClient,
 		signer: ContractClientSignature,
+		submitters: SignerPool,
 		now: u64,
 		block_number: u64,
+		config: ChainConfig,
+		allowed_contracts: Vec<Address>,
 	) -> Self {
-		QuantumPortalClient { contract, signer, now, block_number, _phantom: Default::default() }
+		QuantumPortalClient {
+			contract,
+			signer,
+			submitters,
+			now,
+			block_number,
+			config,
+			allowed_contracts,
+			_phantom: Default::default(),
+		}
+	}
+
+	/// Balance of `pool` on the chain, of its token or of the native token
+	pub fn custody_balance(&self, pool: &CustodyPool) -> ChainRequestResult<U256> {
+		if pool.token.is_zero() {
+			return self.contract.balance(pool.address);
+		}
+		let signature = b"balanceOf(address)";
+		let res: Box<CallResponse> =
+			self.contract
+				.call(signature, &[Token::Address(pool.address)], Some(pool.token))?;
+		ChainUtils::hex_to_u256(&res.result)
 	}
 
 	pub fn is_local_block_ready(&self, chain_id: u64) -> ChainRequestResult<bool> {
@@ -191,6 +307,13 @@ impl<T: Config> QuantumPortalClient<T> {
 		self.decode_local_block(res.result.as_slice())
 	}
 
+	/// Whether the last block of `chain_id` mined on this chain is finalized
+	pub fn is_mined_block_finalized(&self, chain_id: u64) -> ChainRequestResult<bool> {
+		let mined = self.last_remote_mined_block(chain_id)?;
+		let finalized = self.last_finalized_block(chain_id)?;
+		Ok(mined.nonce <= finalized.nonce)
+	}
+
 	pub fn last_local_block(&self, chain_id: u64) -> ChainRequestResult<QpLocalBlock> {
 		let signature = b"getLastLocalBlock(uint256)";
 		let res: Box<CallResponse> =
@@ -202,9 +325,17 @@ impl<T: Config> QuantumPortalClient<T> {
 		&self,
 		chain_id: u64,
 		last_block_nonce: u64,
+	) -> ChainRequestResult<(QpLocalBlock, Vec<QpTransaction>)> {
+		Self::fetch_local_block_by_nonce(&self.contract, chain_id, last_block_nonce)
+	}
+
+	fn fetch_local_block_by_nonce(
+		contract: &ContractClient,
+		chain_id: u64,
+		last_block_nonce: u64,
 	) -> ChainRequestResult<(QpLocalBlock, Vec<QpTransaction>)> {
 		let signature = b"localBlockByNonce(uint64,uint64)";
-		let res: Box<CallResponse> = self.contract.call(
+		let res: Box<CallResponse> = contract.call(
 			signature,
 			&[Token::Uint(U256::from(chain_id)), Token::Uint(U256::from(last_block_nonce))],
 			None,
@@ -300,6 +431,15 @@ impl<T: Config> QuantumPortalClient<T> {
 			signature: multi_sig.clone(),
 		};
 
+		if self.contract.dry_run {
+			log::info!(
+				"Simulation: not submitting finalize signature for ({}, {})",
+				remote_chain_id,
+				block_nonce
+			);
+			return Ok(Default::default());
+		}
+
 		let _ = SubmitTransaction::<T, crate::Call<T>>::submit_unsigned_transaction(call.into())
 			.map_err(|_| <Error<T>>::OffchainUnsignedTxSignedPayload);
 
@@ -387,18 +527,15 @@ impl<T: Config> QuantumPortalClient<T> {
 		];
 
 		let recipient_address = self.contract.get_ledger_manager_address()?;
-
-		let res = self.contract.send(
+		let gas_limit = self.gas_limit(
+			self.config.finalize_gas_limit,
+			&self.config.finalize_gas,
 			method_signature,
 			&inputs,
-			None, //Some(U256::from(1000000 as u64)), // None,
-			None, //Some(U256::from(10000000000 as u64)), // None,
-			U256::zero(),
-			None,
-			self.signer.from,
-			&self.signer,
 			recipient_address,
-		)?;
+		);
+
+		let res = self.submit(method_signature, &inputs, gas_limit, recipient_address)?;
 
 		Ok(Default::default())
 	}
@@ -482,18 +619,15 @@ impl<T: Config> QuantumPortalClient<T> {
 		];
 
 		let recipient_address = self.contract.get_ledger_manager_address()?;
-
-		let res = self.contract.send(
+		let gas_limit = self.gas_limit(
+			self.config.finalize_gas_limit,
+			&self.config.finalize_gas,
 			method_signature,
 			&inputs,
-			None, //Some(U256::from(1000000 as u64)), // None,
-			None, //Some(U256::from(10000000000 as u64)), // None,
-			U256::zero(),
-			None,
-			self.signer.from,
-			&self.signer,
 			recipient_address,
-		)?;
+		);
+
+		let res = self.submit(method_signature, &inputs, gas_limit, recipient_address)?;
 
 		Ok(Default::default())
 	}
@@ -603,8 +737,142 @@ impl<T: Config> QuantumPortalClient<T> {
 		txs: &Vec<QpTransaction>,
 		source_block: QpLocalBlock,
 	) -> ChainRequestResult<H256> {
-		let method_signature = b"mineRemoteBlock(uint64,uint64,(uint64,address,address,address,address,uint256,bytes,uint256,uint256)[],bytes32,uint64,bytes)";
+		let inputs = self.mine_call_inputs(remote_chain_id, block_nonce, txs, source_block)?;
+		let recipient_address = self.contract.get_ledger_manager_address()?;
+		let gas_limit = self.gas_limit(
+			self.config.mine_gas_limit,
+			&self.config.mine_gas,
+			MINE_METHOD_SIGNATURE,
+			inputs.as_slice(),
+			recipient_address,
+		);
+
+		let res =
+			self.submit(MINE_METHOD_SIGNATURE, inputs.as_slice(), gas_limit, recipient_address)?;
+		Ok(res)
+	}
+
+	/// Mines the given source blocks in a single `multicall` transaction to the ledger manager
+	pub fn create_batch_mine_transaction(
+		&self,
+		remote_chain_id: u64,
+		blocks: Vec<(QpLocalBlock, Vec<QpTransaction>)>,
+	) -> ChainRequestResult<H256> {
+		let block_count = blocks.len();
+		let calls = blocks
+			.into_iter()
+			.map(|(block, txs)| {
+				let inputs = self.mine_call_inputs(remote_chain_id, block.nonce, &txs, block)?;
+				Ok(Token::Bytes(encoder::encode_function_u8(MINE_METHOD_SIGNATURE, &inputs)))
+			})
+			.collect::<ChainRequestResult<Vec<Token>>>()?;
+		let inputs = [Token::Array(calls)];
+		let recipient_address = self.contract.get_ledger_manager_address()?;
+
+		let gas_limit = match self.config.mine_gas_limit {
+			Some(gas_limit) => {
+				let gas_limit = gas_limit.saturating_mul(block_count as u64);
+				U256::from(
+					self.config
+						.mine_batch_gas_budget
+						.map_or(gas_limit, |budget| gas_limit.min(budget)),
+				)
+			},
+			None => self.contract.estimate_gas_limit(
+				MULTICALL_METHOD_SIGNATURE,
+				&inputs,
+				self.signer.from,
+				recipient_address,
+				&self
+					.config
+					.mine_gas
+					.for_batch(block_count as u64, self.config.mine_batch_gas_budget),
+			),
+		};
+
+		let res = self.submit(MULTICALL_METHOD_SIGNATURE, &inputs, gas_limit, recipient_address)?;
+		Ok(res)
+	}
+
+	/// Send a transaction from the next key of the pool, to an allowed contract only
+	fn submit(
+		&self,
+		method_signature: &[u8],
+		inputs: &[Token],
+		gas_limit: U256,
+		recipient_address: Address,
+	) -> ChainRequestResult<H256> {
+		if !self.allowed_contracts.contains(&recipient_address) {
+			log::error!(
+				"{} Contract {:?} is not allowed on chain {}, not sending",
+				self.contract.trace.get(),
+				recipient_address,
+				self.contract.chain_id,
+			);
+			return Err(ChainRequestError::ContractNotAllowed(recipient_address));
+		}
+		let gas_price = self.contract.gas_price()?.saturating_mul(U256::from(125_u32)) / 100;
+		let cost = gas_limit.saturating_mul(gas_price);
+		let (signer, nonce) = self.submitters.next(&self.contract, cost)?;
+		let res = self.contract.send(
+			method_signature,
+			inputs,
+			Some(gas_limit),
+			Some(gas_price),
+			U256::zero(),
+			Some(nonce),
+			signer.from,
+			signer,
+			recipient_address,
+		)?;
+		self.submitters.record_nonce(&self.contract, signer.from, nonce);
+		Ok(res)
+	}
+
+	/// The configured `gas_limit`, estimated with `estimation` when not set
+	fn gas_limit(
+		&self,
+		gas_limit: Option<u64>,
+		estimation: &GasEstimation,
+		method_signature: &[u8],
+		inputs: &[Token],
+		recipient_address: Address,
+	) -> U256 {
+		match gas_limit {
+			Some(gas_limit) => U256::from(gas_limit),
+			None => self.contract.estimate_gas_limit(
+				method_signature,
+				inputs,
+				self.signer.from,
+				recipient_address,
+				estimation,
+			),
+		}
+	}
 
+	/// Number of blocks to mine in one transaction given the number of blocks waiting, bounded by
+	/// the configured batch size, raised while catching up, and gas budget
+	fn mine_batch_size(&self, pending_blocks: u64, catching_up: bool) -> u64 {
+		let mut batch_size = u64::from(self.config.max_mine_batch_size);
+		if catching_up {
+			batch_size = batch_size.max(u64::from(self.config.catch_up_batch_size));
+		}
+		if let Some(budget) = self.config.mine_batch_gas_budget {
+			let block_gas = self.config.mine_gas_limit.unwrap_or(DEFAULT_MINE_GAS_LIMIT).max(1);
+			batch_size = batch_size.min(budget / block_gas);
+		}
+		batch_size.min(pending_blocks).max(1)
+	}
+
+	/// Returns the signed `mineRemoteBlock` inputs for the given source block
+	#[allow(clippy::ptr_arg)]
+	fn mine_call_inputs(
+		&self,
+		remote_chain_id: u64,
+		block_nonce: u64,
+		txs: &Vec<QpTransaction>,
+		source_block: QpLocalBlock,
+	) -> ChainRequestResult<Vec<Token>> {
 		// set timestamp 1hr from now
 		let current_timestamp = source_block.timestamp;
 		let expiry_buffer = core::time::Duration::from_secs(360000u64);
@@ -643,27 +911,14 @@ impl<T: Config> QuantumPortalClient<T> {
 				.unwrap()
 		);
 
-		let recipient_address = self.contract.get_ledger_manager_address()?;
-
-		let res = self.contract.send(
-			method_signature,
-			&[
-				Token::Uint(U256::from(remote_chain_id)),
-				Token::Uint(U256::from(block_nonce)),
-				Token::Array(tx_vec),
-				salt,
-				expiry,
-				Token::Bytes(multi_sig),
-			],
-			Some(U256::from(1000000_u32)), // None,
-			None,                          // Some(U256::from(60000000000 as u64)), // None,
-			U256::zero(),
-			None,
-			self.signer.from,
-			&self.signer,
-			recipient_address,
-		)?;
-		Ok(res)
+		Ok(vec![
+			Token::Uint(U256::from(remote_chain_id)),
+			Token::Uint(U256::from(block_nonce)),
+			Token::Array(tx_vec),
+			salt,
+			expiry,
+			Token::Bytes(multi_sig),
+		])
 	}
 
 	/// Returns the Signature to sign mine transactions
@@ -750,12 +1005,15 @@ impl<T: Config> QuantumPortalClient<T> {
 	}
 
 	pub fn finalize(&self, chain_id: u64) -> ChainRequestResult<Option<H256>> {
-		log::info!("finalize({})", chain_id);
+		log::info!("{} finalize({})", self.contract.trace.get(), chain_id);
 		let block = self.last_remote_mined_block(chain_id)?;
 		log::info!("finalize-last_remote_mined_block({:?})", &block);
 		let last_fin = self.last_finalized_block(chain_id)?;
 
 		log::info!("finalize-last_finalized_block({:?})", &last_fin);
+		if let Err(e) = self.report_finalized_block(chain_id, &last_fin) {
+			log::warn!("Could not report the finalized messages of chain {} : {:?}", chain_id, e);
+		}
 		if block.nonce > last_fin.nonce {
 			log::info!(
 				"Preparing to finalize, verifying mined block ({}, {})",
@@ -764,8 +1022,13 @@ impl<T: Config> QuantumPortalClient<T> {
 			);
 			let (_mined_block, mined_txs) = self.mined_block_by_nonce(chain_id, block.nonce)?;
 			let (_source_block, source_txs) = self.local_block_by_nonce(chain_id, block.nonce)?;
+			self.verify_source_block(chain_id, block.nonce, &source_txs)?;
 			// verify data before finalization
 			let verification_result = Self::compare_and_verify_mined_block(&source_txs, &mined_txs);
+			self.report_message_states(chain_id, &mined_txs, MessageState::Mined);
+			if !verification_result {
+				self.report_message_states(chain_id, &source_txs, MessageState::Failed);
+			}
 
 			// if we have enough signers for finalize then we post transaction onchain
 			let multi_sigs = PendingFinalizeSignatures::<T>::get(chain_id, block.nonce);
@@ -804,15 +1067,240 @@ impl<T: Config> QuantumPortalClient<T> {
 				)?))
 			}
 		} else {
-			log::info!("Nothing to finalize for ({})", chain_id);
+			log::info!("{} Nothing to finalize for ({})", self.contract.trace.get(), chain_id);
 			Ok(None)
 		}
 	}
 
+	/// Storage key of the last block of this chain scanned for inbound messages
+	pub fn inbound_cursor_key(&self) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(self.contract.chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::inbound_cursor::".as_slice(), chain.as_slice()].concat()
+	}
+
+	/// Storage key of the last block of this chain scanned for reward distributions
+	pub fn rewards_cursor_key(&self) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(self.contract.chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::rewards_cursor::".as_slice(), chain.as_slice()].concat()
+	}
+
+	/// Sequence of the next `kind` payload for `chain_id` signed at the current block, none once
+	/// `MAX_PAYLOADS_PER_BLOCK` of them were signed
+	pub fn next_payload_sequence(&self, kind: &[u8], chain_id: u64) -> Option<u8> {
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		let key = [b"quantum-portal::payload_sequence::".as_slice(), kind, b"::", chain.as_slice()]
+			.concat();
+		let stored = StorageValueRef::persistent(&key);
+		let sequence = match stored.get::<(u64, u8)>().ok().flatten() {
+			Some((block_number, next)) if block_number == self.block_number => next,
+			_ => 0,
+		};
+		if sequence >= MAX_PAYLOADS_PER_BLOCK {
+			return None;
+		}
+		stored.set(&(self.block_number, sequence + 1));
+		Some(sequence)
+	}
+
+	/// Blocks to scan for events after the scan cursor stored at `cursor_key`, the confirmed
+	/// blocks up to `MAX_LOG_BLOCK_RANGE` of them. The range is empty when `from` is after `to`,
+	/// on the first scan it ends at the current block.
+	fn log_range(&self, url: &str, cursor_key: &[u8]) -> ChainRequestResult<(u64, u64)> {
+		let confirmed =
+			ChainQueries::block_number(url)?.saturating_sub(self.config.confirmation_depth);
+		let cursor = StorageValueRef::persistent(cursor_key).get::<u64>();
+		let from_block = match cursor.ok().flatten() {
+			Some(cursor) => cursor + 1,
+			None => return Ok((confirmed + 1, confirmed)),
+		};
+		if from_block > confirmed {
+			return Ok((from_block, from_block - 1));
+		}
+		Ok((from_block, confirmed.min(from_block + MAX_LOG_BLOCK_RANGE - 1)))
+	}
+
+	/// Messages registered on the ledger manager of this chain for `target_chain`, in the
+	/// confirmed blocks after the scan cursor, with the last block scanned. The first scan starts
+	/// from the current block, a scan covers at most `MAX_LOG_BLOCK_RANGE` blocks.
+	pub fn inbound_messages(
+		&self,
+		target_chain: u64,
+	) -> ChainRequestResult<(Vec<InboundMessage>, u64)> {
+		let url = sp_std::str::from_utf8(&self.contract.http_api[..]).unwrap();
+		let (from_block, to_block) = self.log_range(url, &self.inbound_cursor_key())?;
+		if from_block > to_block {
+			return Ok((Vec::new(), to_block));
+		}
+
+		let topics = [
+			ChainUtils::keccack(REMOTE_TRANSACTION_EVENT_SIGNATURE),
+			H256::from_low_u64_be(target_chain),
+		];
+		let ledger_manager = self.contract.get_ledger_manager_address()?;
+		let logs = ChainQueries::get_logs(url, ledger_manager, &topics, from_block, to_block)?;
+		let messages = logs
+			.iter()
+			.filter_map(|log| match decode_inbound_message(self.contract.chain_id, &log.data) {
+				Ok(message) => Some(message),
+				Err(e) => {
+					log::warn!(
+						"Skipping the message of transaction {:?} : {:?}",
+						log.transaction_hash,
+						e
+					);
+					None
+				},
+			})
+			.collect();
+		Ok((messages, to_block))
+	}
+
+	/// Rewards distributed by the master staking contract `source` of this chain, in the
+	/// confirmed blocks after the scan cursor, with the last block scanned. Scanned like
+	/// `inbound_messages`.
+	pub fn reward_distributions(
+		&self,
+		source: Address,
+	) -> ChainRequestResult<(Vec<RewardDistribution>, u64)> {
+		let url = sp_std::str::from_utf8(&self.contract.http_api[..]).unwrap();
+		let (from_block, to_block) = self.log_range(url, &self.rewards_cursor_key())?;
+		if from_block > to_block {
+			return Ok((Vec::new(), to_block));
+		}
+
+		let topics = [ChainUtils::keccack(REWARDS_DISTRIBUTED_EVENT_SIGNATURE)];
+		let logs = ChainQueries::get_logs(url, source, &topics, from_block, to_block)?;
+		let distributions = logs
+			.iter()
+			.filter_map(|log| match decode_reward_distribution(self.contract.chain_id, &log.data) {
+				Ok(distribution) => Some(distribution),
+				Err(e) => {
+					log::warn!(
+						"Skipping the rewards distribution of transaction {:?} : {:?}",
+						log.transaction_hash,
+						e
+					);
+					None
+				},
+			})
+			.collect();
+		Ok((distributions, to_block))
+	}
+
+	/// Report the state of the messages of `remote_chain` to the pallet, in an unsigned
+	/// transaction per `MAX_PAYLOAD_ITEMS` messages signed by the relayer signer
+	fn report_message_states(&self, remote_chain: u64, txs: &[QpTransaction], state: MessageState) {
+		if self.contract.dry_run {
+			return;
+		}
+		for chunk in txs.chunks(MAX_PAYLOAD_ITEMS as usize) {
+			let sequence = match self.next_payload_sequence(b"message_states", remote_chain) {
+				Some(sequence) => sequence,
+				None => {
+					log::warn!(
+						"Too many message state payloads of chain {} in block {}",
+						remote_chain,
+						self.block_number
+					);
+					return;
+				},
+			};
+			let payload = MessageStatePayload {
+				remote_chain,
+				updates: PayloadItems::truncate_from(
+					chunk.iter().map(|tx| (tx.hash(), state)).collect(),
+				),
+				block_number: self.block_number,
+				sequence,
+			};
+			let signature = match self.signer.signer(&payload.signing_hash()) {
+				Ok(signature) => signature,
+				Err(e) => {
+					log::warn!(
+						"Could not sign the message states of chain {} : {:?}",
+						remote_chain,
+						e
+					);
+					return;
+				},
+			};
+			let call = crate::Call::update_message_states { payload, signature };
+			let result =
+				SubmitTransaction::<T, crate::Call<T>>::submit_unsigned_transaction(call.into());
+			if result.is_err() {
+				log::warn!("Could not submit the message states of chain {}", remote_chain);
+			}
+		}
+	}
+
+	/// Report the messages of the last finalized block of `chain_id`, once per block
+	fn report_finalized_block(
+		&self,
+		chain_id: u64,
+		last_fin: &QpLocalBlock,
+	) -> ChainRequestResult<()> {
+		let remote = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		let local = ChainUtils::bytes_to_hex(self.contract.chain_id.to_be_bytes().as_slice());
+		let key = [
+			b"quantum-portal::finalized_reported::".as_slice(),
+			remote.as_slice(),
+			b"::",
+			local.as_slice(),
+		]
+		.concat();
+		Recorder::track(&key);
+		let reported = StorageValueRef::persistent(key.as_slice());
+		if last_fin.nonce <= reported.get::<u64>().ok().flatten().unwrap_or_default() {
+			return Ok(());
+		}
+		let (_, txs) = self.mined_block_by_nonce(chain_id, last_fin.nonce)?;
+		self.report_message_states(chain_id, &txs, MessageState::Finalized);
+		reported.set(&last_fin.nonce);
+		Ok(())
+	}
+
+	/// Checks the transactions root of the source block against the verification endpoints, we
+	/// refuse to finalize if any of them disagrees or fewer than the quorum could be reached.
+	fn verify_source_block(
+		&self,
+		chain_id: u64,
+		block_nonce: u64,
+		source_txs: &[QpTransaction],
+	) -> ChainRequestResult<()> {
+		if self.config.verification_rpc_urls.is_empty() {
+			return Ok(());
+		}
+		let expected_root = transactions_root(source_txs);
+		let mut agreeing = 1_u32;
+		for url in self.config.verification_rpc_urls.iter() {
+			let mut contract = self.contract.clone();
+			contract.http_api = url.clone();
+			match Self::fetch_local_block_by_nonce(&contract, chain_id, block_nonce) {
+				Ok((_, txs)) if transactions_root(&txs) == expected_root => agreeing += 1,
+				Ok((_, txs)) => {
+					log::error!(
+						"Source block {}:{} mismatch, expected root {:?} got {:?}",
+						chain_id,
+						block_nonce,
+						expected_root,
+						transactions_root(&txs)
+					);
+					return Err(ChainRequestError::ProviderDataMismatch);
+				},
+				Err(e) => log::warn!("Verification endpoint unavailable : {:?}", e),
+			}
+		}
+		log::info!("Source block {}:{} confirmed by {} endpoints", chain_id, block_nonce, agreeing);
+		if agreeing < self.config.verification_quorum {
+			return Err(ChainRequestError::VerificationQuorumNotReached);
+		}
+		Ok(())
+	}
+
 	pub fn mine(&self, remote_client: &QuantumPortalClient<T>) -> ChainRequestResult<Option<H256>> {
 		let local_chain = self.contract.chain_id;
 		let remote_chain = remote_client.contract.chain_id;
-		log::info!("mine({} => {})", remote_chain, local_chain);
+		log::info!("{} mine({} => {})", self.contract.trace.get(), remote_chain, local_chain);
 		let block_ready = remote_client.is_local_block_ready(local_chain)?;
 		log::info!("local block ready? {}", block_ready);
 		if !block_ready {
@@ -824,8 +1312,10 @@ impl<T: Config> QuantumPortalClient<T> {
 		let last_mined_block = self.last_remote_mined_block(remote_chain)?;
 		log::info!("Local block f remote (chain {}) nonce is {}. Remote mined block on local (chain {}) is {}",
 			remote_chain, last_block.nonce, local_chain, last_mined_block.nonce);
+		let backlog = last_block.nonce.saturating_sub(last_mined_block.nonce);
+		let catching_up = self.track_catch_up(remote_chain, backlog);
 		if last_mined_block.nonce >= last_block.nonce {
-			log::info!("Nothing to mine!");
+			log::info!("{} Nothing to mine!", self.contract.trace.get());
 			return Ok(None);
 		}
 		log::info!("Last block is on chain1 for target {} is {}", local_chain, last_block.nonce);
@@ -848,6 +1338,18 @@ impl<T: Config> QuantumPortalClient<T> {
 			}
 		);
 		let txs = source_block.1;
+		let safe_block = remote_client.safe_block()?;
+		if !Self::is_final(&source_block.0, &txs, &safe_block) {
+			log::info!(
+				"{} Block {}:{} is not final yet, the safe block is {}",
+				self.contract.trace.get(),
+				remote_chain,
+				source_block.0.nonce,
+				safe_block.number
+			);
+			return Ok(None);
+		}
+		self.report_message_states(remote_chain, &txs, MessageState::Discovered);
 
 		log::info!(
 			"Checking if the slot to mine block on chain is assigned to us {}:{}",
@@ -856,9 +1358,9 @@ impl<T: Config> QuantumPortalClient<T> {
 		);
 
 		let _assigned_miner = self.contract.get_miner_for_block(
-			source_block.0.hash(),                      // block hash from txs
-			source_block.0.timestamp,                   // block timestamp
-			sp_io::offchain::timestamp().unix_millis(), // chain timestamp
+			source_block.0.hash(),    // block hash from txs
+			source_block.0.timestamp, // block timestamp
+			self.now,                 // chain timestamp
 		)?;
 
 		// if ChainUtils::address_to_hex(assigned_miner) != self.signer.get_signer_address() {
@@ -871,6 +1373,27 @@ impl<T: Config> QuantumPortalClient<T> {
 		//     return Err(ChainRequestError::SlotNotAvailable);
 		// }
 
+		let batch_size = self.mine_batch_size(backlog, catching_up);
+		if batch_size > 1 {
+			let first_nonce = source_block.0.nonce;
+			let mut blocks = vec![(source_block.0, txs)];
+			for nonce in first_nonce.saturating_add(1)..first_nonce.saturating_add(batch_size) {
+				let block = remote_client.local_block_by_nonce(local_chain, nonce)?;
+				// the batch stops at the first block that is not final yet
+				if !Self::is_final(&block.0, &block.1, &safe_block) {
+					break;
+				}
+				blocks.push(block);
+			}
+			log::info!(
+				"About to mine blocks {}:{}..={} in one batch",
+				remote_chain,
+				first_nonce,
+				first_nonce.saturating_add(blocks.len() as u64 - 1)
+			);
+			return Ok(Some(self.create_batch_mine_transaction(remote_chain, blocks)?));
+		}
+
 		log::info!("About to mine block {}:{}", remote_chain, source_block.0.nonce);
 		Ok(Some(self.create_mine_transaction(
 			remote_chain,
@@ -880,6 +1403,100 @@ impl<T: Config> QuantumPortalClient<T> {
 		)?))
 	}
 
+	/// Latest final block of this chain by the configured finality mode, the block
+	/// `confirmation_depth` deep when the node does not support the block tag of the mode. The
+	/// block tracked in offchain storage only moves forward.
+	pub fn safe_block(&self) -> ChainRequestResult<SafeBlock> {
+		let url = sp_std::str::from_utf8(&self.contract.http_api[..]).unwrap();
+		let chain_id = self.contract.chain_id;
+		let tagged = match self.config.finality.block_tag() {
+			Some(tag) => ChainQueries::block_header(url, tag).unwrap_or_else(|e| {
+				log::warn!(
+					"{} Chain {} does not support the {:?} block tag : {:?}",
+					self.contract.trace.get(),
+					chain_id,
+					sp_std::str::from_utf8(tag).unwrap(),
+					e
+				);
+				None
+			}),
+			None => None,
+		};
+		let header = match tagged {
+			Some(header) => Some(header),
+			None => {
+				let number =
+					ChainQueries::block_number(url)?.saturating_sub(self.config.confirmation_depth);
+				ChainQueries::block_header(url, &ChainUtils::u64_to_hex_0x(number))?
+			},
+		};
+		let (number, timestamp) =
+			header.ok_or_else(|| ChainRequestError::from(b"No final block".as_slice()))?;
+
+		let stored = StorageValueRef::persistent(&SafeBlock::storage_key(chain_id));
+		match stored.get::<SafeBlock>().ok().flatten() {
+			Some(tracked) if tracked.number >= number => Ok(tracked),
+			_ => {
+				let safe_block = SafeBlock { number, timestamp };
+				stored.set(&safe_block);
+				Ok(safe_block)
+			},
+		}
+	}
+
+	/// Whether `block` and its transactions are from remote blocks before `safe_block`
+	fn is_final(block: &QpLocalBlock, txs: &[QpTransaction], safe_block: &SafeBlock) -> bool {
+		block.timestamp < safe_block.timestamp &&
+			txs.iter().all(|tx| tx.timestamp < safe_block.timestamp)
+	}
+
+	/// Enters catch-up once `backlog` blocks of `remote_chain` are above the configured threshold
+	/// and leaves it once they fit in a regular batch, returns whether the relayer is catching up
+	fn track_catch_up(&self, remote_chain: u64, backlog: u64) -> bool {
+		let threshold = match self.config.catch_up_threshold {
+			Some(threshold) => threshold,
+			None => return false,
+		};
+		let trace = self.contract.trace.get();
+		let key = CatchUpProgress::storage_key(remote_chain, self.contract.chain_id);
+		Recorder::track(&key);
+		let mut stored = StorageValueRef::persistent(key.as_slice());
+		match stored.get::<CatchUpProgress>().ok().flatten() {
+			None if backlog > threshold => {
+				log::info!("{} Catching up on {} blocks of chain {}", trace, backlog, remote_chain);
+				stored.set(&CatchUpProgress {
+					started_at: self.now,
+					initial_backlog: backlog,
+					backlog,
+				});
+				true
+			},
+			None => false,
+			Some(progress) if backlog <= u64::from(self.config.max_mine_batch_size) => {
+				log::info!(
+					"{} Caught up on chain {} in {} ms",
+					trace,
+					remote_chain,
+					self.now.saturating_sub(progress.started_at)
+				);
+				stored.clear();
+				false
+			},
+			Some(mut progress) => {
+				log::info!(
+					"{} Catching up on chain {}: {} of {} blocks left",
+					trace,
+					remote_chain,
+					backlog,
+					progress.initial_backlog
+				);
+				progress.backlog = backlog;
+				stored.set(&progress);
+				true
+			},
+		}
+	}
+
 	fn decode_local_block(&self, data: &[u8]) -> ChainRequestResult<QpLocalBlock> {
 		let dec = decode(
 			// &[local_block_tuple()],
@@ -15,27 +15,219 @@
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
 	chain_queries::{ChainQueries, TransactionStatus},
-	chain_utils::{ChainRequestResult, ChainUtils},
-	qp_types::Role,
+	chain_utils::{ChainRequestError, ChainRequestResult, ChainUtils, TraceContext},
+	qp_types::{
+		ChainId, CustodyPool, InboundMessagesPayload, PayloadItems, PendingTransactionsPayload,
+		ReservesPayload, RewardsPayload, Role, MAX_PAYLOAD_ITEMS, RECORDING_MODE_KEY,
+	},
 	quantum_portal_client::QuantumPortalClient,
+	recording::{Decision, PairRecording, Recorder, ReplayReport},
 	Config,
 };
+use frame_system::offchain::SubmitTransaction;
 use parity_scale_codec::{Decode, Encode, MaxEncodedLen};
-use sp_core::H256;
-use sp_runtime::offchain::storage::StorageValueRef;
+use sp_core::{H160, H256, U256};
+use sp_runtime::offchain::{
+	storage::StorageValueRef,
+	storage_lock::{BlockAndTime, StorageLock},
+	Duration,
+};
 use sp_std::{marker::PhantomData, prelude::*, str};
 
-const TIMEOUT: u64 = 3600 * 1000;
+const PAIR_LOCK_EXPIRATION_BLOCKS: u32 = 20;
+const PAIR_LOCK_EXPIRATION: u64 = 10 * 60 * 1000;
 
-#[derive(Debug, Encode, Decode, Clone, PartialEq, MaxEncodedLen, scale_info::TypeInfo, Default)]
+#[derive(
+	Debug, Encode, Decode, Clone, PartialEq, Eq, MaxEncodedLen, scale_info::TypeInfo, Default,
+)]
 pub enum PendingTransaction {
 	// MineTransaction(chain, remote_chain, timestamp, tx_id)
 	MineTransaction(u64, u64, u64, H256),
 	FinalizeTransaction(u64, u64, H256),
+	// RecoveredTransaction(chain, timestamp, tx_id), found in the journal after a restart
+	RecoveredTransaction(u64, u64, H256),
 	#[default]
 	None,
 }
 
+impl PendingTransaction {
+	/// The same transaction, tracked again from `timestamp`
+	pub fn requeued(&self, timestamp: u64) -> Self {
+		match self.clone() {
+			PendingTransaction::MineTransaction(c1, c2, _, tid) =>
+				PendingTransaction::MineTransaction(c1, c2, timestamp, tid),
+			PendingTransaction::FinalizeTransaction(c, _, tid) =>
+				PendingTransaction::FinalizeTransaction(c, timestamp, tid),
+			PendingTransaction::RecoveredTransaction(c, _, tid) =>
+				PendingTransaction::RecoveredTransaction(c, timestamp, tid),
+			PendingTransaction::None => PendingTransaction::None,
+		}
+	}
+
+	/// The transaction sent again as `tx_id`, tracked from `timestamp`
+	pub fn replaced(&self, timestamp: u64, tx_id: H256) -> Self {
+		match self.clone() {
+			PendingTransaction::MineTransaction(c1, c2, _, _) =>
+				PendingTransaction::MineTransaction(c1, c2, timestamp, tx_id),
+			PendingTransaction::FinalizeTransaction(c, _, _) =>
+				PendingTransaction::FinalizeTransaction(c, timestamp, tx_id),
+			PendingTransaction::RecoveredTransaction(c, _, _) =>
+				PendingTransaction::RecoveredTransaction(c, timestamp, tx_id),
+			PendingTransaction::None => PendingTransaction::None,
+		}
+	}
+}
+
+#[derive(Debug, Encode, Decode, Clone, PartialEq, MaxEncodedLen, scale_info::TypeInfo)]
+pub enum TransactionOutcome {
+	Confirmed,
+	Failed,
+	TimedOut,
+	/// The block the transaction was confirmed in is no longer canonical
+	Reorged,
+	/// Replaced by an operator with a zero value transfer at the same nonce
+	Cancelled,
+	/// Sent again at the same nonce with a higher gas price, as the given transaction
+	SpedUp(H256),
+}
+
+/// Operator command on a stuck pending transaction, queued in persistent offchain storage through
+/// the `qp` rpc and run by the offchain worker before the pending transactions are checked.
+#[derive(Debug, Encode, Decode, Clone, PartialEq, scale_info::TypeInfo)]
+pub enum TxCommand {
+	/// Replace the transaction with a zero value transfer to its sender at the same nonce
+	Cancel(H256),
+	/// Stop tracking the transaction, as if it timed out
+	Expire(H256),
+}
+
+/// A confirmed transaction, kept until its block is `reorg_depth` blocks deep so a reorg
+/// dropping it can be detected.
+#[derive(Debug, Encode, Decode, Clone, PartialEq, scale_info::TypeInfo)]
+pub struct ConfirmedTransaction {
+	pub tx: PendingTransaction,
+	pub block_number: u64,
+	pub block_hash: H256,
+}
+
+/// Status record written to persistent offchain storage once a relayer transaction leaves the
+/// pending state, so external indexers can follow relayer throughput through
+/// `offchain_localStorageGet` without parsing node logs.
+///
+/// Note that `sp_io::offchain_index` is only writable during block execution, the offchain worker
+/// has to use the persistent local storage instead.
+#[derive(Debug, Encode, Decode, Clone, PartialEq, MaxEncodedLen, scale_info::TypeInfo)]
+pub struct TransactionStatusRecord {
+	pub tx: PendingTransaction,
+	pub outcome: TransactionOutcome,
+	pub timestamp: u64,
+}
+
+/// Last confirmed mine and finalize transactions of a chain, kept in persistent offchain storage
+#[derive(Debug, Encode, Decode, Clone, PartialEq, Default, scale_info::TypeInfo)]
+pub struct RelayerActivity {
+	pub last_mine: Option<ConfirmedTransaction>,
+	pub last_finalize: Option<ConfirmedTransaction>,
+}
+
+/// A relayer key with a balance under the low balance threshold of its chain, kept in persistent
+/// offchain storage until the key is topped up
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+pub struct LowBalance {
+	pub address: H160,
+	pub balance: U256,
+	/// Time the balance was first seen under the threshold
+	pub since: u64,
+}
+
+/// Last error seen while processing a chain pair, kept in persistent offchain storage
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+pub struct PairError {
+	pub remote_chain: u64,
+	pub local_chain: u64,
+	pub error: ChainRequestError,
+	pub timestamp: u64,
+}
+
+/// Outcome of a `process_pair` run, logged as a summary once the run completes
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PairRunSummary {
+	/// Transactions still pending on the local chain, the run does no new work if any
+	pub pending_txs: usize,
+	/// A block was ready to be mined or finalized
+	pub work_found: bool,
+	pub submitted_tx: Option<H256>,
+}
+
+/// Work done on a chain pair: finalizing the blocks mined on the local chain or mining the
+/// blocks of the remote chain
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq, scale_info::TypeInfo)]
+pub enum WorkKind {
+	Finalize,
+	Mine,
+}
+
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq, scale_info::TypeInfo)]
+pub enum WorkState {
+	/// Waiting for the items before it to be done
+	Queued,
+	/// The transaction was sent and is pending on the local chain
+	Submitted(H256),
+	/// The transaction is confirmed, or there was nothing to do
+	Done,
+}
+
+#[derive(Debug, Encode, Decode, Clone, PartialEq, Eq, scale_info::TypeInfo)]
+pub struct WorkItem {
+	pub kind: WorkKind,
+	pub state: WorkState,
+	/// Time of the last state change
+	pub updated_at: u64,
+}
+
+impl WorkItem {
+	fn transition(&mut self, state: WorkState, now: u64, trace: TraceContext) {
+		log::info!("{} {:?} work: {:?} -> {:?}", trace, self.kind, self.state, state);
+		Recorder::decision(Decision::Transition(self.kind, state));
+		self.state = state;
+		self.updated_at = now;
+	}
+}
+
+/// Work queue of a chain pair, kept in persistent offchain storage so a restarted worker resumes
+/// where it stopped. An item only runs once every item before it is done, so the mine queued
+/// after the finalize of a round waits for the finalize to be done.
+#[derive(Debug, Encode, Decode, Clone, PartialEq, Eq, Default, scale_info::TypeInfo)]
+pub struct WorkQueue {
+	pub items: Vec<WorkItem>,
+}
+
+impl WorkQueue {
+	/// The work of one round for `role`, finalizing comes before mining. A finalizer finalizes
+	/// the last mined block and does not mine, a miner waits for the finalizers to finalize it
+	/// before mining the next block.
+	pub fn plan(role: &Role, now: u64) -> Self {
+		let kinds: &[WorkKind] = match role {
+			Role::QP_MINER | Role::QP_FINALIZER => &[WorkKind::Finalize, WorkKind::Mine],
+			Role::None => &[],
+		};
+		let items = kinds
+			.iter()
+			.map(|kind| WorkItem { kind: *kind, state: WorkState::Queued, updated_at: now })
+			.collect();
+		WorkQueue { items }
+	}
+
+	pub fn is_done(&self) -> bool {
+		self.items.iter().all(|item| item.state == WorkState::Done)
+	}
+
+	/// The first item not done yet, the items after it depend on it
+	fn next_mut(&mut self) -> Option<&mut WorkItem> {
+		self.items.iter_mut().find(|item| item.state != WorkState::Done)
+	}
+}
+
 pub struct QuantumPortalService<T: Config> {
 	pub clients: Vec<QuantumPortalClient<T>>,
 	_phantom: PhantomData<T>,
@@ -46,51 +238,151 @@ impl<T: Config> QuantumPortalService<T> {
 		QuantumPortalService { clients, _phantom: Default::default() }
 	}
 
-	fn lock_is_open(&self) -> ChainRequestResult<bool> {
-		// Save a None tx.
-		let tx = self.stored_pending_transactions(9999)?;
-		log::info!("Current pending txs {:?}", tx);
-		if tx.is_empty() {
-			log::info!("No lock! We can go ahead");
-			return Ok(true);
-		}
-		log::info!("LOCKED! {:?}", tx.get(0).unwrap());
-		Ok(false)
-	}
-
-	fn lock(&self) -> ChainRequestResult<()> {
-		log::info!("Saving a lock!");
-		self.save_tx(PendingTransaction::FinalizeTransaction(9999, 0, H256::zero()))?;
-		Ok(())
-	}
-
-	fn remove_lock(&self) -> ChainRequestResult<()> {
-		log::info!("Removing a lock!");
-		let tx = PendingTransaction::FinalizeTransaction(9999, 0, H256::zero());
-		self.remove_transaction_from_db(&tx)?;
-		Ok(())
+	/// Storage key of the processing lock for the `remote_chain -> local_chain` pair
+	pub fn pair_lock_storage_key(remote_chain: u64, local_chain: u64) -> Vec<u8> {
+		let remote = ChainUtils::bytes_to_hex(remote_chain.to_be_bytes().as_slice());
+		let local = ChainUtils::bytes_to_hex(local_chain.to_be_bytes().as_slice());
+		[b"quantum-portal::pair_lock::".as_slice(), remote.as_slice(), b"::", local.as_slice()]
+			.concat()
 	}
 
+	/// Processes the pair while holding a lock on it. Each pair has its own lock, so a stuck pair
+	/// does not hold back the others, and a lock left behind by a crashed worker expires after
+	/// `PAIR_LOCK_EXPIRATION_BLOCKS` blocks and `PAIR_LOCK_EXPIRATION` have both passed.
 	pub fn process_pair_with_lock(
 		&self,
 		remote_chain: u64,
 		local_chain: u64,
 		role: Role,
 	) -> ChainRequestResult<()> {
-		if !self.lock_is_open()? {
-			log::info!(
-				"We will not proceed because we have a process lock lock. Processing {} => {}",
-				remote_chain,
-				local_chain
+		let key = Self::pair_lock_storage_key(remote_chain, local_chain);
+		let mut lock =
+			StorageLock::<BlockAndTime<frame_system::Pallet<T>>>::with_block_and_time_deadline(
+				key.as_slice(),
+				PAIR_LOCK_EXPIRATION_BLOCKS,
+				Duration::from_millis(PAIR_LOCK_EXPIRATION),
 			);
-			return Ok(());
+		let _guard = match lock.try_lock() {
+			Ok(guard) => guard,
+			Err(_) => {
+				log::info!(
+					"We will not proceed because the pair is locked. Processing {} => {}",
+					remote_chain,
+					local_chain
+				);
+				return Ok(());
+			},
+		};
+		let trace = TraceContext { trace_id: Self::new_trace_id(), remote_chain, local_chain };
+		for client in self.clients.iter() {
+			client.contract.trace.set(trace);
+		}
+		log::info!("{} Processing pair as {:?}", trace, role);
+		let recording = StorageValueRef::persistent(RECORDING_MODE_KEY)
+			.get::<bool>()
+			.ok()
+			.flatten()
+			.unwrap_or_default();
+		if recording {
+			let (block_number, now, simulation) = self
+				.clients
+				.first()
+				.map(|c| (c.block_number, c.now, c.contract.dry_run))
+				.unwrap_or_default();
+			Recorder::start(PairRecording {
+				remote_chain,
+				local_chain,
+				role: role.clone(),
+				block_number,
+				now,
+				simulation,
+				state: Vec::new(),
+				exchanges: Vec::new(),
+				decisions: Vec::new(),
+			});
+		}
+		let rv = self.process_pair(remote_chain, local_chain, role, trace);
+		if recording {
+			if let Err(e) = &rv {
+				Recorder::decision(Decision::Failed(e.clone()));
+			}
+			if let Some(session) = Recorder::finish() {
+				Recorder::save(PairRecording { decisions: session.decisions, ..session.recording });
+			}
+		}
+		match &rv {
+			Ok(summary) => log::info!(
+				"{} Run summary: pending txs {}, work found {}, submitted tx {:?}",
+				trace,
+				summary.pending_txs,
+				summary.work_found,
+				summary.submitted_tx
+			),
+			Err(e) => {
+				log::info!("{} Run summary: failed with {:?}", trace, e);
+				self.record_pair_error(remote_chain, local_chain, e);
+			},
+		}
+		rv.map(|_| ())
+	}
+
+	/// Run the pair again as recorded in `recording`, against the recorded responses and from
+	/// the recorded state. The clients are expected to be set up for the block and time of the
+	/// recording.
+	pub fn replay(&self, recording: PairRecording) -> ReplayReport {
+		let (remote_chain, local_chain) = (recording.remote_chain, recording.local_chain);
+		let trace = TraceContext { trace_id: Self::new_trace_id(), remote_chain, local_chain };
+		for client in self.clients.iter() {
+			client.contract.trace.set(trace);
+		}
+		log::info!("{} Replaying pair run of block {}", trace, recording.block_number);
+		recording.restore();
+		Recorder::start_replay(recording.clone());
+		if let Err(e) = self.process_pair(remote_chain, local_chain, recording.role.clone(), trace)
+		{
+			Recorder::decision(Decision::Failed(e));
 		}
-		self.lock()?;
-		let tx = self.stored_pending_transactions(9999)?;
-		log::info!("RESULTAT OF PENDING_TX {:?}", tx);
-		let rv = self.process_pair(remote_chain, local_chain, role);
-		self.remove_lock()?;
-		rv
+		let session = Recorder::finish();
+		let (replayed, cursor) = session.map(|s| (s.decisions, s.cursor)).unwrap_or_default();
+		let report = ReplayReport {
+			unused_exchanges: (recording.exchanges.len() as u32).saturating_sub(cursor),
+			recorded: recording.decisions,
+			replayed,
+		};
+		if report.matches() {
+			log::info!("{} Replay matches the recorded run", trace);
+		} else {
+			log::warn!("{} Replay diverged from the recorded run: {:?}", trace, report);
+		}
+		report
+	}
+
+	fn new_trace_id() -> u64 {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(&sp_io::offchain::random_seed()[..8]);
+		u64::from_le_bytes(bytes)
+	}
+
+	/// Storage key for the last error of the `remote_chain -> local_chain` pair
+	pub fn pair_error_storage_key(remote_chain: u64, local_chain: u64) -> Vec<u8> {
+		let remote = ChainUtils::bytes_to_hex(remote_chain.to_be_bytes().as_slice());
+		let local = ChainUtils::bytes_to_hex(local_chain.to_be_bytes().as_slice());
+		[b"quantum-portal::last_error::".as_slice(), remote.as_slice(), b"::", local.as_slice()]
+			.concat()
+	}
+
+	fn record_pair_error(&self, remote_chain: u64, local_chain: u64, error: &ChainRequestError) {
+		let timestamp = self.clients.first().map(|c| c.now).unwrap_or_default();
+		log::error!("Error processing pair {} -> {} : {:?}", remote_chain, local_chain, error);
+		let record = PairError { remote_chain, local_chain, error: error.clone(), timestamp };
+		let key = Self::pair_error_storage_key(remote_chain, local_chain);
+		StorageValueRef::persistent(key.as_slice()).set(&record);
+	}
+
+	/// Returns the last error recorded for the `remote_chain -> local_chain` pair
+	pub fn last_pair_error(remote_chain: u64, local_chain: u64) -> Option<PairError> {
+		let key = Self::pair_error_storage_key(remote_chain, local_chain);
+		StorageValueRef::persistent(key.as_slice()).get().ok().flatten()
 	}
 
 	pub fn test_tx_storage_and_status(&self) -> ChainRequestResult<()> {
@@ -145,65 +437,134 @@ impl<T: Config> QuantumPortalService<T> {
 		remote_chain: u64,
 		local_chain: u64,
 		role: Role,
-	) -> ChainRequestResult<()> {
+		trace: TraceContext,
+	) -> ChainRequestResult<PairRunSummary> {
 		// Processes between two chains.
 		// If there is an existing pending tx, for this pair, it will wait until the pending is
 		// completed or timed out.
 		// Nonce management? :: V1. No special nonce management
 		//                      V2. TODO: record and re-use the nonce to ensure controlled timeouts
 
-		log::info!("process_pair: {} -> {}", remote_chain, local_chain);
-		let live_txs = self.pending_transactions(local_chain)?; // TODO: Consider having separate config per pair
+		let mut summary = PairRunSummary::default();
+		// statuses are refreshed by `refresh_pending_transactions` before the pairs are processed
+		let live_txs = self.stored_pending_transactions(local_chain)?; // TODO: Consider having separate config per pair
 		if !live_txs.is_empty() {
 			log::info!(
-				"There are already {} pending transactions. Ignoring this round",
+				"{} There are already {} pending transactions. Ignoring this round",
+				trace,
 				live_txs.len()
 			);
-			return Ok(());
+			summary.pending_txs = live_txs.len();
+			Recorder::decision(Decision::Waiting(live_txs.len() as u32));
+			return Ok(summary);
 		}
 		let local_client: &QuantumPortalClient<T> =
-			&self.clients[self.find_client_idx(local_chain)];
+			&self.clients[self.find_client_idx(local_chain)?];
 		let remote_client: &QuantumPortalClient<T> =
-			&self.clients[self.find_client_idx(remote_chain)];
+			&self.clients[self.find_client_idx(remote_chain)?];
 		log::info!(
-			"Clients: {} <> {} :: {} <> {}",
+			"{} Clients: {} <> {} :: {} <> {}",
+			trace,
 			local_client.block_number,
 			remote_client.block_number,
 			str::from_utf8(&local_client.contract.http_api[..]).unwrap(),
 			str::from_utf8(&remote_client.contract.http_api[..]).unwrap()
 		);
 		let now = local_client.now;
+		let simulation = local_client.contract.dry_run;
 
-		// mine if role is miner
-		if role == Role::QP_MINER {
-			let mine_tx = local_client.mine(remote_client)?;
-			if mine_tx.is_some() {
-				self.save_tx(PendingTransaction::MineTransaction(
-					local_chain,
-					remote_chain,
-					now,
-					mine_tx.unwrap(),
-				))?
-			}
-		}
+		let key = Self::work_queue_storage_key(remote_chain, local_chain);
+		Recorder::track(&key);
+		let stored_queue = StorageValueRef::persistent(key.as_slice());
+		let mut queue = stored_queue
+			.get::<WorkQueue>()
+			.ok()
+			.flatten()
+			.filter(|queue| !queue.is_done())
+			.unwrap_or_else(|| WorkQueue::plan(&role, now));
 
-		// finalize if role is finalizer
-		if role == Role::QP_FINALIZER {
-			let fin_tx = local_client.finalize(remote_chain)?;
-			if fin_tx.is_some() {
-				self.save_tx(PendingTransaction::FinalizeTransaction(
-					local_chain,
-					now,
-					fin_tx.unwrap(),
-				))?
+		while let Some(item) = queue.next_mut() {
+			match item.state {
+				WorkState::Submitted(tx_id) => {
+					// no longer pending, a transaction that did not confirm is sent again
+					let state = match Self::tx_status(local_chain, &tx_id).map(|r| r.outcome) {
+						Some(TransactionOutcome::Confirmed) => WorkState::Done,
+						// follow the replacement, it is still pending
+						Some(TransactionOutcome::SpedUp(replacement)) =>
+							WorkState::Submitted(replacement),
+						outcome => {
+							log::warn!("{} {:?} ended as {:?}, retrying", trace, tx_id, outcome);
+							WorkState::Queued
+						},
+					};
+					item.transition(state, now, trace);
+				},
+				WorkState::Queued => {
+					let tx = match (item.kind, &role) {
+						(WorkKind::Finalize, Role::QP_FINALIZER) =>
+							local_client.finalize(remote_chain)?,
+						(WorkKind::Finalize, _) => {
+							if !local_client.is_mined_block_finalized(remote_chain)? {
+								log::info!(
+									"{} Waiting for the last mined block to be finalized",
+									trace
+								);
+								break;
+							}
+							None
+						},
+						(WorkKind::Mine, Role::QP_MINER) => local_client.mine(remote_client)?,
+						(WorkKind::Mine, _) => None,
+					};
+					summary.work_found |= tx.is_some();
+					summary.submitted_tx = summary.submitted_tx.or(tx);
+					match tx {
+						Some(tx_id) if !simulation => {
+							self.save_tx(match item.kind {
+								WorkKind::Mine => PendingTransaction::MineTransaction(
+									local_chain,
+									remote_chain,
+									now,
+									tx_id,
+								),
+								WorkKind::Finalize =>
+									PendingTransaction::FinalizeTransaction(local_chain, now, tx_id),
+							})?;
+							local_client.contract.clear_journal();
+							item.transition(WorkState::Submitted(tx_id), now, trace);
+							// the next items wait for this one to confirm
+							break;
+						},
+						Some(tx_id) => {
+							log::info!(
+								"{} Simulation: {:?} transaction {:?} not saved",
+								trace,
+								item.kind,
+								tx_id
+							);
+							item.transition(WorkState::Done, now, trace);
+						},
+						None => item.transition(WorkState::Done, now, trace),
+					}
+				},
+				WorkState::Done => break,
 			}
 		}
+		stored_queue.set(&queue);
 
-		self.remove_lock()?;
-		Ok(())
+		Ok(summary)
 	}
 
-	fn storage_key(key: u64) -> Vec<u8> {
+	/// Storage key of the work queue of the `remote_chain -> local_chain` pair
+	pub fn work_queue_storage_key(remote_chain: u64, local_chain: u64) -> Vec<u8> {
+		let remote = ChainUtils::bytes_to_hex(remote_chain.to_be_bytes().as_slice());
+		let local = ChainUtils::bytes_to_hex(local_chain.to_be_bytes().as_slice());
+		[b"quantum-portal::work_queue::".as_slice(), remote.as_slice(), b"::", local.as_slice()]
+			.concat()
+	}
+
+	/// Storage key for the pending transaction of chain `key`
+	pub fn storage_key(key: u64) -> Vec<u8> {
 		let key = key.to_be_bytes();
 		let key = key.as_slice();
 		let key = ChainUtils::bytes_to_hex(key);
@@ -222,14 +583,536 @@ impl<T: Config> QuantumPortalService<T> {
 		Ok(())
 	}
 
+	/// Reconcile the transaction journals left by a worker that stopped between building a
+	/// transaction and tracking it as pending.
+	pub fn recover_journals(&self) {
+		for client in self.clients.iter() {
+			if let Err(e) = self.recover_journal(client) {
+				log::warn!(
+					"Error recovering the journal of chain {} : {:?}",
+					client.contract.chain_id,
+					e
+				);
+			}
+		}
+	}
+
+	fn recover_journal(&self, client: &QuantumPortalClient<T>) -> ChainRequestResult<()> {
+		let entry = match client.contract.journal_entry() {
+			Some(entry) => entry,
+			None => return Ok(()),
+		};
+		let chain_id = client.contract.chain_id;
+		log::info!("Recovering journaled transaction {:?} on chain {}", entry.tx_hash, chain_id);
+
+		let tracked = self.stored_pending_transactions(chain_id)?.iter().any(|t| match t {
+			PendingTransaction::MineTransaction(_, _, _, tx_id) |
+			PendingTransaction::FinalizeTransaction(_, _, tx_id) |
+			PendingTransaction::RecoveredTransaction(_, _, tx_id) => *tx_id == entry.tx_hash,
+			PendingTransaction::None => false,
+		});
+		if tracked {
+			client.contract.clear_journal();
+			return Ok(());
+		}
+
+		let url = str::from_utf8(&client.contract.http_api[..]).unwrap();
+		if ChainQueries::get_transaction_receipt(url, &entry.tx_hash)?.is_none() {
+			if client.contract.nonce(entry.from)? > entry.nonce {
+				// another transaction used the nonce, this one can never be mined
+				log::warn!("Journaled transaction {:?} was replaced, dropping it", entry.tx_hash);
+				client.contract.clear_journal();
+				return Ok(());
+			}
+			log::info!("Re-broadcasting journaled transaction {:?}", entry.tx_hash);
+			client.contract.broadcast(&entry.raw_tx)?;
+		}
+
+		self.save_tx(PendingTransaction::RecoveredTransaction(
+			chain_id,
+			client.now,
+			entry.tx_hash,
+		))?;
+		client.contract.clear_journal();
+		Ok(())
+	}
+
+	/// Storage key for the operator commands queued for the pending transactions of `chain_id`
+	pub fn tx_commands_storage_key(chain_id: u64) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::tx_commands::".as_slice(), chain.as_slice()].concat()
+	}
+
+	/// Run the operator commands queued for the pending transactions of `chain_ids`. Commands are
+	/// only tried once, a failed one has to be queued again.
+	pub fn run_tx_commands(&self, chain_ids: &[u64]) {
+		for chain_id in chain_ids {
+			let key = Self::tx_commands_storage_key(*chain_id);
+			let mut queued = StorageValueRef::persistent(key.as_slice());
+			let commands = queued.get::<Vec<TxCommand>>().ok().flatten().unwrap_or_default();
+			if commands.is_empty() {
+				continue;
+			}
+			queued.clear();
+			for command in commands {
+				if let Err(e) = self.run_tx_command(*chain_id, &command) {
+					log::warn!("Error running {:?} on chain {} : {:?}", command, chain_id, e);
+				}
+			}
+		}
+	}
+
+	fn run_tx_command(&self, chain_id: u64, command: &TxCommand) -> ChainRequestResult<()> {
+		let client = &self.clients[self.find_client_idx(chain_id)?];
+		let tx_id = match command {
+			TxCommand::Cancel(tx_id) | TxCommand::Expire(tx_id) => *tx_id,
+		};
+		let tx = self.stored_pending_transactions(chain_id)?.into_iter().find(|t| match t {
+			PendingTransaction::MineTransaction(_, _, _, tid) |
+			PendingTransaction::FinalizeTransaction(_, _, tid) |
+			PendingTransaction::RecoveredTransaction(_, _, tid) => *tid == tx_id,
+			PendingTransaction::None => false,
+		});
+		let tx = match tx {
+			Some(tx) => tx,
+			None => {
+				log::warn!("Transaction {:?} is not pending on chain {}", tx_id, chain_id);
+				return Ok(());
+			},
+		};
+
+		match command {
+			TxCommand::Expire(_) => {
+				log::warn!("Expiring pending transaction {:?} on chain {}", tx_id, chain_id);
+				self.index_tx_status(&tx, TransactionOutcome::TimedOut, client.now);
+				self.remove_transaction_from_db(&tx)
+			},
+			TxCommand::Cancel(_) => {
+				let url = str::from_utf8(&client.contract.http_api[..]).unwrap();
+				let details = ChainQueries::get_transaction(url, &tx_id)?.ok_or_else(|| {
+					ChainRequestError::from(b"Transaction is unknown to the node".as_slice())
+				})?;
+				let signer = client.submitters.signers.iter().find(|s| s.from == details.from);
+				let signer = signer.ok_or_else(|| {
+					ChainRequestError::from(b"Transaction was not sent by a relayer key".as_slice())
+				})?;
+				// nodes only accept a replacement paying at least 10% more than the original
+				let bumped = details.gas_price.saturating_mul(U256::from(125_u32)) / 100;
+				let gas_price = bumped.max(client.contract.gas_price()?);
+				let replacement = client.contract.cancel(details.nonce, gas_price, signer)?;
+				if client.contract.dry_run {
+					return Ok(());
+				}
+				log::warn!(
+					"Cancelled pending transaction {:?} on chain {} with {:?} at nonce {}",
+					tx_id,
+					chain_id,
+					replacement,
+					details.nonce
+				);
+				self.index_tx_status(&tx, TransactionOutcome::Cancelled, client.now);
+				// no new work is sent until the replacement is mined
+				self.save_tx(PendingTransaction::RecoveredTransaction(
+					chain_id,
+					client.now,
+					replacement,
+				))
+			},
+		}
+	}
+
+	/// Check the status of the stored pending transactions of `chain_ids`, completed ones are
+	/// removed from storage.
+	pub fn refresh_pending_transactions(&self, chain_ids: &[u64]) {
+		for chain_id in chain_ids {
+			if let Err(e) = self.pending_transactions(*chain_id) {
+				log::warn!("Error checking pending transactions on chain {} : {:?}", chain_id, e);
+			}
+		}
+	}
+
+	/// Mirror the pending transactions of `chain_ids` on chain, for the chains where they changed
+	/// since the last report.
+	pub fn mirror_pending_transactions(&self, chain_ids: &[u64]) {
+		for chain_id in chain_ids {
+			if let Err(e) = self.mirror_pending(*chain_id) {
+				log::warn!("Error mirroring pending transactions of chain {} : {:?}", chain_id, e);
+			}
+		}
+	}
+
+	fn mirror_pending(&self, chain_id: u64) -> ChainRequestResult<()> {
+		let client = &self.clients[self.find_client_idx(chain_id)?];
+		if client.contract.dry_run {
+			return Ok(());
+		}
+		let pending = self.stored_pending_transactions(chain_id)?;
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		let key = [b"quantum-portal::mirrored::".as_slice(), chain.as_slice()].concat();
+		let mirrored = StorageValueRef::persistent(key.as_slice());
+		if mirrored.get::<Vec<PendingTransaction>>().ok().flatten().unwrap_or_default() == pending {
+			return Ok(());
+		}
+
+		if pending.len() > MAX_PAYLOAD_ITEMS as usize {
+			log::warn!(
+				"Mirroring {} of the {} pending transactions of chain {}",
+				MAX_PAYLOAD_ITEMS,
+				pending.len(),
+				chain_id
+			);
+		}
+		let payload = PendingTransactionsPayload {
+			chain_id,
+			pending: PayloadItems::truncate_from(pending.clone()),
+			block_number: client.block_number,
+		};
+		let signature = client.signer.signer(&payload.signing_hash())?;
+		let call = crate::Call::mirror_pending_transactions { payload, signature };
+		SubmitTransaction::<T, crate::Call<T>>::submit_unsigned_transaction(call.into())
+			.map_err(|_| ChainRequestError::from(b"Could not submit the transaction".as_slice()))?;
+		mirrored.set(&pending);
+		Ok(())
+	}
+
+	/// Report the messages registered on the configured chains for `target_chain` to the pallet,
+	/// which passes them to its `InboundMessageHandler`, in payloads of up to `MAX_PAYLOAD_ITEMS`
+	/// messages. The scan cursor of a chain only moves on once all its messages are submitted.
+	pub fn ingest_inbound_messages(&self, target_chain: u64) {
+		for client in self.clients.iter().filter(|c| c.contract.chain_id != target_chain) {
+			if let Err(e) = Self::ingest_inbound(client, target_chain) {
+				log::warn!(
+					"Error scanning chain {} for inbound messages : {:?}",
+					client.contract.chain_id,
+					e
+				);
+			}
+		}
+	}
+
+	fn ingest_inbound(
+		client: &QuantumPortalClient<T>,
+		target_chain: u64,
+	) -> ChainRequestResult<()> {
+		if client.contract.dry_run {
+			return Ok(());
+		}
+		let (messages, scanned) = client.inbound_messages(target_chain)?;
+		if !messages.is_empty() {
+			log::info!(
+				"Reporting {} inbound messages of chain {}",
+				messages.len(),
+				client.contract.chain_id
+			);
+		}
+		for chunk in messages.chunks(MAX_PAYLOAD_ITEMS as usize) {
+			// the cursor stays put, the rest is reported on the next run
+			let sequence = client
+				.next_payload_sequence(b"inbound_messages", client.contract.chain_id)
+				.ok_or_else(|| {
+					ChainRequestError::from(b"Too many payloads in the block".as_slice())
+				})?;
+			let payload = InboundMessagesPayload {
+				remote_chain: client.contract.chain_id,
+				messages: PayloadItems::truncate_from(chunk.to_vec()),
+				block_number: client.block_number,
+				sequence,
+			};
+			let signature = client.signer.signer(&payload.signing_hash())?;
+			let call = crate::Call::record_inbound_messages { payload, signature };
+			SubmitTransaction::<T, crate::Call<T>>::submit_unsigned_transaction(call.into())
+				.map_err(|_| {
+					ChainRequestError::from(b"Could not submit the transaction".as_slice())
+				})?;
+		}
+		StorageValueRef::persistent(&client.inbound_cursor_key()).set(&scanned);
+		Ok(())
+	}
+
+	/// Report the reward distributions of the master staking contracts of `sources` to the
+	/// pallet, which passes them to its `RewardsHandler`, in payloads of up to
+	/// `MAX_PAYLOAD_ITEMS` distributions. The scan cursor of a chain only moves on once all its
+	/// distributions are submitted.
+	pub fn ingest_reward_distributions(&self, sources: &[(ChainId, H160)]) {
+		for (chain_id, source) in sources {
+			if let Err(e) = self.ingest_rewards(*chain_id, *source) {
+				log::warn!("Error scanning chain {} for reward distributions : {:?}", chain_id, e);
+			}
+		}
+	}
+
+	fn ingest_rewards(&self, chain_id: ChainId, source: H160) -> ChainRequestResult<()> {
+		let client = &self.clients[self.find_client_idx(chain_id)?];
+		if client.contract.dry_run {
+			return Ok(());
+		}
+		let (distributions, scanned) = client.reward_distributions(source)?;
+		if !distributions.is_empty() {
+			log::info!(
+				"Reporting {} reward distributions of chain {}",
+				distributions.len(),
+				chain_id
+			);
+		}
+		for chunk in distributions.chunks(MAX_PAYLOAD_ITEMS as usize) {
+			// the cursor stays put, the rest is reported on the next run
+			let sequence = client.next_payload_sequence(b"rewards", chain_id).ok_or_else(|| {
+				ChainRequestError::from(b"Too many payloads in the block".as_slice())
+			})?;
+			let payload = RewardsPayload {
+				remote_chain: chain_id,
+				distributions: PayloadItems::truncate_from(chunk.to_vec()),
+				block_number: client.block_number,
+				sequence,
+			};
+			let signature = client.signer.signer(&payload.signing_hash())?;
+			let call = crate::Call::record_reward_distributions { payload, signature };
+			SubmitTransaction::<T, crate::Call<T>>::submit_unsigned_transaction(call.into())
+				.map_err(|_| {
+					ChainRequestError::from(b"Could not submit the transaction".as_slice())
+				})?;
+		}
+		StorageValueRef::persistent(&client.rewards_cursor_key()).set(&scanned);
+		Ok(())
+	}
+
+	/// Storage key for the relayer keys of `chain_id` with a low balance
+	pub fn low_balances_storage_key(chain_id: u64) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::low_balances::".as_slice(), chain.as_slice()].concat()
+	}
+
+	/// Check the balance of the relayer keys on `chain_ids` against the low balance threshold
+	/// of the chain, keys under it are recorded so operators top them up before the relayer
+	/// runs out of keys to submit from.
+	pub fn check_relayer_balances(&self, chain_ids: &[u64]) {
+		for chain_id in chain_ids {
+			if let Err(e) = self.check_balances(*chain_id) {
+				log::warn!("Error checking relayer balances on chain {} : {:?}", chain_id, e);
+			}
+		}
+	}
+
+	fn check_balances(&self, chain_id: u64) -> ChainRequestResult<()> {
+		let client = &self.clients[self.find_client_idx(chain_id)?];
+		let key = Self::low_balances_storage_key(chain_id);
+		let stored = StorageValueRef::persistent(key.as_slice());
+		let threshold = match client.config.low_balance_threshold {
+			Some(threshold) => threshold,
+			None => {
+				stored.clear();
+				return Ok(());
+			},
+		};
+		let previous = stored.get::<Vec<LowBalance>>().ok().flatten().unwrap_or_default();
+		let mut low_balances = Vec::new();
+		for signer in client.submitters.signers.iter() {
+			let balance = client.contract.balance(signer.from)?;
+			if balance >= threshold {
+				continue;
+			}
+			log::warn!(
+				"Relayer key {:?} balance {} on chain {} is under {}, top it up",
+				signer.from,
+				balance,
+				chain_id,
+				threshold
+			);
+			let since = previous
+				.iter()
+				.find(|low| low.address == signer.from)
+				.map_or(client.now, |low| low.since);
+			low_balances.push(LowBalance { address: signer.from, balance, since });
+		}
+		if low_balances.is_empty() {
+			stored.clear();
+		} else {
+			stored.set(&low_balances);
+		}
+		Ok(())
+	}
+
+	/// Report the balance of the custody `pools` to the pallet, once per accounting `era` for
+	/// each chain, so their solvency is checked against the value bridged into them
+	pub fn report_reserves(&self, pools: &[(ChainId, CustodyPool)], era: u32) {
+		for (chain_id, pool) in pools {
+			if let Err(e) = self.report_reserve(*chain_id, pool, era) {
+				log::warn!("Error reporting the custody pool of chain {} : {:?}", chain_id, e);
+			}
+		}
+	}
+
+	fn report_reserve(
+		&self,
+		chain_id: ChainId,
+		pool: &CustodyPool,
+		era: u32,
+	) -> ChainRequestResult<()> {
+		let client = &self.clients[self.find_client_idx(chain_id)?];
+		if client.contract.dry_run {
+			return Ok(());
+		}
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		let key = [b"quantum-portal::reserves_era::".as_slice(), chain.as_slice()].concat();
+		let reported = StorageValueRef::persistent(key.as_slice());
+		if reported.get::<u32>().ok().flatten() == Some(era) {
+			return Ok(());
+		}
+
+		let balance = client.custody_balance(pool)?;
+		let balance = if balance > U256::from(u128::MAX) { u128::MAX } else { balance.low_u128() };
+		let payload = ReservesPayload { chain_id, balance, block_number: client.block_number };
+		let signature = client.signer.signer(&payload.signing_hash())?;
+		let call = crate::Call::report_reserves { payload, signature };
+		SubmitTransaction::<T, crate::Call<T>>::submit_unsigned_transaction(call.into())
+			.map_err(|_| ChainRequestError::from(b"Could not submit the transaction".as_slice()))?;
+		reported.set(&era);
+		Ok(())
+	}
+
+	/// Check that the recently confirmed transactions of `chain_ids` are still in canonical
+	/// blocks. A transaction dropped by a reorg is tracked as pending again, so no new work is
+	/// sent until it is either included again or timed out, after which it is redone.
+	pub fn check_reorgs(&self, chain_ids: &[u64]) {
+		for chain_id in chain_ids {
+			if let Err(e) = self.check_reorg(*chain_id) {
+				log::warn!("Error checking chain {} for reorgs : {:?}", chain_id, e);
+			}
+		}
+	}
+
+	fn check_reorg(&self, chain_id: u64) -> ChainRequestResult<()> {
+		let confirmed = Self::confirmed_transactions(chain_id);
+		if confirmed.is_empty() {
+			return Ok(());
+		}
+		let client = &self.clients[self.find_client_idx(chain_id)?];
+		let url = str::from_utf8(&client.contract.http_api[..]).unwrap();
+		let latest = ChainQueries::block_number(url)?;
+
+		let mut kept = Vec::new();
+		for entry in confirmed {
+			if entry.block_number.saturating_add(client.config.reorg_depth) < latest {
+				// deep enough to be final
+				continue;
+			}
+			if ChainQueries::block_hash(url, entry.block_number)? == Some(entry.block_hash) {
+				kept.push(entry);
+				continue;
+			}
+
+			let tx_id = match &entry.tx {
+				PendingTransaction::MineTransaction(_, _, _, tid) |
+				PendingTransaction::FinalizeTransaction(_, _, tid) |
+				PendingTransaction::RecoveredTransaction(_, _, tid) => *tid,
+				PendingTransaction::None => continue,
+			};
+			match ChainQueries::get_transaction_status(url, &tx_id, 0)? {
+				TransactionStatus::Confirmed { block_number, block_hash } => {
+					log::info!(
+						"Transaction {:?} was included again in block {} after a reorg",
+						tx_id,
+						block_number
+					);
+					kept.push(ConfirmedTransaction { tx: entry.tx, block_number, block_hash });
+				},
+				_ => {
+					log::warn!(
+						"Reorg on chain {}, block {} no longer contains transaction {:?}",
+						chain_id,
+						entry.block_number,
+						tx_id
+					);
+					self.index_tx_status(&entry.tx, TransactionOutcome::Reorged, client.now);
+					if self.stored_pending_transactions(chain_id)?.is_empty() {
+						self.save_tx(entry.tx.requeued(client.now))?;
+					}
+				},
+			}
+		}
+		Self::set_confirmed_transactions(chain_id, kept);
+		Ok(())
+	}
+
+	/// Storage key for the recently confirmed transactions of `chain_id`
+	pub fn confirmed_storage_key(chain_id: u64) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::confirmed::".as_slice(), chain.as_slice()].concat()
+	}
+
+	/// Returns the confirmed transactions of `chain_id` still checked for reorgs
+	pub fn confirmed_transactions(chain_id: u64) -> Vec<ConfirmedTransaction> {
+		let key = Self::confirmed_storage_key(chain_id);
+		StorageValueRef::persistent(key.as_slice())
+			.get()
+			.ok()
+			.flatten()
+			.unwrap_or_default()
+	}
+
+	fn set_confirmed_transactions(chain_id: u64, confirmed: Vec<ConfirmedTransaction>) {
+		let key = Self::confirmed_storage_key(chain_id);
+		StorageValueRef::persistent(key.as_slice()).set(&confirmed);
+	}
+
+	fn track_confirmed(
+		&self,
+		chain_id: u64,
+		reorg_depth: u64,
+		tx: &PendingTransaction,
+		block_number: u64,
+		block_hash: H256,
+	) {
+		if reorg_depth == 0 {
+			return;
+		}
+		let mut confirmed = Self::confirmed_transactions(chain_id);
+		confirmed.push(ConfirmedTransaction { tx: tx.clone(), block_number, block_hash });
+		Self::set_confirmed_transactions(chain_id, confirmed);
+	}
+
+	/// Storage key for the relayer activity of `chain_id`
+	pub fn activity_storage_key(chain_id: u64) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		[b"quantum-portal::activity::".as_slice(), chain.as_slice()].concat()
+	}
+
+	/// Returns the last confirmed mine and finalize transactions of `chain_id`
+	pub fn relayer_activity(chain_id: u64) -> RelayerActivity {
+		let key = Self::activity_storage_key(chain_id);
+		StorageValueRef::persistent(key.as_slice())
+			.get()
+			.ok()
+			.flatten()
+			.unwrap_or_default()
+	}
+
+	fn record_activity(
+		chain_id: u64,
+		tx: &PendingTransaction,
+		block_number: u64,
+		block_hash: H256,
+	) {
+		let mut activity = Self::relayer_activity(chain_id);
+		let confirmed = Some(ConfirmedTransaction { tx: tx.clone(), block_number, block_hash });
+		match tx {
+			PendingTransaction::MineTransaction(..) => activity.last_mine = confirmed,
+			PendingTransaction::FinalizeTransaction(..) => activity.last_finalize = confirmed,
+			_ => return,
+		}
+		let key = Self::activity_storage_key(chain_id);
+		StorageValueRef::persistent(key.as_slice()).set(&activity);
+	}
+
 	fn pending_transactions(&self, chain_id: u64) -> ChainRequestResult<Vec<PendingTransaction>> {
 		let stored_pending_transactions = self.stored_pending_transactions(chain_id)?;
-		Ok(stored_pending_transactions
-			.into_iter()
-			.filter(
-				|t| self.is_tx_pending(t).unwrap(), // TODO: No unwrap here.
-			)
-			.collect())
+		let mut pending = Vec::new();
+		for t in stored_pending_transactions {
+			if self.is_tx_pending(&t)? {
+				pending.push(t);
+			}
+		}
+		Ok(pending)
 	}
 
 	fn stored_pending_transactions(
@@ -237,6 +1120,7 @@ impl<T: Config> QuantumPortalService<T> {
 		chain_id: u64,
 	) -> ChainRequestResult<Vec<PendingTransaction>> {
 		let key = Self::storage_key(chain_id);
+		Recorder::track(&key);
 		let key = key.as_slice();
 		let s = StorageValueRef::persistent(key);
 		let rv = s.get().unwrap();
@@ -258,6 +1142,33 @@ impl<T: Config> QuantumPortalService<T> {
 		Ok(())
 	}
 
+	/// Storage key for the status record of `tx_id` on `chain_id`
+	pub fn status_storage_key(chain_id: u64, tx_id: &H256) -> Vec<u8> {
+		let chain = ChainUtils::bytes_to_hex(chain_id.to_be_bytes().as_slice());
+		let tx = ChainUtils::bytes_to_hex(tx_id.as_bytes());
+		[b"quantum-portal::status::".as_slice(), chain.as_slice(), b"::", tx.as_slice()].concat()
+	}
+
+	/// Returns the status record of `tx_id` on `chain_id`, once it left the pending state
+	pub fn tx_status(chain_id: u64, tx_id: &H256) -> Option<TransactionStatusRecord> {
+		let key = Self::status_storage_key(chain_id, tx_id);
+		Recorder::track(&key);
+		StorageValueRef::persistent(key.as_slice()).get().ok().flatten()
+	}
+
+	fn index_tx_status(&self, t: &PendingTransaction, outcome: TransactionOutcome, now: u64) {
+		let (chain_id, tx_id) = match t {
+			PendingTransaction::MineTransaction(c, _, _, tid) => (c, tid),
+			PendingTransaction::FinalizeTransaction(c, _, tid) => (c, tid),
+			PendingTransaction::RecoveredTransaction(c, _, tid) => (c, tid),
+			PendingTransaction::None => return,
+		};
+		let key = Self::status_storage_key(*chain_id, tx_id);
+		let record = TransactionStatusRecord { tx: t.clone(), outcome, timestamp: now };
+		log::info!("Indexing transaction status {:?}", record);
+		StorageValueRef::persistent(key.as_slice()).set(&record);
+	}
+
 	fn is_tx_pending(&self, t: &PendingTransaction) -> ChainRequestResult<bool> {
 		// Check if the tx is still pending
 		// If so, return true.
@@ -265,12 +1176,16 @@ impl<T: Config> QuantumPortalService<T> {
 		// then return false
 		let (chain_id1, _chain_id2, timestamp, tx_id) = match t {
 			PendingTransaction::MineTransaction(c1, c2, timestamp, tid) => (c1, c2, timestamp, tid),
-			PendingTransaction::FinalizeTransaction(c, timestamp, tid) => {
-				(c, &0_u64, timestamp, tid)
-			},
+			PendingTransaction::FinalizeTransaction(c, timestamp, tid) =>
+				(c, &0_u64, timestamp, tid),
+			PendingTransaction::RecoveredTransaction(c, timestamp, tid) =>
+				(c, &0_u64, timestamp, tid),
 			PendingTransaction::None => panic!("tx is none"),
 		};
-		let client = &self.clients[self.find_client_idx(*chain_id1)];
+		let client = &self.clients[self.find_client_idx(*chain_id1)?];
+		if !Self::poll_due(client) {
+			return Ok(true);
+		}
 
 		log::info!(
 			"is_tx_pending {}::{:?} ({}) [Current time {}]",
@@ -282,15 +1197,25 @@ impl<T: Config> QuantumPortalService<T> {
 		let status = ChainQueries::get_transaction_status(
 			str::from_utf8(&client.contract.http_api[..]).unwrap(),
 			tx_id,
+			client.config.confirmation_depth,
 		)?;
 		let res = match status {
-			TransactionStatus::Confirmed => {
+			TransactionStatus::Confirmed { block_number, block_hash } => {
 				// Remove
 				log::info!(
 					"The transaction is confirmed! {} - {}",
 					chain_id1,
 					str::from_utf8(ChainUtils::h256_to_hex_0x(tx_id).as_slice()).unwrap()
 				);
+				self.index_tx_status(t, TransactionOutcome::Confirmed, client.now);
+				Self::record_activity(*chain_id1, t, block_number, block_hash);
+				self.track_confirmed(
+					*chain_id1,
+					client.config.reorg_depth,
+					t,
+					block_number,
+					block_hash,
+				);
 				self.remove_transaction_from_db(t)?;
 				false
 			},
@@ -301,20 +1226,42 @@ impl<T: Config> QuantumPortalService<T> {
 					chain_id1,
 					str::from_utf8(ChainUtils::h256_to_hex_0x(tx_id).as_slice()).unwrap()
 				);
+				self.index_tx_status(t, TransactionOutcome::Failed, client.now);
 				self.remove_transaction_from_db(t)?;
 				false
 			},
-			TransactionStatus::Pending => true,
+			TransactionStatus::Pending => match client.config.max_pending_age {
+				Some(max_age) if timestamp.saturating_add(max_age) < client.now => {
+					log::error!(
+						"The transaction is pending for too long! Please investigate {} - {}",
+						chain_id1,
+						str::from_utf8(ChainUtils::h256_to_hex_0x(tx_id).as_slice()).unwrap()
+					);
+					self.index_tx_status(t, TransactionOutcome::TimedOut, client.now);
+					self.remove_transaction_from_db(t)?;
+					false
+				},
+				_ => true,
+			},
 			TransactionStatus::NotFound => {
-				if (timestamp + TIMEOUT) < client.now {
+				if timestamp.saturating_add(client.config.pending_timeout) < client.now {
 					log::error!(
 						"The transaction is timed out! Please investigate {} - {}",
 						chain_id1,
 						str::from_utf8(ChainUtils::h256_to_hex_0x(tx_id).as_slice()).unwrap()
 					);
+					self.index_tx_status(t, TransactionOutcome::TimedOut, client.now);
 					self.remove_transaction_from_db(t)?;
 					false
 				} else {
+					match client.config.speed_up_after {
+						Some(after) if timestamp.saturating_add(after) < client.now => {
+							if let Err(e) = self.speed_up(client, t, tx_id) {
+								log::warn!("Could not speed up {:?} : {:?}", tx_id, e);
+							}
+						},
+						_ => {},
+					}
 					true
 				}
 			},
@@ -322,16 +1269,178 @@ impl<T: Config> QuantumPortalService<T> {
 		Ok(res)
 	}
 
-	fn find_client_idx(&self, chain_id: u64) -> usize {
+	/// Send the pending transaction `t` again at the same nonce with its gas price raised by
+	/// `speed_up_percent`, up to `max_gas_price`. The replacement is tracked in place of `t` from
+	/// now, so it gets the whole pending timeout.
+	fn speed_up(
+		&self,
+		client: &QuantumPortalClient<T>,
+		t: &PendingTransaction,
+		tx_id: &H256,
+	) -> ChainRequestResult<()> {
+		let url = str::from_utf8(&client.contract.http_api[..]).unwrap();
+		let details = match ChainQueries::get_transaction(url, tx_id)? {
+			Some(details) => details,
+			None => {
+				log::warn!("Transaction {:?} is unknown to the node, not sped up", tx_id);
+				return Ok(());
+			},
+		};
+		let signer = client.submitters.signers.iter().find(|s| s.from == details.from);
+		let signer = signer.ok_or_else(|| {
+			ChainRequestError::from(b"Transaction was not sent by a relayer key".as_slice())
+		})?;
+		let bumped = details
+			.gas_price
+			.saturating_mul(U256::from(100 + client.config.speed_up_percent)) /
+			100;
+		let gas_price = client.config.max_gas_price.map_or(bumped, |max| bumped.min(max));
+		if gas_price <= details.gas_price {
+			log::warn!(
+				"Transaction {:?} is at the maximum gas price {}, not sped up",
+				tx_id,
+				details.gas_price
+			);
+			return Ok(());
+		}
+
+		let replacement = client.contract.resend(&details, gas_price, signer)?;
+		if client.contract.dry_run {
+			return Ok(());
+		}
+		log::warn!(
+			"Sped up pending transaction {:?} on chain {} with {:?} at gas price {}",
+			tx_id,
+			client.contract.chain_id,
+			replacement,
+			gas_price
+		);
+		self.index_tx_status(t, TransactionOutcome::SpedUp(replacement), client.now);
+		self.save_tx(t.replaced(client.now, replacement))
+	}
+
+	/// Whether the pending transactions of the client chain are due for a status check, as set by
+	/// its poll interval
+	fn poll_due(client: &QuantumPortalClient<T>) -> bool {
+		let chain = ChainUtils::bytes_to_hex(client.contract.chain_id.to_be_bytes().as_slice());
+		let key = [b"quantum-portal::last_poll::".as_slice(), chain.as_slice()].concat();
+		let last_poll = StorageValueRef::persistent(key.as_slice());
+		let last = last_poll.get::<u64>().ok().flatten().unwrap_or_default();
+		if client.now < last.saturating_add(client.config.poll_interval) {
+			log::info!(
+				"Pending transactions of chain {} were checked recently",
+				client.contract.chain_id
+			);
+			return false;
+		}
+		last_poll.set(&client.now);
+		true
+	}
+
+	fn find_client_idx(&self, chain_id: u64) -> ChainRequestResult<usize> {
 		let c = self.clients.as_slice();
-		c.iter().position(|c| c.contract.chain_id == chain_id).unwrap()
+		c.iter()
+			.position(|c| c.contract.chain_id == chain_id)
+			.ok_or(ChainRequestError::ChainNotConfigured(chain_id))
 	}
 
 	fn storage_key_for_tx(tx: &PendingTransaction) -> u64 {
 		*match tx {
 			PendingTransaction::MineTransaction(c, _, _, _) => c,
 			PendingTransaction::FinalizeTransaction(c, _, _) => c,
+			PendingTransaction::RecoveredTransaction(c, _, _) => c,
 			PendingTransaction::None => panic!("tx is none. Cannot save"),
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{WorkItem, WorkKind, WorkQueue, WorkState};
+	use crate::{
+		chain_utils::TraceContext,
+		qp_types::Role,
+		recording::{Decision, PairRecording, Recorder},
+	};
+	use sp_core::{
+		offchain::{testing::TestOffchainExt, OffchainDbExt},
+		H256,
+	};
+
+	fn with_offchain(f: impl FnOnce()) {
+		let (offchain, _state) = TestOffchainExt::new();
+		let mut ext = sp_io::TestExternalities::default();
+		ext.register_extension(OffchainDbExt::new(offchain));
+		ext.execute_with(f);
+	}
+
+	fn kinds(queue: &WorkQueue) -> Vec<WorkKind> {
+		queue.items.iter().map(|item| item.kind).collect()
+	}
+
+	#[test]
+	fn plan_queues_finalize_before_mine() {
+		for role in [Role::QP_MINER, Role::QP_FINALIZER] {
+			let queue = WorkQueue::plan(&role, 10);
+			assert_eq!(kinds(&queue), vec![WorkKind::Finalize, WorkKind::Mine]);
+			assert!(queue
+				.items
+				.iter()
+				.all(|item| item.state == WorkState::Queued && item.updated_at == 10));
+			assert!(!queue.is_done());
+		}
+
+		let queue = WorkQueue::plan(&Role::None, 10);
+		assert!(queue.items.is_empty());
+		assert!(queue.is_done());
+	}
+
+	#[test]
+	fn mine_waits_for_the_finalize_to_be_done() {
+		with_offchain(|| {
+			let trace = TraceContext::default();
+			let mut queue = WorkQueue::plan(&Role::QP_FINALIZER, 10);
+
+			let finalize = queue.next_mut().unwrap();
+			assert_eq!(finalize.kind, WorkKind::Finalize);
+			finalize.transition(WorkState::Submitted(H256::repeat_byte(1)), 11, trace);
+			// a pending finalize still holds the mine back
+			assert_eq!(queue.next_mut().unwrap().kind, WorkKind::Finalize);
+
+			queue.next_mut().unwrap().transition(WorkState::Done, 12, trace);
+			let mine = queue.next_mut().unwrap();
+			assert_eq!(mine.kind, WorkKind::Mine);
+			mine.transition(WorkState::Done, 13, trace);
+			assert!(queue.next_mut().is_none());
+			assert!(queue.is_done());
+		});
+	}
+
+	#[test]
+	fn transition_records_the_new_state() {
+		with_offchain(|| {
+			Recorder::start(PairRecording {
+				remote_chain: 1,
+				local_chain: 2,
+				role: Role::QP_MINER,
+				block_number: 100,
+				now: 1_000,
+				simulation: false,
+				state: Vec::new(),
+				exchanges: Vec::new(),
+				decisions: Vec::new(),
+			});
+			let mut item =
+				WorkItem { kind: WorkKind::Mine, state: WorkState::Queued, updated_at: 1 };
+			let tx_id = H256::repeat_byte(2);
+
+			item.transition(WorkState::Submitted(tx_id), 5, TraceContext::default());
+			assert_eq!(item.state, WorkState::Submitted(tx_id));
+			assert_eq!(item.updated_at, 5);
+			assert_eq!(
+				Recorder::finish().unwrap().decisions,
+				vec![Decision::Transition(WorkKind::Mine, WorkState::Submitted(tx_id))]
+			);
+		});
+	}
+}
@@ -0,0 +1,317 @@
+// Copyright 2019-2024 Ferrum Inc.
+// This file is part of Ferrum.
+
+// Ferrum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Ferrum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Record and replay of relayer runs. A recorded `process_pair` run keeps the offchain state it
+//! started from, every json-rpc exchange and every decision taken, so the run can be replayed
+//! offline against the same responses and its decisions compared.
+use crate::{
+	chain_queries::HttpTransport,
+	chain_utils::{ChainRequestError, ChainRequestResult, ChainUtils},
+	qp_types::Role,
+	quantum_portal_service::{WorkKind, WorkState},
+};
+use parity_scale_codec::{Decode, Encode};
+use sp_core::{ecdsa, offchain::StorageKind, H256};
+use sp_runtime::offchain::storage::StorageValueRef;
+use sp_std::{prelude::*, str};
+
+/// Number of recorded runs kept for each pair, the oldest is dropped first
+pub const MAX_RECORDINGS: usize = 5;
+
+const SESSION_KEY: &[u8] = b"quantum-portal::recording::session";
+
+/// A json-rpc request and the status and body the endpoint returned, or the transport error
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct RecordedExchange {
+	/// Hash of the endpoint url, the url is not kept since it may carry credentials
+	pub url: H256,
+	pub request: Vec<u8>,
+	pub response: ChainRequestResult<(u16, Vec<u8>)>,
+}
+
+/// A decision taken by the relayer during a run
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Decision {
+	/// No work is done while transactions of the local chain are pending
+	Waiting(u32),
+	/// A work item moved to a new state
+	Transition(WorkKind, WorkState),
+	/// The run stopped with an error
+	Failed(ChainRequestError),
+}
+
+/// A recorded `process_pair` run
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct PairRecording {
+	pub remote_chain: u64,
+	pub local_chain: u64,
+	pub role: Role,
+	pub block_number: u64,
+	pub now: u64,
+	pub simulation: bool,
+	/// Offchain storage read by the run, as it was before the run changed it
+	pub state: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+	pub exchanges: Vec<RecordedExchange>,
+	pub decisions: Vec<Decision>,
+}
+
+impl PairRecording {
+	/// Storage key for the recorded runs of the `remote_chain -> local_chain` pair
+	pub fn storage_key(remote_chain: u64, local_chain: u64) -> Vec<u8> {
+		let remote = ChainUtils::bytes_to_hex(remote_chain.to_be_bytes().as_slice());
+		let local = ChainUtils::bytes_to_hex(local_chain.to_be_bytes().as_slice());
+		[b"quantum-portal::recordings::".as_slice(), remote.as_slice(), b"::", local.as_slice()]
+			.concat()
+	}
+
+	/// Put the offchain storage back in the state the run started from
+	pub fn restore(&self) {
+		for (key, value) in self.state.iter() {
+			match value {
+				Some(value) =>
+					sp_io::offchain::local_storage_set(StorageKind::PERSISTENT, key, value),
+				None => sp_io::offchain::local_storage_clear(StorageKind::PERSISTENT, key),
+			}
+		}
+	}
+}
+
+/// Outcome of a replay, the replay reproduced the run when `matches` is true
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct ReplayReport {
+	pub recorded: Vec<Decision>,
+	pub replayed: Vec<Decision>,
+	/// Recorded exchanges the replay did not get to, the replay stopped early
+	pub unused_exchanges: u32,
+}
+
+impl ReplayReport {
+	pub fn matches(&self) -> bool {
+		self.recorded == self.replayed && self.unused_exchanges == 0
+	}
+}
+
+/// The run being recorded or replayed, kept in offchain storage while it lasts
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct Session {
+	pub replaying: bool,
+	pub recording: PairRecording,
+	/// Next exchange served by a replay
+	pub cursor: u32,
+	pub decisions: Vec<Decision>,
+}
+
+pub struct Recorder;
+
+impl Recorder {
+	fn session() -> Option<Session> {
+		StorageValueRef::persistent(SESSION_KEY).get().ok().flatten()
+	}
+
+	fn set_session(session: &Session) {
+		StorageValueRef::persistent(SESSION_KEY).set(session);
+	}
+
+	/// Record the run starting now in `recording`
+	pub fn start(recording: PairRecording) {
+		Self::set_session(&Session { replaying: false, recording, cursor: 0, decisions: vec![] });
+	}
+
+	/// Serve the json-rpc requests of the run starting now from `recording`
+	pub fn start_replay(recording: PairRecording) {
+		Self::set_session(&Session { replaying: true, recording, cursor: 0, decisions: vec![] });
+	}
+
+	/// End the session, returns it if there was one
+	pub fn finish() -> Option<Session> {
+		let session = Self::session();
+		StorageValueRef::persistent(SESSION_KEY).clear();
+		session
+	}
+
+	/// Whether the run in progress is a replay
+	pub fn replaying() -> bool {
+		Self::session().map_or(false, |session| session.replaying)
+	}
+
+	/// Current time, the time of the recorded run while it is replayed
+	pub fn now() -> u64 {
+		match Self::session() {
+			Some(session) if session.replaying => session.recording.now,
+			_ => sp_io::offchain::timestamp().unix_millis(),
+		}
+	}
+
+	/// Keep the value `key` has before the run changes it with the recorded state, for storage
+	/// read by the run that decides which requests are sent
+	pub fn track(key: &[u8]) {
+		if let Some(mut session) = Self::session() {
+			if session.replaying || session.recording.state.iter().any(|(k, _)| k == key) {
+				return;
+			}
+			let value = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, key);
+			session.recording.state.push((key.to_vec(), value));
+			Self::set_session(&session);
+		}
+	}
+
+	/// Signature used in place of the relayer key while replaying, so a run can be replayed
+	/// without the key. The broadcast transactions are matched by method only for this reason.
+	pub fn replay_signature() -> ecdsa::Signature {
+		let mut raw = [0_u8; 65];
+		raw[31] = 1;
+		raw[63] = 1;
+		ecdsa::Signature::from_raw(raw)
+	}
+
+	/// Note `decision` in the session, if any
+	pub fn decision(decision: Decision) {
+		if let Some(mut session) = Self::session() {
+			session.decisions.push(decision);
+			Self::set_session(&session);
+		}
+	}
+
+	/// Keep `recording` with the last recorded runs of its pair
+	pub fn save(recording: PairRecording) {
+		let key = PairRecording::storage_key(recording.remote_chain, recording.local_chain);
+		let stored = StorageValueRef::persistent(key.as_slice());
+		let mut recordings = stored.get::<Vec<PairRecording>>().ok().flatten().unwrap_or_default();
+		recordings.push(recording);
+		if recordings.len() > MAX_RECORDINGS {
+			recordings.remove(0);
+		}
+		stored.set(&recordings);
+	}
+}
+
+/// Run `f` with `inner`, wrapped so the requests are recorded or replayed while a session is on
+pub fn with_session<R>(inner: &dyn HttpTransport, f: impl FnOnce(&dyn HttpTransport) -> R) -> R {
+	match Recorder::session() {
+		Some(session) if session.replaying => f(&ReplayTransport),
+		Some(_) => f(&RecordingTransport { inner }),
+		None => f(inner),
+	}
+}
+
+struct RecordingTransport<'a> {
+	inner: &'a dyn HttpTransport,
+}
+
+impl HttpTransport for RecordingTransport<'_> {
+	fn post(&self, url: &str, body: &[u8]) -> ChainRequestResult<(u16, Vec<u8>)> {
+		let response = self.inner.post(url, body);
+		if let Some(mut session) = Recorder::session() {
+			session.recording.exchanges.push(RecordedExchange {
+				url: ChainUtils::keccack(url.as_bytes()),
+				request: body.to_vec(),
+				response: response.clone(),
+			});
+			Recorder::set_session(&session);
+		}
+		response
+	}
+}
+
+/// Serves the recorded responses in order, a request that differs from the recorded one means
+/// the replay diverged from the run
+struct ReplayTransport;
+
+impl HttpTransport for ReplayTransport {
+	fn post(&self, url: &str, body: &[u8]) -> ChainRequestResult<(u16, Vec<u8>)> {
+		let mut session = Recorder::session().ok_or(ChainRequestError::RpcTransportError)?;
+		let exchange = session.recording.exchanges.get(session.cursor as usize).cloned();
+		match exchange {
+			Some(exchange)
+				if exchange.url == ChainUtils::keccack(url.as_bytes()) &&
+					(exchange.request == body ||
+						is_broadcast(&exchange.request) && is_broadcast(body)) =>
+			{
+				session.cursor += 1;
+				Recorder::set_session(&session);
+				exchange.response
+			},
+			_ => {
+				log::warn!(
+					"Replay diverged at exchange {}, unexpected request {}",
+					session.cursor,
+					str::from_utf8(body).unwrap_or_default()
+				);
+				Err(ChainRequestError::RpcTransportError)
+			},
+		}
+	}
+}
+
+fn is_broadcast(body: &[u8]) -> bool {
+	serde_json::from_slice::<serde_json::Value>(body)
+		.map_or(false, |request| request["method"] == "eth_sendRawTransaction")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Decision, PairRecording, Recorder};
+	use crate::{
+		chain_queries::ChainQueries, chain_utils::ChainRequestError, mock_http, qp_types::Role,
+	};
+	use sp_core::offchain::{testing::TestOffchainExt, OffchainDbExt, OffchainWorkerExt};
+
+	const URL: &str = "http://localhost:8545";
+
+	fn with_offchain(f: impl FnOnce()) {
+		let (offchain, _state) = TestOffchainExt::new();
+		let mut ext = sp_io::TestExternalities::default();
+		ext.register_extension(OffchainDbExt::new(offchain.clone()));
+		ext.register_extension(OffchainWorkerExt::new(offchain));
+		mock_http::reset();
+		ext.execute_with(f);
+	}
+
+	fn recording() -> PairRecording {
+		PairRecording {
+			remote_chain: 1,
+			local_chain: 2,
+			role: Role::QP_MINER,
+			block_number: 100,
+			now: 1_000,
+			simulation: false,
+			state: Vec::new(),
+			exchanges: Vec::new(),
+			decisions: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn replay_serves_the_recorded_responses() {
+		with_offchain(|| {
+			mock_http::respond("eth_blockNumber", "\"0x10\"");
+			Recorder::start(recording());
+			assert_eq!(ChainQueries::block_number(URL), Ok(16));
+			Recorder::decision(Decision::Waiting(1));
+			let session = Recorder::finish().unwrap();
+			assert_eq!(session.recording.exchanges.len(), 1);
+			assert_eq!(session.decisions, vec![Decision::Waiting(1)]);
+
+			Recorder::start_replay(session.recording);
+			assert_eq!(Recorder::now(), 1_000);
+			assert_eq!(ChainQueries::block_number(URL), Ok(16));
+			// a request that was not recorded means the replay diverged
+			assert_eq!(ChainQueries::block_number(URL), Err(ChainRequestError::RpcTransportError));
+			assert_eq!(Recorder::finish().unwrap().cursor, 1);
+			assert_eq!(mock_http::requests(), vec!["eth_blockNumber".to_string()]);
+		});
+	}
+}
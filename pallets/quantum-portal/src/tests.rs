@@ -13,394 +13,902 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Ferrum.  If not, see <http://www.gnu.org/licenses/>.
-
-use crate as example_offchain_worker;
-use crate::*;
-use codec::Decode;
-use frame_support::{assert_ok, parameter_types};
-use sp_core::{
-	offchain::{testing, OffchainWorkerExt, TransactionPoolExt},
-	sr25519::Signature,
-	H256,
+#![cfg(test)]
+use super::*;
+use crate::{
+	contract_client::ContractClientSignature,
+	migrations::{v1, v2, v3, v4},
+	mock::{RuntimeEvent, *},
+	qp_types::{
+		ChainConfig, CustodyPool, InboundMessagesPayload, MessageState, MessageStatePayload,
+		PayloadItems, QpNetworkItem, ReservesPayload, RewardsPayload, WithdrawalReceipt,
+		WithdrawalReceiptsPayload, MAX_PAYLOADS_PER_BLOCK, MAX_PAYLOAD_ITEMS,
+	},
 };
-use std::sync::Arc;
-
-use sp_keystore::{testing::KeyStore, KeystoreExt, Keystore};
+use ferrum_primitives::{InboundMessage, RewardDistribution, SignatureReceiver};
+use frame_support::{
+	assert_noop, assert_ok,
+	dispatch::GetDispatchInfo,
+	storage::unhashed,
+	traits::{GetStorageVersion, Hooks, OnRuntimeUpgrade, ReservableCurrency, StorageVersion},
+};
+use parity_scale_codec::Encode;
+use sp_core::{ecdsa, Pair, H160, H256};
 use sp_runtime::{
-	testing::{Header, TestXt},
-	traits::{BlakeTwo256, Extrinsic as ExtrinsicT, IdentifyAccount, IdentityLookup, Verify},
-	RuntimeAppPublic,
+	traits::{BadOrigin, ValidateUnsigned},
+	transaction_validity::{InvalidTransaction, TransactionSource, TransactionValidity},
+	DispatchError,
 };
 
-type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
-type Block = frame_system::mocking::MockBlock<Test>;
-
-// For testing the module, we construct a mock runtime.
-frame_support::construct_runtime!(
-	pub enum Test where
-		Block = Block,
-		NodeBlock = Block,
-		UncheckedExtrinsic = UncheckedExtrinsic,
-	{
-		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
-		Example: example_offchain_worker::{Pallet, Call, Storage, Event<T>, ValidateUnsigned},
-	}
-);
-
-parameter_types! {
-	pub const BlockHashCount: u64 = 250;
-	pub BlockWeights: frame_system::limits::BlockWeights =
-		frame_system::limits::BlockWeights::simple_max(1024);
-}
-impl frame_system::Config for Test {
-	type BaseCallFilter = frame_support::traits::Everything;
-	type BlockWeights = ();
-	type BlockLength = ();
-	type DbWeight = ();
-	type Origin = Origin;
-	type Call = Call;
-	type Index = u64;
-	type BlockNumber = u64;
-	type Hash = H256;
-	type Hashing = BlakeTwo256;
-	type AccountId = sp_core::sr25519::Public;
-	type Lookup = IdentityLookup<Self::AccountId>;
-	type Header = Header;
-	type Event = Event;
-	type BlockHashCount = BlockHashCount;
-	type Version = ();
-	type PalletInfo = PalletInfo;
-	type AccountData = ();
-	type OnNewAccount = ();
-	type OnKilledAccount = ();
-	type SystemWeightInfo = ();
-	type SS58Prefix = ();
-	type OnSetCode = ();
-}
-
-type Extrinsic = TestXt<Call, ()>;
-type AccountId = <<Signature as Verify>::Signer as IdentifyAccount>::AccountId;
-
-impl frame_system::offchain::SigningTypes for Test {
-	type Public = <Signature as Verify>::Signer;
-	type Signature = Signature;
-}
-
-impl<LocalCall> frame_system::offchain::SendTransactionTypes<LocalCall> for Test
-	where
-		Call: From<LocalCall>,
-{
-	type OverarchingCall = Call;
-	type Extrinsic = Extrinsic;
-}
-
-impl<LocalCall> frame_system::offchain::CreateSignedTransaction<LocalCall> for Test
-	where
-		Call: From<LocalCall>,
-{
-	fn create_transaction<C: frame_system::offchain::AppCrypto<Self::Public, Self::Signature>>(
-		call: Call,
-		_public: <Signature as Verify>::Signer,
-		_account: AccountId,
-		nonce: u64,
-	) -> Option<(Call, <Extrinsic as ExtrinsicT>::SignaturePayload)> {
-		Some((call, (nonce, ())))
+const REMOTE_CHAIN: u64 = 97;
+
+fn relayer_key(seed: u8) -> ecdsa::Pair {
+	ecdsa::Pair::from_seed(&[seed; 32])
+}
+
+/// Address the offchain worker of a relayer signs with
+fn signer_of(key: &ecdsa::Pair) -> H160 {
+	ContractClientSignature::from(key.public()).from
+}
+
+fn register_relayer(relayer: AccountId, key: &ecdsa::Pair) {
+	assert_ok!(QuantumPortal::register_relayer(RuntimeOrigin::signed(relayer), signer_of(key)));
+}
+
+/// Register `finalizers` for `REMOTE_CHAIN`, its items are acted on once `threshold` of them
+/// reported them
+fn set_finalizers(finalizers: &[AccountId], threshold: u32) {
+	for finalizer in finalizers {
+		assert_ok!(QuantumPortal::register_finalizer(
+			RuntimeOrigin::root(),
+			REMOTE_CHAIN,
+			*finalizer
+		));
 	}
+	assert_ok!(QuantumPortal::set_finalizer_threshold(
+		RuntimeOrigin::root(),
+		REMOTE_CHAIN,
+		threshold
+	));
 }
 
-parameter_types! {
-	pub const GracePeriod: u64 = 5;
-	pub const UnsignedInterval: u64 = 128;
-	pub const UnsignedPriority: u64 = 1 << 20;
+fn chain_config(chain_id: u64) -> ChainConfig {
+	let mut config = ChainConfig::from(QpNetworkItem {
+		url: b"http://localhost:8545".to_vec(),
+		gateway_contract_address: b"0000000000000000000000000000000000000001".to_vec(),
+		id: chain_id,
+	});
+	config.confirmation_depth = 12;
+	config.max_mine_batch_size = 4;
+	config
+}
+
+/// `config` encoded in the layout of storage version `version`, the fields added since are left
+/// out
+fn stored_config(config: &ChainConfig, version: u16) -> Vec<u8> {
+	let added_since = match version {
+		1 => (
+			config.speed_up_after,
+			config.speed_up_percent,
+			config.max_gas_price,
+			config.low_balance_threshold,
+			config.finality,
+		)
+			.encode(),
+		2 => (config.low_balance_threshold, config.finality).encode(),
+		3 => config.finality.encode(),
+		_ => Vec::new(),
+	};
+	let encoded = config.encode();
+	encoded[..encoded.len() - added_since.len()].to_vec()
+}
+
+fn put_config(config: &ChainConfig, version: u16) {
+	StorageVersion::new(version).put::<QuantumPortal>();
+	unhashed::put_raw(
+		&ChainConfigs::<Runtime>::hashed_key_for(config.chain_id),
+		&stored_config(config, version),
+	);
 }
 
-impl Config for Test {
-	type Event = Event;
-	type AuthorityId = crypto::TestAuthId;
-	type Call = Call;
-	type GracePeriod = GracePeriod;
-	type UnsignedInterval = UnsignedInterval;
-	type UnsignedPriority = UnsignedPriority;
+fn raw_config(chain_id: u64) -> Option<Vec<u8>> {
+	unhashed::get_raw(&ChainConfigs::<Runtime>::hashed_key_for(chain_id))
 }
 
 #[test]
-fn it_aggregates_the_price() {
-	sp_io::TestExternalities::default().execute_with(|| {
-		assert_eq!(Example::average_price(), None);
+fn migrate_to_v1_bounds_the_finalizers() {
+	ExtBuilder::default().build().execute_with(|| {
+		StorageVersion::new(0).put::<QuantumPortal>();
+		unhashed::put(
+			&RegisteredFinalizers::<Runtime>::hashed_key_for(REMOTE_CHAIN),
+			&vec![ALICE, BOB, CHARLIE],
+		);
 
-		assert_ok!(Example::submit_price(Origin::signed(Default::default()), 27));
-		assert_eq!(Example::average_price(), Some(27));
+		v1::MigrateToV1::<Runtime>::on_runtime_upgrade();
 
-		assert_ok!(Example::submit_price(Origin::signed(Default::default()), 43));
-		assert_eq!(Example::average_price(), Some(35));
+		assert_eq!(QuantumPortal::on_chain_storage_version(), 1);
+		assert_eq!(
+			RegisteredFinalizers::<Runtime>::get(REMOTE_CHAIN).unwrap().into_inner(),
+			vec![ALICE, BOB]
+		);
 	});
 }
 
 #[test]
-fn should_make_http_call_and_parse_result() {
-	let (offchain, state) = testing::TestOffchainExt::new();
-	let mut t = sp_io::TestExternalities::default();
-	t.register_extension(OffchainWorkerExt::new(offchain));
-
-	price_oracle_response(&mut state.write());
-
-	t.execute_with(|| {
-		// when
-		let price = Example::fetch_price().unwrap();
-		// then
-		assert_eq!(price, 15523);
+fn migrate_to_v2_adds_the_speed_up_settings() {
+	ExtBuilder::default().build().execute_with(|| {
+		let config = chain_config(REMOTE_CHAIN);
+		put_config(&config, 1);
+
+		v2::MigrateToV2::<Runtime>::on_runtime_upgrade();
+
+		assert_eq!(QuantumPortal::on_chain_storage_version(), 2);
+		assert_eq!(raw_config(REMOTE_CHAIN), Some(stored_config(&config, 2)));
 	});
 }
 
 #[test]
-fn knows_how_to_mock_several_http_calls() {
-	let (offchain, state) = testing::TestOffchainExt::new();
-	let mut t = sp_io::TestExternalities::default();
-	t.register_extension(OffchainWorkerExt::new(offchain));
-
-	{
-		let mut state = state.write();
-		state.expect_request(testing::PendingRequest {
-			method: "GET".into(),
-			uri: "https://min-api.cryptocompare.com/data/price?fsym=BTC&tsyms=USD".into(),
-			response: Some(br#"{"USD": 1}"#.to_vec()),
-			sent: true,
-			..Default::default()
-		});
-
-		state.expect_request(testing::PendingRequest {
-			method: "GET".into(),
-			uri: "https://min-api.cryptocompare.com/data/price?fsym=BTC&tsyms=USD".into(),
-			response: Some(br#"{"USD": 2}"#.to_vec()),
-			sent: true,
-			..Default::default()
-		});
-
-		state.expect_request(testing::PendingRequest {
-			method: "GET".into(),
-			uri: "https://min-api.cryptocompare.com/data/price?fsym=BTC&tsyms=USD".into(),
-			response: Some(br#"{"USD": 3}"#.to_vec()),
-			sent: true,
-			..Default::default()
-		});
-	}
+fn migrate_to_v3_adds_the_low_balance_threshold() {
+	ExtBuilder::default().build().execute_with(|| {
+		let mut config = chain_config(REMOTE_CHAIN);
+		config.speed_up_after = Some(60_000);
+		put_config(&config, 2);
+
+		v3::MigrateToV3::<Runtime>::on_runtime_upgrade();
+
+		assert_eq!(QuantumPortal::on_chain_storage_version(), 3);
+		assert_eq!(raw_config(REMOTE_CHAIN), Some(stored_config(&config, 3)));
+	});
+}
+
+#[test]
+fn migrate_to_v4_adds_the_finality_mode() {
+	ExtBuilder::default().build().execute_with(|| {
+		let mut config = chain_config(REMOTE_CHAIN);
+		config.low_balance_threshold = Some(1_000.into());
+		put_config(&config, 3);
+
+		v4::MigrateToV4::<Runtime>::on_runtime_upgrade();
+
+		assert_eq!(QuantumPortal::on_chain_storage_version(), 4);
+		assert_eq!(ChainConfigs::<Runtime>::get(REMOTE_CHAIN), Some(config));
+	});
+}
+
+#[test]
+fn migrations_chain_from_version_1() {
+	ExtBuilder::default().build().execute_with(|| {
+		let config = chain_config(REMOTE_CHAIN);
+		put_config(&config, 1);
+
+		v2::MigrateToV2::<Runtime>::on_runtime_upgrade();
+		v3::MigrateToV3::<Runtime>::on_runtime_upgrade();
+		v4::MigrateToV4::<Runtime>::on_runtime_upgrade();
+
+		assert_eq!(QuantumPortal::on_chain_storage_version(), 4);
+		assert_eq!(ChainConfigs::<Runtime>::get(REMOTE_CHAIN), Some(config));
+	});
+}
+
+#[test]
+fn migration_skips_other_storage_versions() {
+	ExtBuilder::default().build().execute_with(|| {
+		let config = chain_config(REMOTE_CHAIN);
+		put_config(&config, 2);
+
+		v2::MigrateToV2::<Runtime>::on_runtime_upgrade();
+		v4::MigrateToV4::<Runtime>::on_runtime_upgrade();
+
+		assert_eq!(QuantumPortal::on_chain_storage_version(), 2);
+		assert_eq!(raw_config(REMOTE_CHAIN), Some(stored_config(&config, 2)));
+	});
+}
+
+#[test]
+fn chain_config_requires_the_update_origin() {
+	ExtBuilder::default().build().execute_with(|| {
+		assert_noop!(
+			QuantumPortal::set_chain_config(
+				RuntimeOrigin::signed(ALICE),
+				chain_config(REMOTE_CHAIN)
+			),
+			BadOrigin
+		);
+		assert_noop!(
+			QuantumPortal::report_relayer(RuntimeOrigin::signed(ALICE), BOB, REMOTE_CHAIN, 1),
+			BadOrigin
+		);
+
+		assert_ok!(QuantumPortal::set_chain_config(
+			RuntimeOrigin::root(),
+			chain_config(REMOTE_CHAIN)
+		));
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::ChainConfigSet {
+			chain_id: REMOTE_CHAIN,
+		}));
+		assert_noop!(
+			QuantumPortal::remove_chain_config(RuntimeOrigin::root(), LOCAL_CHAIN),
+			Error::<Runtime>::ChainConfigNotFound
+		);
+	});
+}
+
+#[test]
+fn register_relayer_reserves_the_stake() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+
+		assert_eq!(Balances::reserved_balance(ALICE), RELAYER_STAKE);
+		assert_eq!(RelayerSigners::<Runtime>::get(signer_of(&key)), Some(ALICE));
+		assert_noop!(
+			QuantumPortal::register_relayer(
+				RuntimeOrigin::signed(ALICE),
+				signer_of(&relayer_key(2))
+			),
+			Error::<Runtime>::RelayerAlreadyRegistered
+		);
+		assert_noop!(
+			QuantumPortal::register_relayer(RuntimeOrigin::signed(BOB), signer_of(&key)),
+			Error::<Runtime>::RelayerSignerInUse
+		);
+	});
+}
 
-	t.execute_with(|| {
-		let price1 = Example::fetch_price().unwrap();
-		let price2 = Example::fetch_price().unwrap();
-		let price3 = Example::fetch_price().unwrap();
+#[test]
+fn stake_is_withdrawn_after_the_unbonding_period() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		assert_ok!(QuantumPortal::unregister_relayer(RuntimeOrigin::signed(ALICE)));
+		let release_block = 1 + UNBONDING_PERIOD;
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::RelayerUnregistered {
+			relayer: ALICE,
+			release_block,
+		}));
+		assert_eq!(RelayerSigners::<Runtime>::get(signer_of(&key)), None);
+		assert_eq!(Balances::reserved_balance(ALICE), RELAYER_STAKE);
+		assert_noop!(
+			QuantumPortal::register_relayer(RuntimeOrigin::signed(ALICE), signer_of(&key)),
+			Error::<Runtime>::RelayerUnbonding
+		);
 
-		assert_eq!(price1, 100);
-		assert_eq!(price2, 200);
-		assert_eq!(price3, 300);
-	})
+		System::set_block_number(release_block - 1);
+		assert_noop!(
+			QuantumPortal::withdraw_unbonded(RuntimeOrigin::signed(ALICE)),
+			Error::<Runtime>::RelayerUnbonding
+		);
+		System::set_block_number(release_block);
+		assert_ok!(QuantumPortal::withdraw_unbonded(RuntimeOrigin::signed(ALICE)));
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::StakeWithdrawn {
+			relayer: ALICE,
+			amount: RELAYER_STAKE,
+		}));
+		assert_eq!(Balances::reserved_balance(ALICE), 0);
+		assert_eq!(Balances::free_balance(ALICE), 1_000);
+		assert_noop!(
+			QuantumPortal::withdraw_unbonded(RuntimeOrigin::signed(ALICE)),
+			Error::<Runtime>::NothingUnbonding
+		);
+	});
 }
 
 #[test]
-fn should_submit_signed_transaction_on_chain() {
-	const PHRASE: &str =
-		"news slush supreme milk chapter athlete soap sausage put clutch what kitten";
-
-	let (offchain, offchain_state) = testing::TestOffchainExt::new();
-	let (pool, pool_state) = testing::TestTransactionPoolExt::new();
-	let keystore = KeyStore::new();
-	Keystore::sr25519_generate_new(
-		&keystore,
-		crate::crypto::Public::ID,
-		Some(&format!("{}/hunter1", PHRASE)),
-	)
-		.unwrap();
-
-	let mut t = sp_io::TestExternalities::default();
-	t.register_extension(OffchainWorkerExt::new(offchain));
-	t.register_extension(TransactionPoolExt::new(pool));
-	t.register_extension(KeystoreExt(Arc::new(keystore)));
-
-	price_oracle_response(&mut offchain_state.write());
-
-	t.execute_with(|| {
-		// when
-		Example::fetch_price_and_send_signed().unwrap();
-		// then
-		let tx = pool_state.write().transactions.pop().unwrap();
-		assert!(pool_state.read().transactions.is_empty());
-		let tx = Extrinsic::decode(&mut &*tx).unwrap();
-		assert_eq!(tx.signature.unwrap().0, 0);
-		assert_eq!(tx.call, Call::Example(crate::Call::submit_price { price: 15523 }));
+fn slash_during_unbonding_takes_the_stake() {
+	ExtBuilder::default().build().execute_with(|| {
+		register_relayer(ALICE, &relayer_key(1));
+		assert_ok!(QuantumPortal::unregister_relayer(RuntimeOrigin::signed(ALICE)));
+
+		assert_ok!(QuantumPortal::report_relayer(RuntimeOrigin::root(), ALICE, REMOTE_CHAIN, 1));
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::RelayerSlashed {
+			relayer: ALICE,
+			chain_id: REMOTE_CHAIN,
+			block_nonce: 1,
+			amount: RELAYER_STAKE,
+		}));
+		assert_eq!(Balances::reserved_balance(ALICE), 0);
+		assert_eq!(Balances::free_balance(ALICE), 1_000 - RELAYER_STAKE);
+
+		System::set_block_number(1 + UNBONDING_PERIOD);
+		assert_noop!(
+			QuantumPortal::withdraw_unbonded(RuntimeOrigin::signed(ALICE)),
+			Error::<Runtime>::NothingUnbonding
+		);
 	});
 }
 
 #[test]
-fn should_submit_unsigned_transaction_on_chain_for_any_account() {
-	const PHRASE: &str =
-		"news slush supreme milk chapter athlete soap sausage put clutch what kitten";
-	let (offchain, offchain_state) = testing::TestOffchainExt::new();
-	let (pool, pool_state) = testing::TestTransactionPoolExt::new();
-
-	let keystore = KeyStore::new();
-
-	Keystore::sr25519_generate_new(
-		&keystore,
-		crate::crypto::Public::ID,
-		Some(&format!("{}/hunter1", PHRASE)),
-	)
-		.unwrap();
-
-	let public_key = Keystore::sr25519_public_keys(&keystore, crate::crypto::Public::ID)
-		.get(0)
-		.unwrap()
-		.clone();
-
-	let mut t = sp_io::TestExternalities::default();
-	t.register_extension(OffchainWorkerExt::new(offchain));
-	t.register_extension(TransactionPoolExt::new(pool));
-	t.register_extension(KeystoreExt(Arc::new(keystore)));
-
-	price_oracle_response(&mut offchain_state.write());
-
-	let price_payload = PricePayload {
+fn unknown_relayer_is_rejected() {
+	ExtBuilder::default().build().execute_with(|| {
+		assert_noop!(
+			QuantumPortal::unregister_relayer(RuntimeOrigin::signed(ALICE)),
+			Error::<Runtime>::RelayerNotFound
+		);
+		assert_noop!(
+			QuantumPortal::report_relayer(RuntimeOrigin::root(), ALICE, REMOTE_CHAIN, 1),
+			Error::<Runtime>::RelayerNotFound
+		);
+
+		let payload = MessageStatePayload {
+			remote_chain: REMOTE_CHAIN,
+			updates: vec![(H256::repeat_byte(1), MessageState::Mined)].try_into().unwrap(),
+			block_number: 1,
+			sequence: 0,
+		};
+		let signature = relayer_key(1).sign_prehashed(&payload.signing_hash().0);
+		assert_noop!(
+			QuantumPortal::update_message_states(RuntimeOrigin::none(), payload, signature),
+			Error::<Runtime>::RelayerNotFound
+		);
+	});
+}
+
+#[test]
+fn relayer_payload_requires_no_origin() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		set_finalizers(&[ALICE], 1);
+		let payload = MessageStatePayload {
+			remote_chain: REMOTE_CHAIN,
+			updates: vec![(H256::repeat_byte(1), MessageState::Mined)].try_into().unwrap(),
+			block_number: 1,
+			sequence: 0,
+		};
+		let signature = key.sign_prehashed(&payload.signing_hash().0);
+
+		assert_noop!(
+			QuantumPortal::update_message_states(
+				RuntimeOrigin::signed(ALICE),
+				payload.clone(),
+				signature.clone()
+			),
+			BadOrigin
+		);
+		assert_ok!(QuantumPortal::update_message_states(RuntimeOrigin::none(), payload, signature));
+		assert_eq!(
+			MessageStates::<Runtime>::get(REMOTE_CHAIN, H256::repeat_byte(1)),
+			Some(MessageState::Mined)
+		);
+	});
+}
+
+#[test]
+fn relayer_payload_weight_scales_with_its_items() {
+	let call = |count: u8| {
+		let payload = MessageStatePayload {
+			remote_chain: REMOTE_CHAIN,
+			updates: (0..count)
+				.map(|i| (H256::repeat_byte(i), MessageState::Mined))
+				.collect::<Vec<_>>()
+				.try_into()
+				.unwrap(),
+			block_number: 1,
+			sequence: 0,
+		};
+		let signature = relayer_key(1).sign_prehashed(&payload.signing_hash().0);
+		RuntimeCall::QuantumPortal(Call::update_message_states { payload, signature })
+	};
+
+	assert!(call(2).get_dispatch_info().weight.all_gt(call(1).get_dispatch_info().weight));
+	let oversized = (0..=MAX_PAYLOAD_ITEMS)
+		.map(|i| (H256::from_low_u64_be(i.into()), MessageState::Mined))
+		.collect::<Vec<_>>();
+	assert!(PayloadItems::<(H256, MessageState)>::try_from(oversized).is_err());
+}
+
+fn record_receipt(key: &ecdsa::Pair, message_id: H256, beneficiary: AccountId, amount: u128) {
+	let payload = WithdrawalReceiptsPayload {
+		remote_chain: REMOTE_CHAIN,
+		receipts: vec![WithdrawalReceipt { message_id, beneficiary, amount }].try_into().unwrap(),
 		block_number: 1,
-		price: 15523,
-		public: <Test as SigningTypes>::Public::from(public_key),
+		sequence: 0,
 	};
+	let signature = key.sign_prehashed(&payload.signing_hash().0);
+	assert_ok!(QuantumPortal::record_withdrawal_receipts(
+		RuntimeOrigin::none(),
+		payload,
+		signature
+	));
+}
 
-	// let signature = price_payload.sign::<crypto::TestAuthId>().unwrap();
-	t.execute_with(|| {
-		// when
-		Example::fetch_price_and_send_unsigned_for_any_account(1).unwrap();
-		// then
-		let tx = pool_state.write().transactions.pop().unwrap();
-		let tx = Extrinsic::decode(&mut &*tx).unwrap();
-		assert_eq!(tx.signature, None);
-		if let Call::Example(crate::Call::submit_price_unsigned_with_signed_payload {
-								 price_payload: body,
-								 signature,
-							 }) = tx.call
-		{
-			assert_eq!(body, price_payload);
-
-			let signature_valid =
-				<PricePayload<
-					<Test as SigningTypes>::Public,
-					<Test as frame_system::Config>::BlockNumber,
-				> as SignedPayload<Test>>::verify::<crypto::TestAuthId>(&price_payload, signature);
-
-			assert!(signature_valid);
-		}
+#[test]
+fn receipts_of_non_finalized_messages_are_not_recorded() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		set_finalizers(&[ALICE], 1);
+		let message_id = H256::repeat_byte(1);
+		MessageStates::<Runtime>::insert(REMOTE_CHAIN, message_id, MessageState::Mined);
+
+		record_receipt(&key, message_id, BOB, 50);
+
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+		assert!(Released::get().is_empty());
+		assert_noop!(
+			QuantumPortal::claim_withdrawal(RuntimeOrigin::signed(BOB), REMOTE_CHAIN, message_id),
+			Error::<Runtime>::WithdrawalNotFound
+		);
 	});
 }
 
 #[test]
-fn should_submit_unsigned_transaction_on_chain_for_all_accounts() {
-	const PHRASE: &str =
-		"news slush supreme milk chapter athlete soap sausage put clutch what kitten";
-	let (offchain, offchain_state) = testing::TestOffchainExt::new();
-	let (pool, pool_state) = testing::TestTransactionPoolExt::new();
-
-	let keystore = KeyStore::new();
-
-	Keystore::sr25519_generate_new(
-		&keystore,
-		crate::crypto::Public::ID,
-		Some(&format!("{}/hunter1", PHRASE)),
-	)
-		.unwrap();
-
-	let public_key = Keystore::sr25519_public_keys(&keystore, crate::crypto::Public::ID)
-		.get(0)
-		.unwrap()
-		.clone();
-
-	let mut t = sp_io::TestExternalities::default();
-	t.register_extension(OffchainWorkerExt::new(offchain));
-	t.register_extension(TransactionPoolExt::new(pool));
-	t.register_extension(KeystoreExt(Arc::new(keystore)));
-
-	price_oracle_response(&mut offchain_state.write());
-
-	let price_payload = PricePayload {
-		block_number: 1,
-		price: 15523,
-		public: <Test as SigningTypes>::Public::from(public_key),
+fn receipts_of_finalized_messages_are_released_once() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		set_finalizers(&[ALICE], 1);
+		let message_id = H256::repeat_byte(1);
+		MessageStates::<Runtime>::insert(REMOTE_CHAIN, message_id, MessageState::Finalized);
+
+		record_receipt(&key, message_id, BOB, 50);
+		record_receipt(&key, message_id, BOB, 50);
+
+		assert_eq!(Released::get(), vec![(BOB, 50)]);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), Some((BOB, 50, true)));
+	});
+}
+
+#[test]
+fn withdrawal_cannot_be_claimed_twice() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		set_finalizers(&[ALICE], 1);
+		let message_id = H256::repeat_byte(1);
+		MessageStates::<Runtime>::insert(REMOTE_CHAIN, message_id, MessageState::Finalized);
+		ReleaseFails::set(true);
+		record_receipt(&key, message_id, BOB, 50);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), Some((BOB, 50, false)));
+
+		assert_noop!(
+			QuantumPortal::claim_withdrawal(RuntimeOrigin::signed(BOB), REMOTE_CHAIN, message_id),
+			DispatchError::Other("release failed")
+		);
+		ReleaseFails::set(false);
+		assert_noop!(
+			QuantumPortal::claim_withdrawal(
+				RuntimeOrigin::signed(CHARLIE),
+				REMOTE_CHAIN,
+				message_id
+			),
+			Error::<Runtime>::NotWithdrawalBeneficiary
+		);
+		assert_ok!(QuantumPortal::claim_withdrawal(
+			RuntimeOrigin::signed(BOB),
+			REMOTE_CHAIN,
+			message_id
+		));
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::WithdrawalReleased {
+			remote_chain: REMOTE_CHAIN,
+			message_id,
+			beneficiary: BOB,
+			amount: 50,
+		}));
+		assert_noop!(
+			QuantumPortal::claim_withdrawal(RuntimeOrigin::signed(BOB), REMOTE_CHAIN, message_id),
+			Error::<Runtime>::WithdrawalAlreadyReleased
+		);
+		assert_eq!(Released::get(), vec![(BOB, 50)]);
+	});
+}
+
+#[test]
+fn finalizer_threshold_requires_the_update_origin() {
+	ExtBuilder::default().build().execute_with(|| {
+		assert_noop!(
+			QuantumPortal::set_finalizer_threshold(RuntimeOrigin::signed(ALICE), REMOTE_CHAIN, 1),
+			BadOrigin
+		);
+		assert_ok!(QuantumPortal::set_finalizer_threshold(RuntimeOrigin::root(), REMOTE_CHAIN, 2));
+		assert_eq!(FinalizerThreshold::<Runtime>::get(REMOTE_CHAIN), Some(2));
+	});
+}
+
+#[test]
+fn finalizers_require_the_update_origin() {
+	ExtBuilder::default().build().execute_with(|| {
+		assert_noop!(
+			QuantumPortal::register_finalizer(RuntimeOrigin::signed(ALICE), REMOTE_CHAIN, ALICE),
+			BadOrigin
+		);
+		assert_ok!(QuantumPortal::register_finalizer(RuntimeOrigin::root(), REMOTE_CHAIN, ALICE));
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::FinalizerAdded {
+			chain_id: REMOTE_CHAIN,
+			finalizer: ALICE,
+		}));
+		assert_noop!(
+			QuantumPortal::remove_finalizer(RuntimeOrigin::signed(ALICE), REMOTE_CHAIN, ALICE),
+			BadOrigin
+		);
+		assert_ok!(QuantumPortal::remove_finalizer(RuntimeOrigin::root(), REMOTE_CHAIN, ALICE));
+		assert!(RegisteredFinalizers::<Runtime>::get(REMOTE_CHAIN).unwrap().is_empty());
+	});
+}
+
+#[test]
+fn reports_need_a_threshold_and_a_finalizer() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		let message_id = H256::repeat_byte(1);
+		MessageStates::<Runtime>::insert(REMOTE_CHAIN, message_id, MessageState::Finalized);
+		assert_ok!(QuantumPortal::register_finalizer(RuntimeOrigin::root(), REMOTE_CHAIN, ALICE));
+
+		// no threshold is set for the chain
+		record_receipt(&first, message_id, CHARLIE, 50);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+
+		assert_ok!(QuantumPortal::set_finalizer_threshold(RuntimeOrigin::root(), REMOTE_CHAIN, 1));
+		// BOB is a relayer but not a finalizer of the chain
+		record_receipt(&second, message_id, CHARLIE, 50);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+
+		record_receipt(&first, message_id, CHARLIE, 50);
+		assert_eq!(
+			Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id),
+			Some((CHARLIE, 50, true))
+		);
+	});
+}
+
+#[test]
+fn attestations_expire_short_of_the_threshold() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		set_finalizers(&[ALICE, BOB], 2);
+		let message_id = H256::repeat_byte(1);
+		MessageStates::<Runtime>::insert(REMOTE_CHAIN, message_id, MessageState::Finalized);
+
+		record_receipt(&first, message_id, CHARLIE, 50);
+		assert_eq!(Attestations::<Runtime>::iter().count(), 1);
+		let expiry = 1 + ATTESTATION_PERIOD;
+		QuantumPortal::on_initialize(expiry - 1);
+		assert_eq!(Attestations::<Runtime>::iter().count(), 1);
+		QuantumPortal::on_initialize(expiry);
+		assert_eq!(Attestations::<Runtime>::iter().count(), 0);
+		assert_eq!(AttestationExpiries::<Runtime>::iter().count(), 0);
+
+		// the report of the first finalizer no longer counts
+		System::set_block_number(expiry);
+		record_receipt(&second, message_id, CHARLIE, 50);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+		record_receipt(&first, message_id, CHARLIE, 50);
+		assert_eq!(
+			Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id),
+			Some((CHARLIE, 50, true))
+		);
+		assert_eq!(AttestationExpiries::<Runtime>::iter().count(), 0);
+	});
+}
+
+#[test]
+fn message_state_is_updated_once_the_threshold_is_reached() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		set_finalizers(&[ALICE, BOB], 2);
+		let message_id = H256::repeat_byte(1);
+		let update = |key: &ecdsa::Pair, state: MessageState| {
+			let payload = MessageStatePayload {
+				remote_chain: REMOTE_CHAIN,
+				updates: vec![(message_id, state)].try_into().unwrap(),
+				block_number: 1,
+				sequence: 0,
+			};
+			let signature = key.sign_prehashed(&payload.signing_hash().0);
+			assert_ok!(QuantumPortal::update_message_states(
+				RuntimeOrigin::none(),
+				payload,
+				signature
+			));
+		};
+
+		update(&first, MessageState::Finalized);
+		update(&first, MessageState::Finalized);
+		assert_eq!(MessageStates::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+		// a state altered by the second relayer does not count towards the first one
+		update(&second, MessageState::Mined);
+		assert_eq!(MessageStates::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+
+		update(&second, MessageState::Finalized);
+		assert_eq!(
+			MessageStates::<Runtime>::get(REMOTE_CHAIN, message_id),
+			Some(MessageState::Finalized)
+		);
+	});
+}
+
+#[test]
+fn withdrawal_is_released_once_the_threshold_is_reached() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		set_finalizers(&[ALICE, BOB], 2);
+		let message_id = H256::repeat_byte(1);
+		MessageStates::<Runtime>::insert(REMOTE_CHAIN, message_id, MessageState::Finalized);
+
+		record_receipt(&first, message_id, CHARLIE, 50);
+		record_receipt(&first, message_id, CHARLIE, 50);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+		// a receipt altered by the second relayer does not count towards the first one
+		record_receipt(&second, message_id, BOB, 50);
+		assert_eq!(Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id), None);
+		assert!(Released::get().is_empty());
+
+		record_receipt(&second, message_id, CHARLIE, 50);
+		assert_eq!(
+			Withdrawals::<Runtime>::get(REMOTE_CHAIN, message_id),
+			Some((CHARLIE, 50, true))
+		);
+		assert_eq!(Released::get(), vec![(CHARLIE, 50)]);
+	});
+}
+
+#[test]
+fn inbound_message_is_handled_once_the_threshold_is_reached() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		set_finalizers(&[ALICE, BOB], 2);
+		let message = InboundMessage {
+			remote_chain: REMOTE_CHAIN,
+			message_id: H256::repeat_byte(1),
+			remote_contract: H160::repeat_byte(2),
+			source_msg_sender: H160::repeat_byte(3),
+			source_beneficiary: H160::repeat_byte(4),
+			token: H160::zero(),
+			amount: 50,
+			method: vec![],
+		};
+		let record = |key: &ecdsa::Pair| {
+			let payload = InboundMessagesPayload {
+				remote_chain: REMOTE_CHAIN,
+				messages: vec![message.clone()].try_into().unwrap(),
+				block_number: 1,
+				sequence: 0,
+			};
+			let signature = key.sign_prehashed(&payload.signing_hash().0);
+			assert_ok!(QuantumPortal::record_inbound_messages(
+				RuntimeOrigin::none(),
+				payload,
+				signature
+			));
+		};
+
+		record(&first);
+		assert_eq!(InboundMessages::<Runtime>::get(REMOTE_CHAIN, message.message_id), None);
+		assert_eq!(Liabilities::<Runtime>::get(REMOTE_CHAIN), 0);
+
+		record(&second);
+		// the mock runtime has no handler, the message is recorded as failed
+		assert_eq!(InboundMessages::<Runtime>::get(REMOTE_CHAIN, message.message_id), Some(false));
+		assert_eq!(Liabilities::<Runtime>::get(REMOTE_CHAIN), 50);
+		record(&first);
+		assert_eq!(Liabilities::<Runtime>::get(REMOTE_CHAIN), 50);
+	});
+}
+
+#[test]
+fn reward_distribution_is_handled_once_the_threshold_is_reached() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		set_finalizers(&[ALICE, BOB], 2);
+		assert_ok!(QuantumPortal::set_rewards_source(
+			RuntimeOrigin::root(),
+			REMOTE_CHAIN,
+			H160::repeat_byte(1)
+		));
+		let record = |key: &ecdsa::Pair| {
+			let payload = RewardsPayload {
+				remote_chain: REMOTE_CHAIN,
+				distributions: vec![RewardDistribution {
+					remote_chain: REMOTE_CHAIN,
+					distribution_id: 7,
+					rewards: vec![(H160::repeat_byte(2), 10)],
+				}]
+				.try_into()
+				.unwrap(),
+				block_number: 1,
+				sequence: 0,
+			};
+			let signature = key.sign_prehashed(&payload.signing_hash().0);
+			assert_ok!(QuantumPortal::record_reward_distributions(
+				RuntimeOrigin::none(),
+				payload,
+				signature
+			));
+		};
+
+		record(&first);
+		assert_eq!(RewardDistributions::<Runtime>::get(REMOTE_CHAIN, 7), None);
+		record(&second);
+		// the mock runtime has no handler, the distribution is recorded as failed
+		assert_eq!(RewardDistributions::<Runtime>::get(REMOTE_CHAIN, 7), Some(false));
+	});
+}
+
+#[test]
+fn reserves_are_recorded_once_the_threshold_is_reached() {
+	ExtBuilder::default().build().execute_with(|| {
+		let (first, second) = (relayer_key(1), relayer_key(2));
+		register_relayer(ALICE, &first);
+		register_relayer(BOB, &second);
+		set_finalizers(&[ALICE, BOB], 2);
+		let pool = CustodyPool { address: H160::repeat_byte(1), token: H160::zero() };
+		assert_ok!(QuantumPortal::set_custody_pool(RuntimeOrigin::root(), REMOTE_CHAIN, pool));
+		let report = |key: &ecdsa::Pair, balance: u128, block_number: u64| {
+			let payload = ReservesPayload { chain_id: REMOTE_CHAIN, balance, block_number };
+			let signature = key.sign_prehashed(&payload.signing_hash().0);
+			assert_ok!(QuantumPortal::report_reserves(RuntimeOrigin::none(), payload, signature));
+		};
+
+		report(&first, 100, 1);
+		assert_eq!(ObservedReserves::<Runtime>::get(REMOTE_CHAIN), None);
+		// a balance altered by the second relayer does not count towards the first one
+		report(&second, 1_000, 2);
+		assert_eq!(ObservedReserves::<Runtime>::get(REMOTE_CHAIN), None);
+		// nor does the same balance reported in another era
+		report(&second, 100, 10);
+		assert_eq!(ObservedReserves::<Runtime>::get(REMOTE_CHAIN), None);
+
+		report(&second, 100, 2);
+		assert_eq!(ObservedReserves::<Runtime>::get(REMOTE_CHAIN), Some((100, 2)));
+		System::assert_last_event(RuntimeEvent::QuantumPortal(crate::Event::ReservesReported {
+			chain_id: REMOTE_CHAIN,
+			balance: 100,
+		}));
+	});
+}
+
+fn message_states_call(key: &ecdsa::Pair, block_number: u64) -> crate::Call<Runtime> {
+	let payload = MessageStatePayload {
+		remote_chain: REMOTE_CHAIN,
+		updates: vec![(H256::repeat_byte(1), MessageState::Mined)].try_into().unwrap(),
+		block_number,
+		sequence: 0,
 	};
+	let signature = key.sign_prehashed(&payload.signing_hash().0);
+	crate::Call::update_message_states { payload, signature }
+}
+
+fn validate(call: &crate::Call<Runtime>) -> TransactionValidity {
+	QuantumPortal::validate_unsigned(TransactionSource::External, call)
+}
+
+#[test]
+fn quorum_signature_round_trip() {
+	ExtBuilder::default().build().execute_with(|| {
+		let payload = b"outbound transaction hash".to_vec();
+		assert_noop!(
+			QuantumPortal::request_quorum_signature(
+				RuntimeOrigin::signed(ALICE),
+				payload.clone().try_into().unwrap()
+			),
+			Error::<Runtime>::RelayerNotFound
+		);
+
+		register_relayer(ALICE, &relayer_key(1));
+		assert_ok!(QuantumPortal::request_quorum_signature(
+			RuntimeOrigin::signed(ALICE),
+			payload.clone().try_into().unwrap()
+		));
+		let payload_hash = H256(sp_io::hashing::keccak_256(&payload));
+		assert_eq!(SignatureRequests::get(), vec![payload]);
+		assert_eq!(QuorumSignatureRequests::<Runtime>::get(1), Some((ALICE, payload_hash)));
+
+		QuantumPortal::on_signature_ready(1, vec![7; 65]);
+		assert_eq!(QuantumPortal::quorum_signature(1), Some((payload_hash, vec![7; 65])));
+		assert_eq!(QuorumSignatureRequests::<Runtime>::get(1), None);
+		System::assert_last_event(RuntimeEvent::QuantumPortal(
+			crate::Event::QuorumSignatureReady { request_id: 1, payload_hash },
+		));
+
+		// signatures of requests that are not pending are ignored
+		QuantumPortal::on_signature_ready(1, vec![8; 65]);
+		QuantumPortal::on_signature_ready(2, vec![8; 65]);
+		assert_eq!(QuantumPortal::quorum_signature(1), Some((payload_hash, vec![7; 65])));
+		assert_eq!(QuantumPortal::quorum_signature(2), None);
+	});
+}
 
-	// let signature = price_payload.sign::<crypto::TestAuthId>().unwrap();
-	t.execute_with(|| {
-		// when
-		Example::fetch_price_and_send_unsigned_for_all_accounts(1).unwrap();
-		// then
-		let tx = pool_state.write().transactions.pop().unwrap();
-		let tx = Extrinsic::decode(&mut &*tx).unwrap();
-		assert_eq!(tx.signature, None);
-		if let Call::Example(crate::Call::submit_price_unsigned_with_signed_payload {
-								 price_payload: body,
-								 signature,
-							 }) = tx.call
-		{
-			assert_eq!(body, price_payload);
-
-			let signature_valid =
-				<PricePayload<
-					<Test as SigningTypes>::Public,
-					<Test as frame_system::Config>::BlockNumber,
-				> as SignedPayload<Test>>::verify::<crypto::TestAuthId>(&price_payload, signature);
-
-			assert!(signature_valid);
-		}
+#[test]
+fn validate_unsigned_accepts_a_payload_of_a_relayer() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		System::set_block_number(5);
+
+		let valid = validate(&message_states_call(&key, 3)).unwrap();
+		assert_eq!(valid.longevity, RELAYER_PAYLOAD_LONGEVITY);
+		assert_eq!(valid.priority, RELAYER_PAYLOAD_PRIORITY);
+		assert_eq!(valid.provides.len(), 1);
+		// one payload per relayer, chain, block and sequence
+		assert_eq!(validate(&message_states_call(&key, 3)).unwrap().provides, valid.provides);
+		assert_ne!(validate(&message_states_call(&key, 4)).unwrap().provides, valid.provides);
 	});
 }
 
 #[test]
-fn should_submit_raw_unsigned_transaction_on_chain() {
-	let (offchain, offchain_state) = testing::TestOffchainExt::new();
-	let (pool, pool_state) = testing::TestTransactionPoolExt::new();
-
-	let keystore = KeyStore::new();
-
-	let mut t = sp_io::TestExternalities::default();
-	t.register_extension(OffchainWorkerExt::new(offchain));
-	t.register_extension(TransactionPoolExt::new(pool));
-	t.register_extension(KeystoreExt(Arc::new(keystore)));
-
-	price_oracle_response(&mut offchain_state.write());
-
-	t.execute_with(|| {
-		// when
-		Example::fetch_price_and_send_raw_unsigned(1).unwrap();
-		// then
-		let tx = pool_state.write().transactions.pop().unwrap();
-		assert!(pool_state.read().transactions.is_empty());
-		let tx = Extrinsic::decode(&mut &*tx).unwrap();
-		assert_eq!(tx.signature, None);
+fn validate_unsigned_caps_the_payloads_of_a_block() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		let call = |message_id: u8, sequence: u8| {
+			let payload = MessageStatePayload {
+				remote_chain: REMOTE_CHAIN,
+				updates: vec![(H256::repeat_byte(message_id), MessageState::Mined)]
+					.try_into()
+					.unwrap(),
+				block_number: 1,
+				sequence,
+			};
+			let signature = key.sign_prehashed(&payload.signing_hash().0);
+			crate::Call::update_message_states { payload, signature }
+		};
+
+		let provides = validate(&call(1, 0)).unwrap().provides;
+		// other content under the same sequence takes the same slot
+		assert_eq!(validate(&call(2, 0)).unwrap().provides, provides);
+		assert_ne!(validate(&call(2, 1)).unwrap().provides, provides);
+		assert!(validate(&call(1, MAX_PAYLOADS_PER_BLOCK - 1)).is_ok());
 		assert_eq!(
-			tx.call,
-			Call::Example(crate::Call::submit_price_unsigned { block_number: 1, price: 15523 })
+			validate(&call(1, MAX_PAYLOADS_PER_BLOCK)),
+			InvalidTransaction::ExhaustsResources.into()
 		);
 	});
 }
 
-fn price_oracle_response(state: &mut testing::OffchainState) {
-	state.expect_request(testing::PendingRequest {
-		method: "GET".into(),
-		uri: "https://min-api.cryptocompare.com/data/price?fsym=BTC&tsyms=USD".into(),
-		response: Some(br#"{"USD": 155.23}"#.to_vec()),
-		sent: true,
-		..Default::default()
+#[test]
+fn validate_unsigned_rejects_unknown_signers() {
+	ExtBuilder::default().build().execute_with(|| {
+		register_relayer(ALICE, &relayer_key(1));
+
+		assert_eq!(
+			validate(&message_states_call(&relayer_key(2), 1)),
+			InvalidTransaction::BadSigner.into()
+		);
+
+		let crate::Call::update_message_states { payload, .. } =
+			message_states_call(&relayer_key(1), 1)
+		else {
+			unreachable!()
+		};
+		let signature = ecdsa::Signature::from_raw([0; 65]);
+		assert_eq!(
+			validate(&crate::Call::update_message_states { payload, signature }),
+			InvalidTransaction::BadProof.into()
+		);
 	});
 }
 
 #[test]
-fn parse_price_works() {
-	let test_data = vec![
-		("{\"USD\":6536.92}", Some(653692)),
-		("{\"USD\":65.92}", Some(6592)),
-		("{\"USD\":6536.924565}", Some(653692)),
-		("{\"USD\":6536}", Some(653600)),
-		("{\"USD2\":6536}", None),
-		("{\"USD\":\"6432\"}", None),
-	];
-
-	for (json, expected) in test_data {
-		assert_eq!(expected, Example::parse_price(json));
-	}
+fn validate_unsigned_rejects_future_and_stale_payloads() {
+	ExtBuilder::default().build().execute_with(|| {
+		let key = relayer_key(1);
+		register_relayer(ALICE, &key);
+		System::set_block_number(20);
+
+		assert_eq!(validate(&message_states_call(&key, 21)), InvalidTransaction::Future.into());
+		assert_eq!(
+			validate(&message_states_call(&key, 20 - RELAYER_PAYLOAD_LONGEVITY - 1)),
+			InvalidTransaction::Stale.into()
+		);
+		assert!(validate(&message_states_call(&key, 20 - RELAYER_PAYLOAD_LONGEVITY)).is_ok());
+	});
+}
+
+#[test]
+fn validate_unsigned_rejects_other_calls() {
+	ExtBuilder::default().build().execute_with(|| {
+		assert_eq!(validate(&crate::Call::unregister_relayer {}), InvalidTransaction::Call.into());
+	});
 }
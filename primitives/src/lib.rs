@@ -35,8 +35,9 @@ use sp_core::{ecdsa, offchain::KeyTypeId, H160, H256};
 use sp_runtime::{
 	generic,
 	traits::{BlakeTwo256, IdentifyAccount, Verify},
-	Perbill,
+	DispatchError, Perbill,
 };
+use sp_std::vec::Vec;
 
 pub mod account_id20;
 pub mod xcm_primitives;
@@ -151,3 +152,86 @@ impl WeightToFeePolynomial for WeightToFee {
 		}]
 	}
 }
+
+/// Queues payloads to be signed by the validator quorum.
+pub trait SignatureRequester {
+	/// Queue `payload` for signing, returns the id the signature is reported back with.
+	fn request_signature(payload: Vec<u8>) -> Result<u64, DispatchError>;
+}
+
+impl SignatureRequester for () {
+	fn request_signature(_payload: Vec<u8>) -> Result<u64, DispatchError> {
+		Err(DispatchError::Other("No signature requester configured"))
+	}
+}
+
+/// Receives the signatures requested through a `SignatureRequester`.
+pub trait SignatureReceiver {
+	/// Called once the threshold signature of `request_id` is ready.
+	fn on_signature_ready(request_id: u64, signature: Vec<u8>);
+}
+
+impl SignatureReceiver for () {
+	fn on_signature_ready(_request_id: u64, _signature: Vec<u8>) {}
+}
+
+/// Pays out the funds of withdrawals processed on a remote chain.
+pub trait WithdrawalReleaser<AccountId> {
+	/// Release `amount` to `beneficiary`, an error leaves the withdrawal to be claimed later.
+	fn release(beneficiary: &AccountId, amount: u128) -> Result<(), DispatchError>;
+}
+
+impl<AccountId> WithdrawalReleaser<AccountId> for () {
+	fn release(_beneficiary: &AccountId, _amount: u128) -> Result<(), DispatchError> {
+		Err(DispatchError::Other("No withdrawal releaser configured"))
+	}
+}
+
+/// A cross-chain call registered on a remote chain for this chain.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, Debug, TypeInfo)]
+pub struct InboundMessage {
+	pub remote_chain: u64,
+	/// Id of the message, the hash of the remote transaction
+	pub message_id: H256,
+	pub remote_contract: H160,
+	pub source_msg_sender: H160,
+	pub source_beneficiary: H160,
+	pub token: H160,
+	pub amount: u128,
+	pub method: Vec<u8>,
+}
+
+/// Reacts to the cross-chain calls targeting this chain.
+pub trait InboundMessageHandler {
+	/// Handle `message`, an error rolls back its effects and records it as failed.
+	fn on_message(message: &InboundMessage) -> Result<(), DispatchError>;
+}
+
+impl InboundMessageHandler for () {
+	fn on_message(_message: &InboundMessage) -> Result<(), DispatchError> {
+		Err(DispatchError::Other("No inbound message handler configured"))
+	}
+}
+
+/// Rewards distributed to its stakers by the master staking contract of a remote chain.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, Debug, TypeInfo)]
+pub struct RewardDistribution {
+	pub remote_chain: u64,
+	/// Id of the distribution on the master staking contract
+	pub distribution_id: u64,
+	/// Amount rewarded to each staker
+	pub rewards: Vec<(H160, u128)>,
+}
+
+/// Pays out locally the rewards distributed on the remote chains.
+pub trait RewardsHandler {
+	/// Pay out the rewards of `distribution`, an error rolls back its effects and records it as
+	/// failed.
+	fn on_rewards(distribution: &RewardDistribution) -> Result<(), DispatchError>;
+}
+
+impl RewardsHandler for () {
+	fn on_rewards(_distribution: &RewardDistribution) -> Result<(), DispatchError> {
+		Err(DispatchError::Other("No rewards handler configured"))
+	}
+}
@@ -107,8 +107,17 @@ pub type Executive = frame_executive::Executive<
 	frame_system::ChainContext<Runtime>,
 	Runtime,
 	AllPalletsWithSystem,
+	Migrations,
 >;
 
+/// Migrations to run on the next runtime upgrade
+pub type Migrations = (
+	pallet_quantum_portal::migrations::v1::MigrateToV1<Runtime>,
+	pallet_quantum_portal::migrations::v2::MigrateToV2<Runtime>,
+	pallet_quantum_portal::migrations::v3::MigrateToV3<Runtime>,
+	pallet_quantum_portal::migrations::v4::MigrateToV4<Runtime>,
+);
+
 impl fp_self_contained::SelfContainedCall for RuntimeCall {
 	type SignedInfo = H160;
 
@@ -615,10 +624,34 @@ impl pallet_base_fee::Config for Runtime {
 	type DefaultElasticity = DefaultElasticity;
 }
 
+parameter_types! {
+	pub const QpRelayerStake: Balance = 1_000 * UNIT;
+	pub const QpMaxFinalizers: u32 = 32;
+	pub const QpUnbondingPeriod: BlockNumber = 7 * DAYS;
+	pub const QpAccountingPeriod: BlockNumber = 24 * HOURS;
+	pub const QpAttestationPeriod: BlockNumber = HOURS;
+}
+
 impl pallet_quantum_portal::Config for Runtime {
 	type RuntimeCall = RuntimeCall;
 	type RuntimeEvent = RuntimeEvent;
 	type Timestamp = Timestamp;
+	type UpdateOrigin = EnsureRoot<AccountId>;
+	type Currency = Balances;
+	type RelayerStake = QpRelayerStake;
+	type UnbondingPeriod = QpUnbondingPeriod;
+	type MaxFinalizers = QpMaxFinalizers;
+	type Slash = ();
+	// no signing queue in the runtime yet, the quorum signature requests are rejected until one
+	// reports back through `<QuantumPortal as SignatureReceiver>::on_signature_ready`
+	type SignatureRequester = ();
+	type WithdrawalReleaser = ();
+	type LocalChainId = ChainId;
+	type InboundMessageHandler = ();
+	type AccountingPeriod = QpAccountingPeriod;
+	type RewardsHandler = ();
+	type AttestationPeriod = QpAttestationPeriod;
+	type WeightInfo = pallet_quantum_portal::weights::SubstrateWeight<Runtime>;
 }
 
 impl<LocalCall> frame_system::offchain::CreateSignedTransaction<LocalCall> for Runtime
@@ -713,7 +746,7 @@ construct_runtime!(
 		EVM: pallet_evm::{Pallet, Config<T>, Call, Storage, Event<T>}= 41,
 		DynamicFee: pallet_dynamic_fee::{Pallet, Call, Storage, Config<T>, Inherent}= 42,
 		BaseFee: pallet_base_fee::{Pallet, Call, Storage, Config<T>, Event}= 43,
-		QuantumPortal: pallet_quantum_portal::{Pallet, Call, Storage, Event<T>/*, ValidateUnsigned*/}= 44,
+		QuantumPortal: pallet_quantum_portal::{Pallet, Call, Storage, Event<T>, ValidateUnsigned}= 44,
 		TransactionPauser: pallet_transaction_pauser::{Pallet, Call, Storage, Event<T>}= 46,
 		MessageQueue: pallet_message_queue::{Pallet, Call, Storage, Event<T>} = 47,
 	}